@@ -0,0 +1,38 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+fn main() {
+    // GIT_HASH can be set by builds that happen outside of a git checkout
+    let git_hash = env::var("GIT_HASH")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+
+    // Diesel records a migration by its directory name up to the first '_', without the dashes
+    let mut migrations = fs::read_dir("migrations")
+        .expect("migrations directory")
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.split('_').next().map(|version| version.replace('-', ""))
+        })
+        .collect::<Vec<_>>();
+    migrations.sort();
+    println!("cargo:rustc-env=MIGRATIONS={}", migrations.join(","));
+
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+}
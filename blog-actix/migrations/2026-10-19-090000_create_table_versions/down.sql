@@ -0,0 +1,10 @@
+DROP TRIGGER users_insert_version;
+DROP TRIGGER users_update_version;
+DROP TRIGGER users_delete_version;
+DROP TRIGGER posts_insert_version;
+DROP TRIGGER posts_update_version;
+DROP TRIGGER posts_delete_version;
+DROP TRIGGER comments_insert_version;
+DROP TRIGGER comments_update_version;
+DROP TRIGGER comments_delete_version;
+DROP TABLE table_versions;
@@ -0,0 +1,86 @@
+-- Bumped by the triggers below on every write, the validators of the cached responses are
+-- derived from the versions of the tables they were read from
+CREATE TABLE table_versions
+(
+    name       TEXT PRIMARY KEY NOT NULL,
+    version    BIGINT           NOT NULL DEFAULT 0,
+    -- milliseconds since the Unix epoch
+    updated_at BIGINT           NOT NULL
+);
+
+INSERT INTO table_versions (name, updated_at)
+VALUES ('users', CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)),
+       ('posts', CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)),
+       ('comments', CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER));
+
+CREATE TRIGGER users_insert_version AFTER INSERT ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_update_version AFTER UPDATE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_delete_version AFTER DELETE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER posts_insert_version AFTER INSERT ON posts
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'posts';
+END;
+
+CREATE TRIGGER posts_update_version AFTER UPDATE ON posts
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'posts';
+END;
+
+CREATE TRIGGER posts_delete_version AFTER DELETE ON posts
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'posts';
+END;
+
+CREATE TRIGGER comments_insert_version AFTER INSERT ON comments
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'comments';
+END;
+
+CREATE TRIGGER comments_update_version AFTER UPDATE ON comments
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'comments';
+END;
+
+CREATE TRIGGER comments_delete_version AFTER DELETE ON comments
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'comments';
+END;
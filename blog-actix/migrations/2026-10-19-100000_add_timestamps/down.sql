@@ -0,0 +1,131 @@
+-- SQLite only drops columns from 3.35 on, the tables are rebuilt without them. Dropping a table
+-- drops its index and triggers too, they are created again. Foreign keys are off around the
+-- rebuild as in the down.sql of add_profiles, posts and users being referenced.
+COMMIT;
+PRAGMA foreign_keys = OFF;
+BEGIN;
+
+CREATE TABLE comments_without_timestamps
+(
+    id      INTEGER PRIMARY KEY NOT NULL,
+    user_id INTEGER             NOT NULL REFERENCES users (id),
+    post_id INTEGER             NOT NULL REFERENCES posts (id),
+    body    TEXT                NOT NULL
+);
+INSERT INTO comments_without_timestamps (id, user_id, post_id, body)
+SELECT id, user_id, post_id, body
+FROM comments;
+DROP TABLE comments;
+ALTER TABLE comments_without_timestamps RENAME TO comments;
+
+CREATE TRIGGER comments_insert_version AFTER INSERT ON comments
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'comments';
+END;
+
+CREATE TRIGGER comments_update_version AFTER UPDATE ON comments
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'comments';
+END;
+
+CREATE TRIGGER comments_delete_version AFTER DELETE ON comments
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'comments';
+END;
+
+CREATE TABLE posts_without_timestamps
+(
+    id        INTEGER PRIMARY KEY NOT NULL,
+    user_id   INTEGER             NOT NULL REFERENCES users (id),
+    title     VARCHAR             NOT NULL,
+    body      TEXT                NOT NULL,
+    published BOOLEAN             NOT NULL DEFAULT 0
+);
+INSERT INTO posts_without_timestamps (id, user_id, title, body, published)
+SELECT id, user_id, title, body, published
+FROM posts;
+DROP TABLE posts;
+ALTER TABLE posts_without_timestamps RENAME TO posts;
+
+CREATE TRIGGER posts_insert_version AFTER INSERT ON posts
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'posts';
+END;
+
+CREATE TRIGGER posts_update_version AFTER UPDATE ON posts
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'posts';
+END;
+
+CREATE TRIGGER posts_delete_version AFTER DELETE ON posts
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'posts';
+END;
+
+CREATE TABLE users_without_timestamps
+(
+    id       INTEGER PRIMARY KEY NOT NULL,
+    username VARCHAR             NOT NULL
+);
+INSERT INTO users_without_timestamps (id, username)
+SELECT id, username
+FROM users;
+DROP TABLE users;
+ALTER TABLE users_without_timestamps RENAME TO users;
+
+CREATE UNIQUE INDEX username_unique_idx ON users (username);
+
+CREATE TRIGGER users_insert_version AFTER INSERT ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_update_version AFTER UPDATE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_delete_version AFTER DELETE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TEMPORARY TABLE foreign_key_violations
+(
+    count INTEGER NOT NULL CHECK (count = 0)
+);
+INSERT INTO foreign_key_violations
+SELECT COUNT(*)
+FROM pragma_foreign_key_check;
+DROP TABLE foreign_key_violations;
+
+COMMIT;
+PRAGMA foreign_keys = ON;
+BEGIN;
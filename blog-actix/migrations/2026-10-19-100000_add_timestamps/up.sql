@@ -0,0 +1,25 @@
+-- SQLite can't add a column defaulting to CURRENT_TIMESTAMP, models sets the timestamps on every
+-- insert and update instead. All of them are UTC.
+ALTER TABLE users ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+
+ALTER TABLE posts ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE posts ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE posts ADD COLUMN published_at TIMESTAMP;
+
+ALTER TABLE comments ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE comments ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+
+-- rows written before the timestamps existed are dated to the migration
+UPDATE users
+SET created_at = CURRENT_TIMESTAMP,
+    updated_at = CURRENT_TIMESTAMP;
+
+UPDATE posts
+SET created_at   = CURRENT_TIMESTAMP,
+    updated_at   = CURRENT_TIMESTAMP,
+    published_at = CASE WHEN published THEN CURRENT_TIMESTAMP END;
+
+UPDATE comments
+SET created_at = CURRENT_TIMESTAMP,
+    updated_at = CURRENT_TIMESTAMP;
@@ -0,0 +1,28 @@
+-- Log of the writes to users, posts and comments, read by the clients syncing incrementally.
+-- AUTOINCREMENT keeps ids from being reused, clients resume from the last id they saw.
+CREATE TABLE changes
+(
+    id         INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- user, post or comment
+    entity     TEXT      NOT NULL,
+    entity_id  INTEGER   NOT NULL,
+    -- created, updated or deleted
+    operation  TEXT      NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+-- rows written before the log existed are reported as created
+INSERT INTO changes (entity, entity_id, operation, created_at)
+SELECT 'user', id, 'created', created_at
+FROM users
+ORDER BY id;
+
+INSERT INTO changes (entity, entity_id, operation, created_at)
+SELECT 'post', id, 'created', created_at
+FROM posts
+ORDER BY id;
+
+INSERT INTO changes (entity, entity_id, operation, created_at)
+SELECT 'comment', id, 'created', created_at
+FROM comments
+ORDER BY id;
@@ -0,0 +1,28 @@
+-- Endpoints notified of the blog events, `events` being a comma separated list of event types
+CREATE TABLE webhooks
+(
+    id         INTEGER PRIMARY KEY NOT NULL,
+    url        TEXT                NOT NULL,
+    secret     TEXT                NOT NULL,
+    events     TEXT                NOT NULL,
+    created_at TIMESTAMP           NOT NULL
+);
+
+-- Queue of the notifications, written in the transaction of the event. A delivery is done once
+-- delivered_at or failed_at is set.
+CREATE TABLE webhook_deliveries
+(
+    id              INTEGER PRIMARY KEY NOT NULL,
+    webhook_id      INTEGER             NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event           TEXT                NOT NULL,
+    payload         TEXT                NOT NULL,
+    attempts        INTEGER             NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP           NOT NULL,
+    last_error      TEXT,
+    delivered_at    TIMESTAMP,
+    failed_at       TIMESTAMP,
+    created_at      TIMESTAMP           NOT NULL
+);
+
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
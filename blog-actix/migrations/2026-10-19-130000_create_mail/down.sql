@@ -0,0 +1,3 @@
+DROP TABLE outbox;
+DROP TABLE subscribers;
+DROP TABLE user_emails;
@@ -0,0 +1,39 @@
+-- Where a user is notified of comments on their posts, kept out of the users table so it's never
+-- part of the public user records
+CREATE TABLE user_emails
+(
+    user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email   TEXT                NOT NULL
+);
+
+-- Addresses told about every newly published post, `token` identifying them when unsubscribing
+CREATE TABLE subscribers
+(
+    id         INTEGER PRIMARY KEY NOT NULL,
+    email      TEXT                NOT NULL,
+    token      TEXT                NOT NULL,
+    created_at TIMESTAMP           NOT NULL
+);
+
+CREATE UNIQUE INDEX subscribers_email_idx ON subscribers (email);
+CREATE UNIQUE INDEX subscribers_token_idx ON subscribers (token);
+
+-- Mail written in the transaction of the event and sent by a background worker, `template`
+-- naming the message rendered from the JSON `data`. A message is done once sent_at or failed_at
+-- is set.
+CREATE TABLE outbox
+(
+    id              INTEGER PRIMARY KEY NOT NULL,
+    recipient       TEXT                NOT NULL,
+    template        TEXT                NOT NULL,
+    data            TEXT                NOT NULL,
+    attempts        INTEGER             NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP           NOT NULL,
+    last_error      TEXT,
+    sent_at         TIMESTAMP,
+    failed_at       TIMESTAMP,
+    created_at      TIMESTAMP           NOT NULL
+);
+
+CREATE INDEX outbox_pending_idx ON outbox (next_attempt_at)
+    WHERE sent_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,11 @@
+-- Authors whose published posts make up a user's feed
+CREATE TABLE follows
+(
+    follower_id INTEGER   NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    followee_id INTEGER   NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at  TIMESTAMP NOT NULL,
+    PRIMARY KEY (follower_id, followee_id),
+    CHECK (follower_id <> followee_id)
+);
+
+CREATE INDEX follows_followee_idx ON follows (followee_id);
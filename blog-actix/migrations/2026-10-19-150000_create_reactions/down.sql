@@ -0,0 +1,5 @@
+DROP TRIGGER reactions_insert_version;
+DROP TRIGGER reactions_update_version;
+DROP TRIGGER reactions_delete_version;
+DELETE FROM table_versions WHERE name = 'reactions';
+DROP TABLE reactions;
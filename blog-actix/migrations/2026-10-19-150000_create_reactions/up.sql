@@ -0,0 +1,40 @@
+-- A user reacts to a post or a comment at most once with each kind of reaction
+CREATE TABLE reactions
+(
+    target     TEXT      NOT NULL CHECK (target IN ('post', 'comment')),
+    target_id  INTEGER   NOT NULL,
+    kind       TEXT      NOT NULL,
+    user_id    INTEGER   NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (target, target_id, kind, user_id)
+);
+
+CREATE INDEX reactions_user_idx ON reactions (user_id);
+
+-- The counts are part of the post listings
+INSERT INTO table_versions (name, updated_at)
+VALUES ('reactions', CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER));
+
+CREATE TRIGGER reactions_insert_version AFTER INSERT ON reactions
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'reactions';
+END;
+
+CREATE TRIGGER reactions_update_version AFTER UPDATE ON reactions
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'reactions';
+END;
+
+CREATE TRIGGER reactions_delete_version AFTER DELETE ON reactions
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'reactions';
+END;
@@ -0,0 +1,62 @@
+-- SQLite only drops columns from 3.35 on, the table is rebuilt without them. Dropping users while
+-- foreign keys are enforced would fail on the rows referencing it, or delete them with ON DELETE
+-- CASCADE, so they are turned off around the rebuild and checked before committing it. The pragma
+-- is ignored within a transaction, and diesel runs the migration in one: it is committed first,
+-- and the transaction diesel commits after the migration started again at the end. Dropping a
+-- table drops its index and triggers too, they are created again.
+COMMIT;
+PRAGMA foreign_keys = OFF;
+BEGIN;
+
+CREATE TABLE users_without_profiles
+(
+    id         INTEGER PRIMARY KEY NOT NULL,
+    username   VARCHAR             NOT NULL,
+    created_at TIMESTAMP           NOT NULL DEFAULT '1970-01-01 00:00:00',
+    updated_at TIMESTAMP           NOT NULL DEFAULT '1970-01-01 00:00:00'
+);
+INSERT INTO users_without_profiles (id, username, created_at, updated_at)
+SELECT id, username, created_at, updated_at
+FROM users;
+DROP TABLE users;
+ALTER TABLE users_without_profiles RENAME TO users;
+
+CREATE UNIQUE INDEX username_unique_idx ON users (username);
+
+CREATE TRIGGER users_insert_version AFTER INSERT ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_update_version AFTER UPDATE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_delete_version AFTER DELETE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+-- PRAGMA foreign_key_check only lists the violations, the CHECK fails the migration on them
+CREATE TEMPORARY TABLE foreign_key_violations
+(
+    count INTEGER NOT NULL CHECK (count = 0)
+);
+INSERT INTO foreign_key_violations
+SELECT COUNT(*)
+FROM pragma_foreign_key_check;
+DROP TABLE foreign_key_violations;
+
+COMMIT;
+PRAGMA foreign_keys = ON;
+BEGIN;
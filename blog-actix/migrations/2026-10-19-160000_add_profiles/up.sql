@@ -0,0 +1,8 @@
+-- Shown with GET /users/{id}, the listings only embed the id and username of the users
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN bio TEXT;
+ALTER TABLE users ADD COLUMN website TEXT;
+ALTER TABLE users ADD COLUMN avatar_url TEXT;
+-- SHA-256 of the token a user edits their profile with, handed out once when the user is created.
+-- Users created before have none.
+ALTER TABLE users ADD COLUMN token_hash TEXT;
@@ -0,0 +1,65 @@
+-- The users renamed because of a clash keep their new name
+DROP TABLE username_history;
+
+-- Rebuilt without username_key, foreign keys off around it as in the down.sql of add_profiles
+COMMIT;
+PRAGMA foreign_keys = OFF;
+BEGIN;
+
+CREATE TABLE users_without_keys
+(
+    id           INTEGER PRIMARY KEY NOT NULL,
+    username     VARCHAR             NOT NULL,
+    created_at   TIMESTAMP           NOT NULL DEFAULT '1970-01-01 00:00:00',
+    updated_at   TIMESTAMP           NOT NULL DEFAULT '1970-01-01 00:00:00',
+    display_name TEXT,
+    bio          TEXT,
+    website      TEXT,
+    avatar_url   TEXT,
+    token_hash   TEXT
+);
+INSERT INTO users_without_keys (id, username, created_at, updated_at, display_name, bio, website,
+                                avatar_url, token_hash)
+SELECT id, username, created_at, updated_at, display_name, bio, website, avatar_url, token_hash
+FROM users;
+DROP TABLE users;
+ALTER TABLE users_without_keys RENAME TO users;
+
+CREATE UNIQUE INDEX username_unique_idx ON users (username);
+
+CREATE TRIGGER users_insert_version AFTER INSERT ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_update_version AFTER UPDATE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TRIGGER users_delete_version AFTER DELETE ON users
+BEGIN
+    UPDATE table_versions
+    SET version    = version + 1,
+        updated_at = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+    WHERE name = 'users';
+END;
+
+CREATE TEMPORARY TABLE foreign_key_violations
+(
+    count INTEGER NOT NULL CHECK (count = 0)
+);
+INSERT INTO foreign_key_violations
+SELECT COUNT(*)
+FROM pragma_foreign_key_check;
+DROP TABLE foreign_key_violations;
+
+COMMIT;
+PRAGMA foreign_keys = ON;
+BEGIN;
@@ -0,0 +1,20 @@
+-- Usernames are unique regardless of case through username_key, the username lowercased by Rust.
+-- SQLite's lower() only folds ASCII letters, "Émile" and "émile" would both get in. The keys of
+-- the users already there are filled in by the server when it starts, renaming the users whose
+-- name clashes with an older one's.
+ALTER TABLE users ADD COLUMN username_key TEXT;
+CREATE UNIQUE INDEX username_key_unique_idx ON users (username_key);
+
+-- Names users were renamed from. GET /users/find/{name} still finds them for a while, during which
+-- nobody else can take them.
+CREATE TABLE username_history
+(
+    id           INTEGER PRIMARY KEY NOT NULL,
+    user_id      INTEGER             NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    username     TEXT                NOT NULL,
+    username_key TEXT                NOT NULL,
+    renamed_at   TIMESTAMP           NOT NULL
+);
+
+CREATE INDEX username_history_key_idx ON username_history (username_key);
+CREATE INDEX username_history_user_idx ON username_history (user_id);
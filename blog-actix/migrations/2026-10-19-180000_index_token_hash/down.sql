@@ -0,0 +1 @@
+DROP INDEX users_token_hash_idx;
@@ -0,0 +1,2 @@
+-- The rate limiter looks users up by the token their requests carry
+CREATE INDEX users_token_hash_idx ON users (token_hash);
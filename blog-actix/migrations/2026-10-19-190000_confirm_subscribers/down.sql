@@ -0,0 +1,20 @@
+-- SQLite only drops columns from 3.35 on, the table is rebuilt without it. Dropping a table
+-- drops its indexes too, they are created again. The unconfirmed subscribers are dropped, they
+-- never asked for the posts.
+
+CREATE TABLE subscribers_without_confirmation
+(
+    id         INTEGER PRIMARY KEY NOT NULL,
+    email      TEXT                NOT NULL,
+    token      TEXT                NOT NULL,
+    created_at TIMESTAMP           NOT NULL
+);
+INSERT INTO subscribers_without_confirmation (id, email, token, created_at)
+SELECT id, email, token, created_at
+FROM subscribers
+WHERE confirmed_at IS NOT NULL;
+DROP TABLE subscribers;
+ALTER TABLE subscribers_without_confirmation RENAME TO subscribers;
+
+CREATE UNIQUE INDEX subscribers_email_idx ON subscribers (email);
+CREATE UNIQUE INDEX subscribers_token_idx ON subscribers (token);
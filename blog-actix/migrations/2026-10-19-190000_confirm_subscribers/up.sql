@@ -0,0 +1,4 @@
+-- Subscribers are only sent the new posts once they followed the link of the confirmation
+-- message. The ones from before are taken as confirmed.
+ALTER TABLE subscribers ADD COLUMN confirmed_at TIMESTAMP;
+UPDATE subscribers SET confirmed_at = created_at;
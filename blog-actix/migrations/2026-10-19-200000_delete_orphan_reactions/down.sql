@@ -0,0 +1,2 @@
+DROP TRIGGER comments_delete_reactions;
+DROP TRIGGER posts_delete_reactions;
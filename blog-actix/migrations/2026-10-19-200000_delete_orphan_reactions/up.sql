@@ -0,0 +1,15 @@
+-- A reaction's target is a post or a comment, which no foreign key can point to, so the reactions
+-- are deleted along with their post or comment here
+CREATE TRIGGER posts_delete_reactions AFTER DELETE ON posts
+BEGIN
+    DELETE FROM reactions WHERE target = 'post' AND target_id = OLD.id;
+END;
+
+CREATE TRIGGER comments_delete_reactions AFTER DELETE ON comments
+BEGIN
+    DELETE FROM reactions WHERE target = 'comment' AND target_id = OLD.id;
+END;
+
+-- and the ones left by the deletes before
+DELETE FROM reactions WHERE target = 'post' AND target_id NOT IN (SELECT id FROM posts);
+DELETE FROM reactions WHERE target = 'comment' AND target_id NOT IN (SELECT id FROM comments);
@@ -0,0 +1,130 @@
+use crate::errors::AppError;
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags};
+use schemars::JsonSchema;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use server_common::shutdown::Periodic;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type Result<T> = std::result::Result<T, AppError>;
+
+// Copying a handful of pages per step and sleeping in between lets writers on the pool
+// keep going while a snapshot is in progress.
+const PAGES_PER_STEP: i32 = 64;
+const PAUSE_BETWEEN_STEPS: Duration = Duration::from_millis(10);
+
+const SNAPSHOT_PREFIX: &str = "blog-";
+const SNAPSHOT_EXTENSION: &str = "sqlite3";
+
+// Tells apart the snapshots taken within the same millisecond
+static SNAPSHOT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    // None disables scheduled snapshots, the admin endpoint still works
+    pub interval: Option<Duration>,
+    // number of snapshots kept in dir, older ones are deleted. The new one is always kept.
+    pub retain: usize,
+    // bearer token required by the admin endpoint
+    pub admin_token: String,
+}
+
+#[derive(Clone)]
+pub struct Backups {
+    database_url: String,
+    config: BackupConfig,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Snapshot {
+    pub file: String,
+    pub size: u64,
+}
+
+impl Backups {
+    pub fn new(database_url: String, config: BackupConfig) -> Self {
+        Backups { database_url, config }
+    }
+
+    pub fn admin_token(&self) -> &str {
+        self.config.admin_token.as_str()
+    }
+
+    // Takes a snapshot of the live database and removes the ones outside the retention window.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        fs::create_dir_all(&self.config.dir).map_err(backup_failed)?;
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let sequence = SNAPSHOT_COUNTER.fetch_add(1, Ordering::SeqCst) % 1_000_000;
+        let name = format!("{}{}-{:06}.{}", SNAPSHOT_PREFIX, millis, sequence, SNAPSHOT_EXTENSION);
+        let path = self.config.dir.join(&name);
+        let partial = path.with_extension("partial");
+
+        // a failed copy isn't a snapshot, prune would never remove it
+        let copied = copy_database(&self.database_url, &partial)
+            .and_then(|_| fs::rename(&partial, &path).map_err(backup_failed));
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        let size = fs::metadata(&path).map_err(backup_failed)?.len();
+        self.prune()?;
+
+        Ok(Snapshot { file: name, size })
+    }
+
+    fn prune(&self) -> Result<()> {
+        let mut snapshots = fs::read_dir(&self.config.dir)
+            .map_err(backup_failed)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| is_snapshot(path))
+            .collect::<Vec<_>>();
+
+        // names start with a millisecond timestamp, so the newest snapshots sort last
+        snapshots.sort();
+        let expired = snapshots.len().saturating_sub(self.config.retain.max(1));
+        for path in snapshots.into_iter().take(expired) {
+            fs::remove_file(path).map_err(backup_failed)?;
+        }
+        Ok(())
+    }
+
+    pub fn schedule(&self) -> Option<Periodic> {
+        let interval = self.config.interval?;
+
+        let backups = self.clone();
+        Some(Periodic::spawn("Scheduled snapshots", interval, move || {
+            match backups.snapshot() {
+                Ok(snapshot) => log::info!("Created database snapshot {}", snapshot.file),
+                Err(e) => log::error!("Scheduled database snapshot failed: {}", e),
+            }
+        }))
+    }
+}
+
+fn copy_database(database_url: &str, target: &Path) -> Result<()> {
+    let src = Connection::open_with_flags(database_url, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut dst = Connection::open(target)?;
+
+    let backup = Backup::new(&src, &mut dst)?;
+    backup
+        .run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)
+        .map_err(Into::into)
+}
+
+fn backup_failed(e: io::Error) -> AppError {
+    AppError::BackupFailed(e.to_string())
+}
+
+fn is_snapshot(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    name.starts_with(SNAPSHOT_PREFIX) && extension == SNAPSHOT_EXTENSION
+}
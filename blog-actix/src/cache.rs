@@ -0,0 +1,259 @@
+use crate::conditional::Validators;
+use crate::errors::AppError;
+use crate::models::{Post, PostEntry, UserPostEntry};
+use lazy_static::lazy_static;
+use server_common::prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref CACHE_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "cache_requests_total",
+        "Lookups in the response caches, by cache and result",
+        &["cache", "result"]
+    )
+    .unwrap();
+    static ref CACHE_ENTRIES: IntGaugeVec = register_int_gauge_vec!(
+        "cache_entries",
+        "Responses held by the response caches",
+        &["cache"]
+    )
+    .unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    // responses kept per cache, the least recently used one is dropped to make room. 0 disables
+    // the caches
+    pub capacity: usize,
+    // how long a response is served from the cache, a safety net for writes that don't go
+    // through the handlers invalidating it
+    pub ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity: 1000,
+            ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+struct Entry<V> {
+    validators: Validators,
+    value: Arc<V>,
+    inserted: Instant,
+    used: Instant,
+}
+
+struct State<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    // bumped by every invalidation, so a load that started before one isn't stored after it
+    generation: u64,
+    // a read replica may not have the write behind the last invalidation yet
+    invalidated: Option<Instant>,
+}
+
+// Loaded values with the validators of the response they are sent in
+struct Cache<K, V> {
+    name: &'static str,
+    config: CacheConfig,
+    state: Mutex<State<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> {
+    fn new(name: &'static str, config: CacheConfig) -> Self {
+        Cache {
+            name,
+            config,
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                generation: 0,
+                invalidated: None,
+            }),
+        }
+    }
+
+    // `load` is told to read from the primary database while the replica may still lag behind the
+    // last invalidation, taken to be for a TTL, or it would store what the write replaced
+    fn get_or_load<F>(&self, key: K, load: F) -> Result<(Validators, Arc<V>), AppError>
+        where
+            F: FnOnce(bool) -> Result<(Validators, V), AppError>,
+    {
+        if self.config.capacity == 0 {
+            return load(false).map(|(validators, value)| (validators, Arc::new(value)));
+        }
+
+        let now = Instant::now();
+        let (generation, primary) = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(entry) = state.entries.get_mut(&key) {
+                if now.duration_since(entry.inserted) < self.config.ttl {
+                    entry.used = now;
+                    CACHE_REQUESTS.with_label_values(&[self.name, "hit"]).inc();
+                    return Ok((entry.validators.clone(), entry.value.clone()));
+                }
+            }
+            let primary = state
+                .invalidated
+                .is_some_and(|invalidated| now.duration_since(invalidated) < self.config.ttl);
+            (state.generation, primary)
+        };
+        CACHE_REQUESTS.with_label_values(&[self.name, "miss"]).inc();
+
+        // the lock isn't held while loading, other keys stay available in the meantime
+        let (validators, value) = load(primary)?;
+        let value = Arc::new(value);
+
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation {
+            if !state.entries.contains_key(&key) && state.entries.len() >= self.config.capacity {
+                self.evict(&mut state, now);
+            }
+            state.entries.insert(key, Entry {
+                validators: validators.clone(),
+                value: value.clone(),
+                inserted: now,
+                used: now,
+            });
+            CACHE_ENTRIES
+                .with_label_values(&[self.name])
+                .set(state.entries.len() as i64);
+        }
+        Ok((validators, value))
+    }
+
+    // Drops the expired entries, or the least recently used one when none are
+    fn evict(&self, state: &mut State<K, V>, now: Instant) {
+        let ttl = self.config.ttl;
+        state.entries.retain(|_, entry| now.duration_since(entry.inserted) < ttl);
+        if state.entries.len() < self.config.capacity {
+            return;
+        }
+        let oldest = state
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            state.entries.remove(&key);
+        }
+    }
+
+    fn invalidate(&self, key: &K) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.invalidated = Some(Instant::now());
+        state.entries.remove(key);
+        CACHE_ENTRIES
+            .with_label_values(&[self.name])
+            .set(state.entries.len() as i64);
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.invalidated = Some(Instant::now());
+        state.entries.clear();
+        CACHE_ENTRIES.with_label_values(&[self.name]).set(0);
+    }
+}
+
+// Caches of the post listings, shared by the workers. The handlers writing posts and comments
+// invalidate the listings they change.
+#[derive(Clone)]
+pub struct Listings {
+    all_posts: Arc<Cache<(), Vec<PostEntry>>>,
+    users_posts: Arc<Cache<i32, Vec<UserPostEntry>>>,
+}
+
+impl Listings {
+    pub fn new(config: CacheConfig) -> Self {
+        Listings {
+            all_posts: Arc::new(Cache::new("all_posts", config.clone())),
+            users_posts: Arc::new(Cache::new("users_posts", config)),
+        }
+    }
+
+    pub(crate) fn all_posts<F>(&self, load: F) -> Result<(Validators, Arc<Vec<PostEntry>>), AppError>
+        where
+            F: FnOnce(bool) -> Result<(Validators, Vec<PostEntry>), AppError>,
+    {
+        self.all_posts.get_or_load((), load)
+    }
+
+    pub(crate) fn users_posts<F>(&self, user_id: i32, load: F) -> Result<(Validators, Arc<Vec<UserPostEntry>>), AppError>
+        where
+            F: FnOnce(bool) -> Result<(Validators, Vec<UserPostEntry>), AppError>,
+    {
+        self.users_posts.get_or_load(user_id, load)
+    }
+
+    // Drafts are only listed with the other posts of their author
+    pub(crate) fn post_changed(&self, post: &Post) {
+        if post.published {
+            self.all_posts.invalidate(&());
+        }
+        self.users_posts.invalidate(&post.user_id);
+    }
+
+    // Comments, reactions and authors show up in every listing, and which listings hold a given
+    // one isn't known
+    pub(crate) fn clear(&self) {
+        self.all_posts.clear();
+        self.users_posts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CacheConfig, CACHE_REQUESTS};
+    use crate::conditional::Validators;
+    use std::time::Duration;
+
+    #[test]
+    fn disabled_cache_not_counted() {
+        let cache = Cache::new("disabled", CacheConfig {
+            capacity: 0,
+            ttl: Duration::from_secs(60),
+        });
+        for _ in 0..2 {
+            let (_, value) = cache.get_or_load((), |_| Ok((Validators::new(&[]), 42))).unwrap();
+            assert_eq!(*value, 42);
+        }
+        assert_eq!(CACHE_REQUESTS.with_label_values(&["disabled", "miss"]).get(), 0);
+        assert_eq!(CACHE_REQUESTS.with_label_values(&["disabled", "hit"]).get(), 0);
+    }
+
+    #[test]
+    fn loads_read_the_primary_after_an_invalidation() {
+        let cache = Cache::new("primary", CacheConfig {
+            capacity: 10,
+            ttl: Duration::from_millis(50),
+        });
+        let load = |key: i32| {
+            let mut primary = None;
+            cache
+                .get_or_load(key, |from_primary| {
+                    primary = Some(from_primary);
+                    Ok((Validators::new(&[]), ()))
+                })
+                .unwrap();
+            primary
+        };
+
+        assert_eq!(load(1), Some(false));
+        cache.invalidate(&1);
+        assert_eq!(load(1), Some(true));
+        // served from the cache
+        assert_eq!(load(1), None);
+        // every key of the cache, a clear doesn't say which ones changed
+        assert_eq!(load(2), Some(true));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(load(3), Some(false));
+    }
+}
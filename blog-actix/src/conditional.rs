@@ -0,0 +1,125 @@
+use crate::errors::AppError;
+use crate::models::TableVersion;
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch,
+    LastModified,
+};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Validators of a response, derived from the versions of the tables it is read from. They change
+// with any write to those tables, which may be more often than the response itself does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validators {
+    etag: EntityTag,
+    last_modified: SystemTime,
+}
+
+impl Validators {
+    pub fn new(versions: &[TableVersion]) -> Self {
+        // the crate version is part of the tag, a release may change how responses are rendered
+        let mut tag = env!("CARGO_PKG_VERSION").to_owned();
+        for version in versions {
+            tag.push_str(&format!("-{}.{}", version.name, version.version));
+        }
+        let updated_at = versions.iter().map(|version| version.updated_at).max().unwrap_or(0);
+        // HTTP dates have a one second resolution
+        let secs = updated_at.max(0) as u64 / 1000;
+
+        Validators {
+            etag: EntityTag::strong(tag),
+            last_modified: UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    // Only given out once its second is over. A client holding a copy from the same second as a
+    // write would otherwise get a 304 after another write in that second (RFC 7232, 2.2.2).
+    fn last_modified(&self) -> Option<SystemTime> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let current_second = UNIX_EPOCH + Duration::from_secs(now.as_secs());
+        Some(self.last_modified).filter(|last_modified| *last_modified < current_second)
+    }
+
+    // Clients may store the response, but have to revalidate it before every use
+    fn apply<'a>(&self, res: &'a mut HttpResponseBuilder) -> &'a mut HttpResponseBuilder {
+        if let Some(last_modified) = self.last_modified() {
+            res.set(LastModified(HttpDate::from(last_modified)));
+        }
+        res.set(ETag(self.etag.clone()))
+            .set(CacheControl(vec![CacheDirective::NoCache]))
+    }
+}
+
+// What a client holding an earlier copy of the response sent to revalidate it
+#[derive(Debug)]
+pub struct Preconditions {
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<SystemTime>,
+}
+
+impl Preconditions {
+    pub fn new(req: &HttpRequest) -> Self {
+        Preconditions {
+            if_none_match: req.get_header::<IfNoneMatch>(),
+            if_modified_since: req
+                .get_header::<IfModifiedSince>()
+                .map(|since| since.0.into()),
+        }
+    }
+
+    // If-Modified-Since is ignored when If-None-Match is sent, as RFC 7232 asks
+    fn matches(&self, validators: &Validators) -> bool {
+        match &self.if_none_match {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&validators.etag)),
+            None => self.if_modified_since.is_some_and(|since| {
+                validators.last_modified().is_some_and(|last_modified| last_modified <= since)
+            }),
+        }
+    }
+
+    // Only runs `load` when the client's copy is out of date
+    pub fn evaluate<T, F>(&self, versions: &[TableVersion], load: F) -> Result<Conditional<T>, AppError>
+        where
+            F: FnOnce() -> Result<T, AppError>,
+    {
+        let validators = Validators::new(versions);
+        if self.matches(&validators) {
+            Ok(Conditional::NotModified(validators))
+        } else {
+            load().map(|data| Conditional::Modified(validators, data))
+        }
+    }
+
+    // For data loaded along with its validators, as the response caches do
+    pub fn check<T>(&self, validators: Validators, data: T) -> Conditional<T> {
+        if self.matches(&validators) {
+            Conditional::NotModified(validators)
+        } else {
+            Conditional::Modified(validators, data)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Conditional<T> {
+    NotModified(Validators),
+    Modified(Validators, T),
+}
+
+// Like routes::convert, answering 304 without a body when the client's copy is still fresh
+pub fn convert<T, E>(res: Result<Conditional<T>, E>) -> Result<HttpResponse, AppError>
+    where
+        T: serde::Serialize,
+        AppError: From<E>,
+{
+    match res? {
+        Conditional::NotModified(validators) => {
+            Ok(validators.apply(&mut HttpResponse::NotModified()).finish())
+        }
+        Conditional::Modified(validators, data) => {
+            Ok(validators.apply(&mut HttpResponse::Ok()).json(data))
+        }
+    }
+}
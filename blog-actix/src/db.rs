@@ -0,0 +1,180 @@
+use crate::errors::AppError;
+use diesel::connection::SimpleConnection;
+use diesel::query_builder::{QueryBuilder, QueryFragment};
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, PooledConnection};
+use diesel::sqlite::{Sqlite, SqliteQueryBuilder};
+use diesel::SqliteConnection;
+use diesel_migrations::MigrationConnection;
+use diesel::result::Error::DatabaseError;
+use std::ops::Deref;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info_span, Span};
+
+type Pool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+
+const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_POOL_SIZE: u32 = 8;
+const WAL_RETRY_PAUSE: Duration = Duration::from_millis(10);
+// message of SQLITE_BUSY, which diesel has no error kind for
+const DATABASE_LOCKED: &str = "database is locked";
+
+// Versions of the migrations in migrations/, collected by build.rs
+const MIGRATIONS: &str = env!("MIGRATIONS");
+
+// SQLite settings are per connection, so every connection the pool opens has to be set up again.
+#[derive(Debug)]
+pub struct ConnectionOptions {
+    pub busy_timeout: Duration,
+    pub read_only: bool,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            read_only: false,
+        }
+    }
+}
+
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        // The busy timeout comes first so the statements after it wait for the locks
+        let mut pragmas = format!(
+            "PRAGMA busy_timeout = {}; PRAGMA foreign_keys = ON;",
+            self.busy_timeout.as_millis()
+        );
+        // WAL is a setting of the database file, kept once the writer has set it. Readers
+        // never set it: with query_only they can't, and a replica has its own journal mode.
+        if self.read_only {
+            pragmas.push_str("PRAGMA query_only = ON;");
+        }
+        conn.batch_execute(&pragmas).map_err(r2d2::Error::QueryError)?;
+        if self.read_only {
+            Ok(())
+        } else {
+            self.switch_to_wal(conn).map_err(r2d2::Error::QueryError)
+        }
+    }
+}
+
+impl ConnectionOptions {
+    // Switching a new database to WAL takes a write lock. When two connections try at once,
+    // SQLite fails one of them with SQLITE_BUSY right away rather than waiting on the busy
+    // timeout, as both would otherwise wait for each other, so the switch is retried.
+    fn switch_to_wal(&self, conn: &SqliteConnection) -> Result<(), diesel::result::Error> {
+        let started = Instant::now();
+        loop {
+            let result = conn.batch_execute("PRAGMA journal_mode = WAL;");
+            let locked = matches!(&result, Err(DatabaseError(_, info)) if info.message() == DATABASE_LOCKED);
+            if !locked || started.elapsed() >= self.busy_timeout {
+                return result;
+            }
+            thread::sleep(WAL_RETRY_PAUSE);
+        }
+    }
+}
+
+// SQLite allows a single writer and, in WAL mode, any number of readers next to it. Writes
+// go through a pool holding one connection so they queue on the pool instead of on the
+// database lock, while reads get their own pool and never wait behind a write transaction.
+#[derive(Clone)]
+pub struct Pools {
+    read: Pool,
+    write: Pool,
+    // the reads go to a replica rather than the database written to
+    replica: bool,
+}
+
+impl Pools {
+    pub fn new(database_url: &str, read_url: Option<&str>) -> Self {
+        let write = r2d2::Pool::builder()
+            .max_size(1)
+            .connection_customizer(Box::new(ConnectionOptions::default()))
+            .build(ConnectionManager::<SqliteConnection>::new(database_url))
+            .expect("Failed to create write pool");
+
+        let read = r2d2::Pool::builder()
+            .max_size(READ_POOL_SIZE)
+            .connection_customizer(Box::new(ConnectionOptions {
+                read_only: true,
+                ..ConnectionOptions::default()
+            }))
+            .build(ConnectionManager::<SqliteConnection>::new(
+                read_url.unwrap_or(database_url),
+            ))
+            .expect("Failed to create read pool");
+
+        Pools {
+            read,
+            write,
+            replica: read_url.is_some(),
+        }
+    }
+
+    pub fn reader(&self) -> Result<ReadConnection, AppError> {
+        self.read.get().map(ReadConnection).map_err(Into::into)
+    }
+
+    // For reads that have to see the last writes: the write connection when the reads go to a
+    // replica, which may lag behind, a read connection otherwise
+    pub fn primary_reader(&self) -> Result<ReadConnection, AppError> {
+        let pool = if self.replica { &self.write } else { &self.read };
+        pool.get().map(ReadConnection).map_err(Into::into)
+    }
+
+    pub fn writer(&self) -> Result<WriteConnection, AppError> {
+        self.write.get().map(WriteConnection).map_err(Into::into)
+    }
+
+    // Open and idle connections of each pool, for the metrics endpoint
+    pub fn states(&self) -> [(&'static str, r2d2::State); 2] {
+        [("read", self.read.state()), ("write", self.write.state())]
+    }
+}
+
+pub struct ReadConnection(PooledConnection<ConnectionManager<SqliteConnection>>);
+
+pub struct WriteConnection(PooledConnection<ConnectionManager<SqliteConnection>>);
+
+impl Deref for ReadConnection {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        &self.0
+    }
+}
+
+impl Deref for WriteConnection {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        &self.0
+    }
+}
+
+// Span around a single statement, recording its SQL but not the values bound to it
+pub fn sql_span<Q: QueryFragment<Sqlite>>(query: &Q) -> Span {
+    let mut sql = SqliteQueryBuilder::new();
+    let statement = match query.to_sql(&mut sql) {
+        Ok(()) => sql.finish(),
+        Err(_) => String::new(),
+    };
+    info_span!("sql", db.system = "sqlite", db.statement = %statement)
+}
+
+// Migrations this build expects that have not been run on the database yet
+pub fn pending_migrations(conn: &ReadConnection) -> Result<Vec<String>, AppError> {
+    let applied = conn.previously_run_migration_versions()?;
+
+    Ok(MIGRATIONS
+        .split(',')
+        .filter(|version| !applied.contains(*version))
+        .map(str::to_owned)
+        .collect())
+}
+
+pub fn schema_version(conn: &ReadConnection) -> Result<Option<String>, AppError> {
+    conn.latest_run_migration_version().map_err(Into::into)
+}
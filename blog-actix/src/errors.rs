@@ -1,33 +1,69 @@
 use std::fmt;
+use std::time::Duration;
 
 use actix_web::error::BlockingError;
+use actix_web::http::header;
 use actix_web::web::HttpResponse;
-use diesel::result::DatabaseErrorKind::UniqueViolation;
+use diesel::result::DatabaseErrorKind::{ForeignKeyViolation, UniqueViolation};
 use diesel::result::Error::{DatabaseError, NotFound};
-use diesel::result::Error;
-use actix_web::error::PayloadError::Http2Payload;
+use schemars::JsonSchema;
+use server_common::rate_limit::retry_after_secs;
+
+// Message of RecordAlreadyExist, the one 400 clients tell apart from the validation errors
+pub const RECORD_ALREADY_EXIST: &str = "This recird violates a unique contraint";
 
 #[derive(Debug)]
 pub enum AppError {
     RecordAlreadyExist,
     RecordNotFound,
+    ReferenceNotFound,
     DatabaseError(diesel::result::Error),
+    ConnectionUnavailable(String),
+    MigrationsPending(Vec<String>),
     OperationCanceled,
+    Unauthorized,
+    BackupFailed(String),
+    RateLimited(Duration),
+    InvalidSyncToken,
+    InvalidWebhook(String),
+    InvalidEmail,
+    InvalidCursor,
+    CannotFollowSelf,
+    InvalidProfile(String),
+    InvalidUsername,
 }
 
-#[derive(Debug, Serialize)]
-struct ErrorResponse {
-    err: String,
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorResponse {
+    pub err: String,
+    // filled in by the RequestLog middleware, the same id is in the logs and the X-Request-Id header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            AppError::RecordAlreadyExist => write!(f, "This recird violates a unique contraint"),
+            AppError::RecordAlreadyExist => write!(f, "{}", RECORD_ALREADY_EXIST),
             AppError::RecordNotFound => write!(f, "This record does not exist"),
+            AppError::ReferenceNotFound => write!(f, "This record refers to a record that does not exist"),
             AppError::DatabaseError(e) => write!(f, "Database Error: {:?}", e),
+            AppError::ConnectionUnavailable(e) => write!(f, "No database connection available: {}", e),
+            AppError::MigrationsPending(versions) => write!(f, "Database migrations pending: {}", versions.join(", ")),
             AppError::OperationCanceled => write!(f, "Running operation was cancelled"),
+            AppError::Unauthorized => write!(f, "Missing or invalid credentials"),
+            AppError::BackupFailed(e) => write!(f, "Backup Error: {}", e),
+            AppError::InvalidSyncToken => write!(f, "Invalid sync token"),
+            AppError::InvalidWebhook(e) => write!(f, "Invalid webhook: {}", e),
+            AppError::InvalidEmail => write!(f, "Invalid email address"),
+            AppError::InvalidCursor => write!(f, "Invalid page cursor"),
+            AppError::CannotFollowSelf => write!(f, "Users can't follow themselves"),
+            AppError::InvalidProfile(e) => write!(f, "Invalid profile: {}", e),
+            AppError::InvalidUsername => write!(f, "Invalid username"),
+            AppError::RateLimited(retry_after) => {
+                write!(f, "Too many requests, retry in {} seconds", retry_after_secs(*retry_after))
+            }
         }
     }
 }
@@ -37,12 +73,25 @@ impl From<diesel::result::Error> for AppError {
     fn from(e: diesel::result::Error) -> Self {
         match e {
             DatabaseError(UniqueViolation, _) => AppError::RecordAlreadyExist,
+            DatabaseError(ForeignKeyViolation, _) => AppError::ReferenceNotFound,
             NotFound => AppError::RecordNotFound,
             _ => AppError::DatabaseError(e)
         }
     }
 }
 
+impl From<diesel::r2d2::PoolError> for AppError {
+    fn from(e: diesel::r2d2::PoolError) -> Self {
+        AppError::ConnectionUnavailable(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        AppError::BackupFailed(e.to_string())
+    }
+}
+
 impl From<BlockingError<AppError>> for AppError {
     fn from(e: BlockingError<AppError>) -> Self {
         match e {
@@ -56,11 +105,28 @@ impl actix_web::ResponseError for AppError {
     fn error_response(&self) -> HttpResponse {
         let err = format!("{}", self);
         let mut builder = match self {
-            AppError::RecordAlreadyExist => HttpResponse::BadRequest(),
+            AppError::RecordAlreadyExist
+            | AppError::InvalidSyncToken
+            | AppError::InvalidWebhook(_)
+            | AppError::InvalidEmail
+            | AppError::InvalidCursor
+            | AppError::CannotFollowSelf
+            | AppError::InvalidProfile(_)
+            | AppError::InvalidUsername => HttpResponse::BadRequest(),
             AppError::RecordNotFound => HttpResponse::NotFound(),
+            AppError::ReferenceNotFound => HttpResponse::UnprocessableEntity(),
+            AppError::Unauthorized => HttpResponse::Unauthorized(),
+            AppError::ConnectionUnavailable(_) | AppError::MigrationsPending(_) => {
+                HttpResponse::ServiceUnavailable()
+            }
+            AppError::RateLimited(retry_after) => {
+                let mut builder = HttpResponse::TooManyRequests();
+                builder.header(header::RETRY_AFTER, retry_after_secs(*retry_after));
+                builder
+            }
             _ => HttpResponse::InternalServerError()
         };
-        builder.json(ErrorResponse { err })
+        builder.json(ErrorResponse { err, request_id: None })
     }
 
     fn render_response(&self) -> HttpResponse {
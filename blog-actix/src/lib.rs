@@ -3,49 +3,335 @@ extern crate diesel;
 #[macro_use]
 extern crate serde_derive;
 
-use actix_web::{middleware, App, HttpServer};
-use diesel::prelude::*;
-use diesel::r2d2::{self, ConnectionManager};
-use std::io::Result;
+use actix_web::dev::{Body, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, Method};
+use actix_web::{App, Error, HttpServer};
+use actix_service::NewService;
+use server_common::cors::Cors;
+use server_common::logging::RequestLog;
+use server_common::metrics::Metrics;
+use server_common::rate_limit::{Limit, RateLimit};
+use server_common::security::SecurityHeaders;
+use server_common::shutdown;
+use server_common::certs::TlsConfig;
+use std::io::{self, Result};
+use std::sync::Arc;
+use std::time::Duration;
 
-type Pool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+pub use crate::backup::{BackupConfig, Backups, Snapshot};
+pub use crate::cache::{CacheConfig, Listings};
+pub use crate::errors::{ErrorResponse, RECORD_ALREADY_EXIST};
+pub use crate::mail::{MaildirMailer, Mailer, Message, SmtpConfig, SmtpMailer};
+pub use crate::models::{
+    fill_username_keys, Change, Comment, CommentWithAuthor, Delivery, Entity, Event, Follow,
+    Operation, Post, PostEntry, PostWithComment, Profile, ProfileChanges, Reaction, ReactionKind,
+    Reactions, Subscriber, User, UserPostEntry, Webhook,
+};
+pub use crate::outbox::{MailConfig, Outbox};
+pub use crate::routes::docs::SwaggerUi;
+pub use crate::db::{ConnectionOptions, Pools};
+pub use crate::streams::Streams;
+pub use crate::tokens::TokenUsers;
+pub use crate::webhooks::{WebhookConfig, Webhooks};
 
+// online snapshots of the SQLite database
+mod backup;
+// ETag and Last-Modified validators answering conditional GETs with 304
+mod conditional;
+// in-process caches of the post listings
+mod cache;
+// per connection SQLite settings applied by the pool
+mod db;
 // code for working with various failure scenarios
 mod errors;
+// the Mailer trait and its SMTP and Maildir implementations
+mod mail;
+// background sending of the notification mail queued by the writes
+mod outbox;
+// OpenAPI document describing the routes
+mod openapi;
 // code to define the Rust representation of our data model as represented by our database
 mod models;
+// backoff and bookkeeping of the attempts of the outbox and webhook workers
+mod retry;
 // code for defining the handlers that will make up the functions that get
 // called by the framework in response to web requests
-mod routes;
+pub mod routes;
 // this is autogenerated by Diesel as we have mentioned before
 mod schema;
+// Server-Sent Events pushing new comments and published posts
+mod streams;
+// serde format of the timestamps in the models
+mod timestamp;
+// random tokens and secrets, and the hashes stored of the tokens
+mod tokens;
+// signed deliveries of the blog events to the registered webhooks
+mod webhooks;
+
+// Comment line sent on idle event streams
+const STREAM_HEARTBEAT: Duration = Duration::from_secs(15);
+
+// What the App is built from, cloned into every worker. Blog::run fills it in from its
+// settings, the integration tests with their own.
+#[derive(Clone)]
+pub struct AppState {
+    pub pools: Pools,
+    pub listings: Listings,
+    pub streams: Streams,
+    pub token_users: TokenUsers,
+    // enables the admin route taking snapshots
+    pub backups: Option<Backups>,
+    // enables the admin routes managing webhooks
+    pub admin_token: Option<String>,
+    pub cors: Cors,
+    pub security_headers: SecurityHeaders,
+    pub rate_limit: RateLimit,
+    pub swagger_ui: SwaggerUi,
+}
+
+impl AppState {
+    pub fn app(
+        &self,
+    ) -> App<
+        impl NewService<
+            Config=(),
+            Request=ServiceRequest,
+            Response=ServiceResponse<Body>,
+            Error=Error,
+            InitError=(),
+        >,
+        Body,
+    > {
+        let mut app = App::new()
+            .data(self.pools.clone())
+            .data(self.listings.clone())
+            .data(self.streams.clone())
+            .data(self.token_users.clone())
+            .data(self.swagger_ui.clone())
+            .wrap(self.rate_limit.clone().error(routes::rate_limited))
+            .wrap(self.cors.clone().exposed_header(HeaderName::from_static(routes::users::TOKEN_HEADER)))
+            .wrap(self.security_headers.clone())
+            .wrap(RequestLog::default().user_id(routes::user_id))
+            .wrap(Metrics)
+            .configure(routes::users::configure)
+            .configure(routes::follows::configure)
+            .configure(routes::posts::configure)
+            .configure(routes::comments::configure)
+            .configure(routes::reactions::configure)
+            .configure(routes::changes::configure)
+            .configure(routes::streams::configure)
+            .configure(routes::subscribers::configure)
+            .configure(routes::docs::configure)
+            .configure(routes::health::configure)
+            .configure(routes::metrics::configure);
+
+        if let Some(backups) = &self.backups {
+            app = app.data(backups.clone()).configure(routes::admin::configure);
+        }
+        if let Some(token) = &self.admin_token {
+            app = app
+                .data(routes::admin::AdminToken(token.clone()))
+                .configure(routes::webhooks::configure);
+        }
+        app
+    }
+}
 
 pub struct Blog {
+    host: String,
     port: u16,
+    backups: Option<BackupConfig>,
+    read_url: Option<String>,
+    drain_timeout: Duration,
+    tls: Option<TlsConfig>,
+    cors: Cors,
+    security_headers: SecurityHeaders,
+    rate_limit: RateLimit,
+    swagger_ui: SwaggerUi,
+    cache: CacheConfig,
+    admin_token: Option<String>,
+    webhooks: WebhookConfig,
+    mail: Option<(MailConfig, Arc<dyn Mailer>)>,
 }
 
 impl Blog {
     pub fn new(port: u16) -> Self {
-        Blog { port }
+        Blog {
+            host: "127.0.0.1".to_owned(),
+            port,
+            backups: None,
+            read_url: None,
+            drain_timeout: shutdown::DEFAULT_DRAIN_TIMEOUT,
+            tls: None,
+            cors: Cors::default(),
+            security_headers: SecurityHeaders::default(),
+            rate_limit: RateLimit::default()
+                .route(Method::POST, "/users", Limit::per_minute(10))
+                .route(Method::POST, "/users/{id}/posts", Limit::per_minute(10))
+                .route(Method::POST, "/posts/{id}/comments", Limit::per_minute(30))
+                .route(Method::POST, "/subscribers", Limit::per_minute(10))
+                .route(Method::POST, "/users/{id}/following", Limit::per_minute(30))
+                .route(Method::POST, "/users/{id}/rename", Limit::per_minute(10))
+                .route(Method::POST, "/posts/{id}/reactions", Limit::per_minute(60))
+                .route(Method::POST, "/comments/{id}/reactions", Limit::per_minute(60)),
+            swagger_ui: SwaggerUi::default(),
+            cache: CacheConfig::default(),
+            admin_token: None,
+            webhooks: WebhookConfig::default(),
+            mail: None,
+        }
+    }
+
+    pub fn with_backups(mut self, config: BackupConfig) -> Self {
+        self.backups = Some(config);
+        self
+    }
+
+    // Sends reads to a replica of the database instead of the primary
+    pub fn with_read_replica(mut self, read_url: String) -> Self {
+        self.read_url = Some(read_url);
+        self
+    }
+
+    // How long requests in flight get to finish after SIGTERM or SIGINT
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    // Address to listen on, 127.0.0.1 by default
+    pub fn with_host(mut self, host: String) -> Self {
+        self.host = host;
+        self
+    }
+
+    // Serves HTTPS, and HTTP/2 to the clients asking for it, instead of plain HTTP
+    pub fn with_tls(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    // Origins allowed to call the API from a browser, none by default
+    pub fn with_cors(mut self, cors: Cors) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    pub fn with_security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.security_headers = security_headers;
+        self
+    }
+
+    // Replaces the default limits on the routes creating users, posts and comments. Requests with
+    // a user's token are counted for that user, the others for their address.
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    // Where /docs loads Swagger UI from, a URL or a path of a copy of swagger-ui-dist
+    pub fn with_swagger_ui(mut self, assets_url: &str) -> Self {
+        self.swagger_ui = SwaggerUi::new(assets_url);
+        self
+    }
+
+    // Size and lifetime of the caches of the post listings
+    pub fn with_cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    // Enables the admin routes managing webhooks, the ones taking snapshots come with the backups
+    pub fn with_admin_token(mut self, admin_token: String) -> Self {
+        self.admin_token = Some(admin_token);
+        self
+    }
+
+    // Retries and timeouts of the webhook deliveries
+    pub fn with_webhooks(mut self, config: WebhookConfig) -> Self {
+        self.webhooks = config;
+        self
+    }
+
+    // Sends the notification mail. Without a mailer it stays in the outbox until one is set.
+    pub fn with_mailer(mut self, config: MailConfig, mailer: Arc<dyn Mailer>) -> Self {
+        self.mail = Some((config, mailer));
+        self
     }
 
     pub fn run(&self, database_url: String) -> Result<()> {
-        let manager = ConnectionManager::<SqliteConnection>::new(database_url);
-        let pool = r2d2::Pool::builder()
-            .build(manager)
-            .expect("Failed to create pool");
-
-        print!("Starting http server: 127.0.0.1:{}", self.port);
-
-        HttpServer::new(move || {
-            App::new()
-                .data(pool.clone())
-                .wrap(middleware::Logger::default())
-                .configure(routes::users::configure)
-                .configure(routes::posts::configure)
-                .configure(routes::comments::configure)
-        })
-        .bind(("127.0.0.1", self.port))?
-        .run()
+        let pools = Pools::new(&database_url, self.read_url.as_deref());
+        // the users created before usernames had keys, before anybody can take a clashing name
+        let filled = pools
+            .writer()
+            .and_then(|conn| fill_username_keys(&conn))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        if filled > 0 {
+            log::info!("Filled in the username keys of {} users", filled);
+        }
+        let token_users = pools
+            .writer()
+            .and_then(|conn| TokenUsers::load(&conn))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        let backups = self
+            .backups
+            .clone()
+            .map(|config| Backups::new(database_url, config));
+        // stopped once the server has drained, before the pools are closed
+        let mut workers = Vec::new();
+        workers.extend(backups.as_ref().and_then(Backups::schedule));
+
+        // deliveries queued before a restart are sent too
+        workers.push(Webhooks::new(pools.clone(), self.webhooks.clone()).schedule());
+        if let Some((config, mailer)) = &self.mail {
+            workers.push(Outbox::new(pools.clone(), config.clone(), mailer.clone()).schedule());
+        }
+
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        print!("Starting {} server: {}:{}", scheme, self.host, self.port);
+
+        let state = AppState {
+            pools: pools.clone(),
+            listings: Listings::new(self.cache.clone()),
+            streams: Streams::new(),
+            token_users,
+            backups,
+            admin_token: self.admin_token.clone(),
+            cors: self.cors.clone(),
+            security_headers: self.security_headers.clone(),
+            rate_limit: self.rate_limit.clone().user(routes::rate_limited_user),
+            swagger_ui: self.swagger_ui.clone(),
+        };
+        workers.push(state.streams.schedule(STREAM_HEARTBEAT));
+        let factory = move || state.app();
+
+        let served = shutdown::run("blog-actix", || {
+            let addr = (self.host.as_str(), self.port);
+            let server = HttpServer::new(factory);
+            let server = match &self.tls {
+                Some(config) => server.bind_rustls(addr, config.server_config()?)?,
+                None => server.bind(addr)?,
+            };
+            Ok(server
+                .shutdown_timeout(self.drain_timeout.as_secs())
+                .disable_signals()
+                .system_exit()
+                .start())
+        });
+
+        for worker in workers {
+            worker.stop();
+        }
+        served?;
+
+        // Requests that timed out while draining may have left their closure running, its
+        // transaction has to commit or roll back before the connections are closed
+        if !shutdown::wait_for_blocking_tasks(self.drain_timeout) {
+            log::warn!("Database work still running after the drain timeout");
+        }
+        drop(pools);
+        log::info!("Database pools closed");
+        Ok(())
     }
 }
\ No newline at end of file
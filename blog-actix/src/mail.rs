@@ -0,0 +1,259 @@
+use chrono::Utc;
+use rustls::{ClientConfig, ClientSession, StreamOwned};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Sends the messages of the outbox. Implementations are called from the outbox worker thread and
+// may block, an Err is retried later.
+pub trait Mailer: Send + Sync {
+    fn send(&self, message: &Message) -> io::Result<()>;
+}
+
+// A plain text message
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    // added after the standard ones, List-Unsubscribe for instance
+    pub headers: Vec<(String, String)>,
+}
+
+impl Message {
+    // RFC 5322 text, lines ending with CRLF
+    pub fn format(&self) -> String {
+        let mut text = String::new();
+        let mut header = |name: &str, value: &str| {
+            // values come from users, they can't be allowed to start new headers
+            let value: String = value.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+            text.push_str(&format!("{}: {}\r\n", name, value));
+        };
+        header("From", &self.from);
+        header("To", &self.to);
+        header("Subject", &encode_header(&self.subject));
+        header("Date", &Utc::now().to_rfc2822());
+        header("MIME-Version", "1.0");
+        header("Content-Type", "text/plain; charset=utf-8");
+        header("Content-Transfer-Encoding", "8bit");
+        for (name, value) in &self.headers {
+            header(name, value);
+        }
+        text.push_str("\r\n");
+        for line in self.body.lines() {
+            text.push_str(line);
+            text.push_str("\r\n");
+        }
+        text
+    }
+}
+
+// Loose check of the addresses given by users, mostly keeping them from breaking the headers and
+// SMTP commands they end up in
+pub fn valid_email(email: &str) -> bool {
+    let mut parts = email.splitn(2, '@');
+    let (local, domain) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    email.len() <= 254
+        && !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains('@')
+        && email
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_control() && !"<>,;\"\\()[]".contains(c))
+}
+
+// RFC 2047 encoded-word for the headers that aren't ASCII
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_owned()
+    } else {
+        format!("=?utf-8?B?{}?=", base64::encode(value))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    // TLS from the start of the connection, as on port 465. STARTTLS isn't supported.
+    pub tls: bool,
+    // sent with AUTH PLAIN, so only allowed with TLS
+    pub credentials: Option<(String, String)>,
+    pub timeout: Duration,
+}
+
+impl SmtpConfig {
+    pub fn new(host: String) -> Self {
+        SmtpConfig {
+            host,
+            port: 25,
+            tls: false,
+            credentials: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+// Hands the messages to an SMTP relay, one connection per message
+pub struct SmtpMailer {
+    config: SmtpConfig,
+    tls: Arc<ClientConfig>,
+}
+
+impl SmtpMailer {
+    // Fails when credentials would be sent in the clear
+    pub fn new(config: SmtpConfig) -> io::Result<Self> {
+        if config.credentials.is_some() && !config.tls {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SMTP credentials are only sent over TLS",
+            ));
+        }
+        let mut tls = ClientConfig::new();
+        tls.root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+        Ok(SmtpMailer {
+            config,
+            tls: Arc::new(tls),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: &Message) -> io::Result<()> {
+        let config = &self.config;
+        let addr = (config.host.as_str(), config.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| smtp_error("host without an address"))?;
+        let socket = TcpStream::connect_timeout(&addr, config.timeout)?;
+        socket.set_read_timeout(Some(config.timeout))?;
+        socket.set_write_timeout(Some(config.timeout))?;
+
+        if config.tls {
+            let name = webpki::DNSNameRef::try_from_ascii_str(&config.host)
+                .map_err(|_| smtp_error("invalid host name"))?;
+            let session = ClientSession::new(&self.tls, name);
+            Smtp::new(StreamOwned::new(session, socket)).send(config, message)
+        } else {
+            Smtp::new(socket).send(config, message)
+        }
+    }
+}
+
+fn smtp_error(message: &str) -> io::Error {
+    io::Error::other(message.to_owned())
+}
+
+struct Smtp<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Smtp<S> {
+    fn new(stream: S) -> Self {
+        Smtp {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    fn send(&mut self, config: &SmtpConfig, message: &Message) -> io::Result<()> {
+        self.reply(220)?;
+        self.command("EHLO localhost", 250)?;
+        if let Some((username, password)) = &config.credentials {
+            let token = base64::encode(&format!("\0{}\0{}", username, password));
+            self.command(&format!("AUTH PLAIN {}", token), 235)?;
+        }
+        self.command(&format!("MAIL FROM:<{}>", address(&message.from)), 250)?;
+        self.command(&format!("RCPT TO:<{}>", address(&message.to)), 250)?;
+        self.command("DATA", 354)?;
+
+        // a line holding a single dot would end the data early
+        let mut data = String::new();
+        for line in message.format().split_terminator("\r\n") {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push('.');
+        self.command(&data, 250)?;
+        self.command("QUIT", 221)
+    }
+
+    fn command(&mut self, line: &str, expected: u16) -> io::Result<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(line.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.reply(expected)
+    }
+
+    // Reads a reply, the lines of a multiline one all but the last having a dash after the code
+    fn reply(&mut self, expected: u16) -> io::Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                return Err(smtp_error("connection closed by the server"));
+            }
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            if code != Some(expected) {
+                return Err(smtp_error(&format!("unexpected reply: {}", line.trim_end())));
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// The address of `Name <address>`
+fn address(mailbox: &str) -> &str {
+    match (mailbox.rfind('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+static DELIVERIES: AtomicUsize = AtomicUsize::new(0);
+
+// Writes the messages to a Maildir instead of sending them, for local testing. Any mail client
+// reading Maildirs shows them, or they can be read in `{dir}/new`.
+pub struct MaildirMailer {
+    dir: PathBuf,
+}
+
+impl MaildirMailer {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        for sub in &["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        Ok(MaildirMailer { dir })
+    }
+}
+
+impl Mailer for MaildirMailer {
+    fn send(&self, message: &Message) -> io::Result<()> {
+        let now = Utc::now();
+        let name = format!(
+            "{}.M{:06}P{}Q{}.blog-actix",
+            now.timestamp(),
+            now.timestamp_subsec_micros(),
+            process::id(),
+            DELIVERIES.fetch_add(1, Ordering::SeqCst)
+        );
+        // readers only ever see complete messages in new
+        let tmp = self.dir.join("tmp").join(&name);
+        fs::write(&tmp, message.format())?;
+        fs::rename(&tmp, self.dir.join("new").join(&name))
+    }
+}
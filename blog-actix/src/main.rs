@@ -1,15 +1,120 @@
 use dotenv::dotenv;
+use blog_actix::{
+    BackupConfig, CacheConfig, MailConfig, MaildirMailer, Mailer, SmtpConfig, SmtpMailer, WebhookConfig,
+};
+use server_common::cors::Cors;
+use server_common::rate_limit::RateLimit;
+use server_common::security::SecurityHeaders;
+use server_common::certs::TlsConfig;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 
 fn main() -> std::io::Result<()> {
     // Calling dotenv().ok() sets environment variables based on the contents of the .env file in
     // the current directory and ignores any error that might result.
     dotenv().ok();
 
-    env::set_var("RUST_LOG", "actix_web=info");
-    env_logger::init();
+    env::set_var("RUST_LOG", "access=info,actix_web=info,blog_actix=info,server_common=info");
+    server_common::logging::init();
+    let _tracing = server_common::trace::init("blog-actix")?;
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let app = blog_actix::Blog::new(8998);
+    let mut app = blog_actix::Blog::new(8998);
+
+    if let Some(secs) = env::var("DRAIN_TIMEOUT_SECS").ok().and_then(|secs| secs.parse().ok()) {
+        app = app.with_drain_timeout(Duration::from_secs(secs));
+    }
+
+    if let Ok(host) = env::var("HOST") {
+        app = app.with_host(host);
+    }
+
+    if let Some(tls) = TlsConfig::from_env() {
+        app = app.with_tls(tls);
+    }
+
+    app = app
+        .with_cors(Cors::from_env())
+        .with_security_headers(SecurityHeaders::from_env());
+
+    if let Ok(assets_url) = env::var("SWAGGER_UI_URL") {
+        app = app.with_swagger_ui(&assets_url);
+    }
+
+    if let Ok(rules) = env::var("RATE_LIMITS") {
+        app = app.with_rate_limit(RateLimit::default().routes(&rules)?);
+    }
+
+    let mut cache = CacheConfig::default();
+    if let Some(capacity) = env::var("CACHE_CAPACITY").ok().and_then(|n| n.parse().ok()) {
+        cache.capacity = capacity;
+    }
+    if let Some(secs) = env::var("CACHE_TTL_SECS").ok().and_then(|secs| secs.parse().ok()) {
+        cache.ttl = Duration::from_secs(secs);
+    }
+    app = app.with_cache(cache);
+
+    let mut webhooks = WebhookConfig::default();
+    if let Some(n) = env::var("WEBHOOK_MAX_ATTEMPTS").ok().and_then(|n| n.parse().ok()) {
+        webhooks.max_attempts = n;
+    }
+    if let Some(secs) = env::var("WEBHOOK_BACKOFF_SECS").ok().and_then(|secs| secs.parse().ok()) {
+        webhooks.backoff = Duration::from_secs(secs);
+    }
+    app = app.with_webhooks(webhooks);
+
+    // An empty token would let every request through, it leaves the admin routes disabled instead
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+    if let Some(admin_token) = admin_token.clone() {
+        app = app.with_admin_token(admin_token);
+    }
+
+    // Notification mail goes through an SMTP relay, or to a Maildir when testing locally
+    let mailer: Option<Arc<dyn Mailer>> = if let Ok(host) = env::var("SMTP_HOST") {
+        let mut smtp = SmtpConfig::new(host);
+        if let Some(port) = env::var("SMTP_PORT").ok().and_then(|port| port.parse().ok()) {
+            smtp.port = port;
+        }
+        smtp.tls = env::var("SMTP_TLS").map(|tls| tls == "1" || tls == "true").unwrap_or(false);
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            smtp.credentials = Some((username, password));
+        }
+        Some(Arc::new(SmtpMailer::new(smtp)?))
+    } else if let Ok(dir) = env::var("MAIL_DIR") {
+        Some(Arc::new(MaildirMailer::new(dir.into())?))
+    } else {
+        None
+    };
+    if let Some(mailer) = mailer {
+        let from = env::var("MAIL_FROM").unwrap_or_else(|_| "blog@localhost".to_owned());
+        let base_url = env::var("BASE_URL").unwrap_or_else(|_| "http://localhost:8998".to_owned());
+        app = app.with_mailer(MailConfig::new(from, base_url), mailer);
+    }
+
+    if let Ok(read_url) = env::var("READ_DATABASE_URL") {
+        app = app.with_read_replica(read_url);
+    }
+
+    // Snapshots are only enabled when both a target directory and an admin token are configured
+    if let (Ok(dir), Some(admin_token)) = (env::var("BACKUP_DIR"), admin_token) {
+        let interval = env::var("BACKUP_INTERVAL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        let retain = env::var("BACKUP_RETAIN")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(24);
+
+        app = app.with_backups(BackupConfig {
+            dir: dir.into(),
+            interval,
+            retain,
+            admin_token,
+        });
+    }
+
     app.run(database_url)
 }
\ No newline at end of file
@@ -1,16 +1,94 @@
+use crate::db::{sql_span, ReadConnection, WriteConnection};
 use crate::errors::AppError;
-use crate::schema::{users, posts, comments};
+use crate::retry;
+use crate::schema::{
+    changes, comments, follows, outbox, posts, reactions, subscribers, table_versions, user_emails,
+    username_history, users, webhook_deliveries, webhooks,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::{exists, sql};
 use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool, Timestamp};
+use diesel::sqlite::Sqlite;
+use schemars::JsonSchema;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use tracing::instrument;
 
 type Result<T> = std::result::Result<T, AppError>;
 
-#[derive(Queryable, Identifiable, Serialize, Debug, PartialEq)]
+// How long the names users were renamed from still find them, nobody else can take them meanwhile
+pub const RENAME_GRACE_DAYS: i64 = 30;
+
+#[derive(Queryable, Identifiable, Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 pub struct User {
     pub id: i32,
     pub username: String,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub updated_at: NaiveDateTime,
+}
+
+// The columns of User. The profile fields and the token hash aren't part of it, so the users
+// embedded in the listings stay small.
+pub const USER_COLUMNS: (users::id, users::username, users::created_at, users::updated_at) =
+    (users::id, users::username, users::created_at, users::updated_at);
+
+// The columns of Profile
+pub const PROFILE_COLUMNS: (
+    users::id,
+    users::username,
+    users::display_name,
+    users::bio,
+    users::website,
+    users::avatar_url,
+    users::created_at,
+    users::updated_at,
+) = (
+    users::id,
+    users::username,
+    users::display_name,
+    users::bio,
+    users::website,
+    users::avatar_url,
+    users::created_at,
+    users::updated_at,
+);
+
+// A user with everything they tell about themselves, read one user at a time
+#[derive(Queryable, Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct Profile {
+    pub id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    pub avatar_url: Option<String>,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub updated_at: NaiveDateTime,
 }
 
-#[derive(Queryable, Associations, Identifiable, Serialize, Debug)]
+// The profile fields to change, the missing ones are left as they are and an empty one is cleared
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default)]
+pub struct ProfileChanges {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Associations, Identifiable, Serialize, Deserialize, JsonSchema, Debug)]
 #[belongs_to(User)]
 pub struct Post {
     pub id: i32,
@@ -18,9 +96,21 @@ pub struct Post {
     pub title: String,
     pub body: String,
     pub published: bool,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub updated_at: NaiveDateTime,
+    #[serde(with = "crate::timestamp::option")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
+    pub published_at: Option<NaiveDateTime>,
+    // counted for the listings only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<Reactions>,
 }
 
-#[derive(Queryable, Associations, Identifiable, Serialize, Debug)]
+#[derive(Associations, Identifiable, Serialize, Deserialize, JsonSchema, Debug)]
 #[belongs_to(User)]
 #[belongs_to(Post)]
 pub struct Comment {
@@ -28,158 +118,1405 @@ pub struct Comment {
     pub user_id: i32,
     pub post_id: i32,
     pub body: String,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub updated_at: NaiveDateTime,
+    // counted for the listings only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<Reactions>,
 }
 
-#[derive(Queryable, Serialize, Debug)]
+// The reaction counts aren't columns, the rows are built by hand to leave them out
+impl Queryable<posts::SqlType, Sqlite> for Post {
+    type Row = (i32, i32, String, String, bool, NaiveDateTime, NaiveDateTime, Option<NaiveDateTime>);
+
+    fn build(row: Self::Row) -> Self {
+        let (id, user_id, title, body, published, created_at, updated_at, published_at) = row;
+        Post { id, user_id, title, body, published, created_at, updated_at, published_at, reactions: None }
+    }
+}
+
+impl Queryable<comments::SqlType, Sqlite> for Comment {
+    type Row = (i32, i32, i32, String, NaiveDateTime, NaiveDateTime);
+
+    fn build(row: Self::Row) -> Self {
+        let (id, user_id, post_id, body, created_at, updated_at) = row;
+        Comment { id, user_id, post_id, body, created_at, updated_at, reactions: None }
+    }
+}
+
+#[derive(Queryable, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct PostWithComment {
     pub id: i32,
     pub title: String,
     pub published: bool,
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Entity {
+    User,
+    Post,
+    Comment,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Created,
+    Updated,
+    Deleted,
+}
+
+// Entry of the changes log. `data` is the record as it is now rather than as it was written,
+// None once it was deleted, and for the posts not published, whose drafts aren't shown.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Change {
+    pub id: i64,
+    pub entity: Entity,
+    pub entity_id: i32,
+    pub operation: Operation,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+    pub data: Option<Value>,
+}
+
+#[derive(Queryable)]
+struct ChangeRow {
+    id: i64,
+    entity: String,
+    entity_id: i32,
+    operation: String,
+    created_at: NaiveDateTime,
+}
+
+// Events sent to the webhooks
+#[derive(Clone, Copy, Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub enum Event {
+    #[serde(rename = "user.created")]
+    UserCreated,
+    #[serde(rename = "post.published")]
+    PostPublished,
+    #[serde(rename = "comment.created")]
+    CommentCreated,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    // only sent back when the webhook is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub events: Vec<Event>,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable)]
+struct WebhookRow {
+    id: i32,
+    url: String,
+    secret: String,
+    events: String,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Delivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub attempts: i32,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    #[serde(with = "crate::timestamp::option")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
+    pub delivered_at: Option<NaiveDateTime>,
+    #[serde(with = "crate::timestamp::option")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
+    pub failed_at: Option<NaiveDateTime>,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+}
+
+// A delivery waiting for its next attempt, with what is needed to make it
+#[derive(Queryable, Debug)]
+pub struct PendingDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+// Outcome of an attempt at a delivery
+#[derive(Debug)]
+pub enum Attempt {
+    Delivered,
+    Retry { error: String, at: NaiveDateTime },
+    Failed { error: String },
+}
+
+#[derive(Queryable, Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct Follow {
+    pub follower_id: i32,
+    pub followee_id: i32,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ReactionKind {
+    Like,
+    Love,
+    Laugh,
+    Wow,
+    Sad,
+    Angry,
+}
+
+// Number of reactions of each kind, the kinds nobody reacted with are left out
+pub type Reactions = BTreeMap<ReactionKind, i64>;
+
+// A user's reaction to a post or a comment
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct Reaction {
+    pub user_id: i32,
+    pub target: Entity,
+    pub target_id: i32,
+    pub kind: ReactionKind,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+}
+
+// Messages sent through the mail outbox
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    // to the author of a post someone else commented on
+    CommentAdded,
+    // to the subscribers, when a post is first published
+    PostPublished,
+    // to a new subscriber, with the link confirming the address is theirs
+    ConfirmSubscription,
+}
+
+#[derive(Queryable, Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Subscriber {
+    pub id: i32,
+    pub email: String,
+    #[serde(with = "crate::timestamp")]
+    #[schemars(with = "chrono::DateTime<Utc>")]
+    pub created_at: NaiveDateTime,
+    // the posts are only sent once the address is confirmed
+    #[serde(with = "crate::timestamp::option")]
+    #[schemars(with = "Option<chrono::DateTime<Utc>>")]
+    pub confirmed_at: Option<NaiveDateTime>,
+}
+
+// A message waiting for its next attempt, `data` being what its template is rendered with
+#[derive(Queryable, Debug)]
+pub struct PendingMail {
+    pub id: i32,
+    pub recipient: String,
+    pub template: String,
+    pub data: String,
+    pub attempts: i32,
+}
+
+// Shapes returned by the listing queries
+pub type CommentWithAuthor = (Comment, User);
+pub type PostEntry = ((Post, User), Vec<CommentWithAuthor>);
+pub type UserPostEntry = (Post, Vec<CommentWithAuthor>);
+
+// Bumped by triggers on every write to a table, updated_at being in milliseconds since the epoch
+#[derive(Queryable, Debug, Clone, PartialEq)]
+pub struct TableVersion {
+    pub name: String,
+    pub version: i64,
+    pub updated_at: i64,
+}
+
+// What usernames are unique by and looked up with, regardless of case. Lowercased here rather than
+// with SQLite's lower(), which leaves the letters outside ASCII as they are.
+pub fn username_key(username: &str) -> String {
+    username.to_lowercase()
+}
+
 // Static lifetime 'a
+#[derive(Debug)]
 pub enum UserKey<'a> {
     Username(&'a str),
     ID(i32),
 }
 
 // User
-pub fn create_user(conn: &SqliteConnection, username: &str) -> Result<User> {
+#[instrument(skip(conn, email, token_hash))]
+pub fn create_user(conn: &WriteConnection, username: &str, email: Option<&str>, token_hash: &str) -> Result<User> {
+    let conn: &SqliteConnection = conn;
     conn.transaction(|| {
-        diesel::insert_into(users::table)
-            .values(users::username.eq(username))
-            .execute(conn)?;
+        if renamed_from_by_other(conn, username, None)? {
+            return Err(AppError::RecordAlreadyExist);
+        }
+
+        let now = Utc::now().naive_utc();
+        let insert = diesel::insert_into(users::table).values((
+            users::username.eq(username),
+            users::username_key.eq(username_key(username)),
+            users::created_at.eq(now),
+            users::updated_at.eq(now),
+            users::token_hash.eq(token_hash),
+        ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
 
-        users::table
+        let query = users::table
             .order(users::id.desc())
-            .select((users::id, users::username))
-            .first(conn)
-            .map_err(Into::into)
+            .select(USER_COLUMNS)
+            .limit(1);
+        let user: User = sql_span(&query).in_scope(|| query.first(conn))?;
+
+        if let Some(email) = email {
+            let insert = diesel::insert_into(user_emails::table)
+                .values((user_emails::user_id.eq(user.id), user_emails::email.eq(email)));
+            sql_span(&insert).in_scope(|| insert.execute(conn))?;
+        }
+
+        log_change(conn, Entity::User, user.id, Operation::Created)?;
+        enqueue_event(conn, Event::UserCreated, &user)?;
+        Ok(user)
     })
 }
 
-pub fn find_user<'a>(conn: &SqliteConnection, key: UserKey<'a>) -> Result<User> {
+fn grace_start() -> NaiveDateTime {
+    Utc::now().naive_utc() - chrono::Duration::days(RENAME_GRACE_DAYS)
+}
+
+// The user a key refers to. Usernames are matched regardless of case, including the names the
+// users were renamed from during the grace period. A user renamed by fill_username_keys has its
+// old name held by another one, who is found first.
+fn user_by<'a>(key: UserKey<'a>) -> users::BoxedQuery<'a, Sqlite> {
     match key {
-        UserKey::Username(name) => users::table
-            .filter(users::username.eq(name))
-            .select((users::id, users::username))
-            .first::<User>(conn)
-            .map_err(AppError::from),
+        UserKey::Username(name) => {
+            let key = username_key(name);
+            let renamed = username_history::table
+                .filter(username_history::username_key.eq(key.clone()))
+                .filter(username_history::renamed_at.gt(grace_start()))
+                .select(username_history::user_id);
+            users::table
+                .filter(users::username_key.eq(key.clone()).or(users::id.eq_any(renamed)))
+                .order(users::username_key.eq(key).desc())
+                .into_boxed()
+        }
+        UserKey::ID(id) => users::table.filter(users::id.eq(id)).into_boxed(),
+    }
+}
 
-        UserKey::ID(id) => users::table
-            .find(id)
-            .select((users::id, users::username))
-            .first::<User>(conn)
-            .map_err(Into::into),
+// Whether a user other than `user_id` was renamed from the name during the grace period
+fn renamed_from_by_other(conn: &SqliteConnection, username: &str, user_id: Option<i32>) -> Result<bool> {
+    let mut query = username_history::table
+        .filter(username_history::username_key.eq(username_key(username)))
+        .filter(username_history::renamed_at.gt(grace_start()))
+        .select(username_history::id)
+        .into_boxed();
+    if let Some(user_id) = user_id {
+        query = query.filter(username_history::user_id.ne(user_id));
     }
+    let found = sql_span(&query).in_scope(|| query.first::<i32>(conn).optional())?;
+    Ok(found.is_some())
+}
+
+#[instrument(skip(conn))]
+pub fn find_user<'a>(conn: &ReadConnection, key: UserKey<'a>) -> Result<User> {
+    let conn: &SqliteConnection = conn;
+    let query = user_by(key).select(USER_COLUMNS).limit(1);
+    sql_span(&query)
+        .in_scope(|| query.first::<User>(conn))
+        .map_err(Into::into)
+}
+
+#[instrument(skip(conn))]
+pub fn find_profile<'a>(conn: &ReadConnection, key: UserKey<'a>) -> Result<Profile> {
+    let conn: &SqliteConnection = conn;
+    let query = user_by(key).select(PROFILE_COLUMNS).limit(1);
+    sql_span(&query)
+        .in_scope(|| query.first::<Profile>(conn))
+        .map_err(Into::into)
+}
+
+// None for the users created before they were given a token, and for the ones that don't exist,
+// so a token doesn't tell which users do
+#[instrument(skip(conn))]
+pub fn token_hash(conn: &SqliteConnection, user_id: i32) -> Result<Option<String>> {
+    let query = users::table.find(user_id).select(users::token_hash);
+    sql_span(&query)
+        .in_scope(|| query.first::<Option<String>>(conn).optional())
+        .map(Option::flatten)
+        .map_err(Into::into)
+}
+
+// Replaces the token of the user, the previous one stops working
+#[instrument(skip(conn, token_hash))]
+pub fn set_token_hash(conn: &WriteConnection, user_id: i32, token_hash: &str) -> Result<()> {
+    let conn: &SqliteConnection = conn;
+    let update = diesel::update(users::table.find(user_id)).set(users::token_hash.eq(token_hash));
+    match sql_span(&update).in_scope(|| update.execute(conn))? {
+        0 => Err(AppError::RecordNotFound),
+        _ => Ok(()),
+    }
+}
+
+// The hashes of the users' tokens along with their id, for the users who have one
+#[instrument(skip(conn))]
+pub fn token_hashes(conn: &SqliteConnection) -> Result<Vec<(String, i32)>> {
+    let query = users::table
+        .filter(users::token_hash.is_not_null())
+        .select((users::token_hash, users::id));
+    let users: Vec<(Option<String>, i32)> = sql_span(&query).in_scope(|| query.load(conn))?;
+    Ok(users.into_iter().filter_map(|(hash, id)| Some((hash?, id))).collect())
+}
+
+// The old name keeps finding the user for RENAME_GRACE_DAYS
+#[instrument(skip(conn))]
+pub fn rename_user(conn: &WriteConnection, user_id: i32, username: &str) -> Result<Profile> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        let query = users::table.find(user_id).select(users::username);
+        let old: String = sql_span(&query).in_scope(|| query.first(conn))?;
+        if renamed_from_by_other(conn, username, Some(user_id))? {
+            return Err(AppError::RecordAlreadyExist);
+        }
+
+        let now = Utc::now().naive_utc();
+        let key = username_key(username);
+        let update = diesel::update(users::table.find(user_id)).set((
+            users::username.eq(username),
+            users::username_key.eq(&key),
+            users::updated_at.eq(now),
+        ));
+        sql_span(&update).in_scope(|| update.execute(conn))?;
+
+        purge_username_history(conn)?;
+        // taking an old name back, it no longer needs to be kept
+        let delete = diesel::delete(
+            username_history::table
+                .filter(username_history::user_id.eq(user_id))
+                .filter(username_history::username_key.eq(&key)),
+        );
+        sql_span(&delete).in_scope(|| delete.execute(conn))?;
+        // only changing the case, the new name finds the user already
+        if username_key(&old) != key {
+            record_rename(conn, user_id, &old, now)?;
+        }
+
+        let query = users::table.find(user_id).select(PROFILE_COLUMNS);
+        let profile: Profile = sql_span(&query).in_scope(|| query.first(conn))?;
+        log_change(conn, Entity::User, profile.id, Operation::Updated)?;
+        Ok(profile)
+    })
+}
+
+fn record_rename(conn: &SqliteConnection, user_id: i32, old: &str, now: NaiveDateTime) -> Result<()> {
+    let insert = diesel::insert_into(username_history::table).values((
+        username_history::user_id.eq(user_id),
+        username_history::username.eq(old),
+        username_history::username_key.eq(username_key(old)),
+        username_history::renamed_at.eq(now),
+    ));
+    sql_span(&insert).in_scope(|| insert.execute(conn))?;
+    Ok(())
+}
+
+// The names renamed from before the grace period no longer find anybody
+fn purge_username_history(conn: &SqliteConnection) -> Result<usize> {
+    let delete = diesel::delete(username_history::table.filter(username_history::renamed_at.le(grace_start())));
+    sql_span(&delete).in_scope(|| delete.execute(conn)).map_err(Into::into)
+}
+
+// Whether a user other than `user_id` holds the key, or was renamed from it during the grace period
+fn username_key_taken(conn: &SqliteConnection, key: &str, user_id: i32) -> Result<bool> {
+    let query = users::table
+        .filter(users::username_key.eq(key))
+        .filter(users::id.ne(user_id))
+        .select(users::id);
+    let held = sql_span(&query).in_scope(|| query.first::<i32>(conn).optional())?;
+    let renamed = username_history::table
+        .filter(username_history::username_key.eq(key))
+        .filter(username_history::renamed_at.gt(grace_start()))
+        .filter(username_history::user_id.ne(user_id));
+    let query = diesel::select(exists(renamed));
+    let renamed = sql_span(&query).in_scope(|| query.get_result::<bool>(conn))?;
+    Ok(held.is_some() || renamed)
+}
+
+// Gives the users created before usernames had keys theirs, run when the server starts. The oldest
+// user keeps a name, the others clashing with it are renamed to it followed by their id, and a
+// number when that is taken as well. Their old name is recorded like with a rename, the user
+// holding it now being found first. Also purges the names renamed from that expired.
+#[instrument(skip(conn))]
+pub fn fill_username_keys(conn: &SqliteConnection) -> Result<usize> {
+    conn.transaction(|| {
+        purge_username_history(conn)?;
+
+        let query = users::table
+            .filter(users::username_key.is_null())
+            .order(users::id)
+            .select((users::id, users::username));
+        let missing: Vec<(i32, String)> = sql_span(&query).in_scope(|| query.load(conn))?;
+
+        // every name that can be kept is before any renamed user looks for a free one
+        let mut clashing = Vec::new();
+        for (id, username) in &missing {
+            let key = username_key(username);
+            if username_key_taken(conn, &key, *id)? {
+                clashing.push((*id, username));
+                continue;
+            }
+            let update = diesel::update(users::table.find(*id)).set(users::username_key.eq(&key));
+            sql_span(&update).in_scope(|| update.execute(conn))?;
+        }
+
+        let now = Utc::now().naive_utc();
+        for (id, old) in clashing {
+            let mut username = format!("{}-{}", old, id);
+            let mut attempt = 1;
+            while username_key_taken(conn, &username_key(&username), id)? {
+                attempt += 1;
+                username = format!("{}-{}-{}", old, id, attempt);
+            }
+            let update = diesel::update(users::table.find(id)).set((
+                users::username.eq(&username),
+                users::username_key.eq(username_key(&username)),
+                users::updated_at.eq(now),
+            ));
+            sql_span(&update).in_scope(|| update.execute(conn))?;
+            record_rename(conn, id, old, now)?;
+            log_change(conn, Entity::User, id, Operation::Updated)?;
+        }
+        Ok(missing.len())
+    })
+}
+
+// None leaves a field as it is, an empty value clears it
+fn change(field: &Option<String>) -> Option<Option<&str>> {
+    field.as_deref().map(|value| Some(value).filter(|value| !value.is_empty()))
+}
+
+#[instrument(skip(conn, changes))]
+pub fn update_profile(conn: &WriteConnection, user_id: i32, changes: &ProfileChanges) -> Result<Profile> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        let update = diesel::update(users::table.find(user_id)).set((
+            change(&changes.display_name).map(|value| users::display_name.eq(value)),
+            change(&changes.bio).map(|value| users::bio.eq(value)),
+            change(&changes.website).map(|value| users::website.eq(value)),
+            change(&changes.avatar_url).map(|value| users::avatar_url.eq(value)),
+            users::updated_at.eq(Utc::now().naive_utc()),
+        ));
+        if sql_span(&update).in_scope(|| update.execute(conn))? == 0 {
+            return Err(AppError::RecordNotFound);
+        }
+
+        let query = users::table.find(user_id).select(PROFILE_COLUMNS);
+        let profile: Profile = sql_span(&query).in_scope(|| query.first(conn))?;
+        log_change(conn, Entity::User, profile.id, Operation::Updated)?;
+        Ok(profile)
+    })
 }
 
 // Post
-pub fn create_post(conn: &SqliteConnection, user: &User, title: &str, body: &str) -> Result<Post> {
+#[instrument(skip(conn))]
+pub fn find_post(conn: &ReadConnection, post_id: i32) -> Result<Post> {
+    let conn: &SqliteConnection = conn;
+    let query = posts::table
+        .find(post_id)
+        .select(posts::all_columns)
+        .limit(1);
+    sql_span(&query)
+        .in_scope(|| query.first::<Post>(conn))
+        .map_err(Into::into)
+}
+
+#[instrument(skip(conn, body))]
+pub fn create_post(conn: &WriteConnection, user: &User, title: &str, body: &str) -> Result<Post> {
+    let conn: &SqliteConnection = conn;
     conn.transaction(|| {
-        diesel::insert_into(posts::table)
+        let now = Utc::now().naive_utc();
+        let insert = diesel::insert_into(posts::table)
             .values((
                 posts::user_id.eq(user.id),
                 posts::title.eq(title),
-                posts::body.eq(body)
-            ))
-            .execute(conn)?;
+                posts::body.eq(body),
+                posts::created_at.eq(now),
+                posts::updated_at.eq(now),
+            ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
 
-        posts::table
+        let query = posts::table
             .order(posts::id.desc())
             .select(posts::all_columns)
-            .first(conn)
-            .map_err(Into::into)
+            .limit(1);
+        let post: Post = sql_span(&query).in_scope(|| query.first(conn))?;
+
+        log_change(conn, Entity::Post, post.id, Operation::Created)?;
+        Ok(post)
     })
 }
 
-pub fn publish_post(conn: &SqliteConnection, post_id: i32) -> Result<Post> {
+// The post, and whether it wasn't published before
+#[instrument(skip(conn))]
+pub fn publish_post(conn: &WriteConnection, post_id: i32) -> Result<(Post, bool)> {
+    let conn: &SqliteConnection = conn;
     conn.transaction(|| {
-        diesel::update(posts::table.filter(posts::id.eq(post_id)))
-            .set(posts::published.eq(true))
-            .execute(conn)?;
+        let now = Utc::now().naive_utc();
+        let update = diesel::update(posts::table.filter(posts::id.eq(post_id)))
+            .set((posts::published.eq(true), posts::updated_at.eq(now)));
+        sql_span(&update).in_scope(|| update.execute(conn))?;
+
+        // publishing again keeps the time the post was first published
+        let update = diesel::update(
+            posts::table
+                .filter(posts::id.eq(post_id))
+                .filter(posts::published_at.is_null()),
+        )
+            .set(posts::published_at.eq(now));
+        let first_published = sql_span(&update).in_scope(|| update.execute(conn))? > 0;
 
-        posts::table
+        let query = posts::table
             .find(post_id)
             .select(posts::all_columns)
-            .first(conn)
-            .map_err(Into::into)
+            .limit(1);
+        let post: Post = sql_span(&query).in_scope(|| query.first(conn))?;
+
+        log_change(conn, Entity::Post, post.id, Operation::Updated)?;
+        if first_published {
+            enqueue_event(conn, Event::PostPublished, &post)?;
+            notify_subscribers(conn, &post)?;
+        }
+        Ok((post, first_published))
     })
 }
 
-pub fn all_posts(conn: &SqliteConnection) -> Result<Vec<((Post, User), Vec<(Comment, User)>)>> {
+#[instrument(skip(conn))]
+pub fn all_posts(conn: &ReadConnection) -> Result<Vec<PostEntry>> {
+    let conn: &SqliteConnection = conn;
     let query = posts::table
         .order(posts::id.desc())
         .filter(posts::published.eq(true))
         .inner_join(users::table)
-        .select((posts::all_columns, (users::id, users::username)));
+        .select((posts::all_columns, USER_COLUMNS));
 
-    let posts_with_user = query.load::<(Post, User)>(conn)?;
-    let (posts, post_users): (Vec<_>, Vec<_>) = posts_with_user.into_iter().unzip();
+    let posts_with_user = sql_span(&query).in_scope(|| query.load::<(Post, User)>(conn))?;
+    with_comments(conn, posts_with_user)
+}
 
-    let comments = Comment::belonging_to(&posts)
+// The listing entries of the posts, with the comments loaded in one more query
+fn with_comments(conn: &SqliteConnection, posts_with_user: Vec<(Post, User)>) -> Result<Vec<PostEntry>> {
+    let (mut posts, post_users): (Vec<_>, Vec<_>) = posts_with_user.into_iter().unzip();
+    count_reactions(conn, &mut posts)?;
+
+    let query = Comment::belonging_to(&posts)
         .inner_join(users::table)
-        .select((comments::all_columns, (users::id, users::username)))
-        .load::<(Comment, User)>(conn)?
-        .grouped_by(&posts);
+        .select((comments::all_columns, USER_COLUMNS));
+    let mut comments = sql_span(&query).in_scope(|| query.load::<(Comment, User)>(conn))?;
+    count_reactions(conn, comments.iter_mut().map(|(comment, _)| comment))?;
+    let comments = comments.grouped_by(&posts);
 
     Ok(posts.into_iter().zip(post_users).zip(comments).collect())
 }
 
-pub fn users_posts(conn: &SqliteConnection, user_id: i32) -> Result<Vec<(Post, Vec<(Comment, User)>)>> {
-    let posts = posts::table
+// Published posts of the authors the user follows, newest first, from the post before `before`
+#[instrument(skip(conn))]
+pub fn feed(conn: &ReadConnection, user_id: i32, before: Option<i32>, limit: i64) -> Result<Vec<PostEntry>> {
+    let conn: &SqliteConnection = conn;
+    let followed = follows::table
+        .filter(follows::follower_id.eq(user_id))
+        .select(follows::followee_id);
+    let query = posts::table
+        .order(posts::id.desc())
+        .filter(posts::published.eq(true))
+        .filter(posts::user_id.eq_any(followed))
+        .filter(posts::id.lt(before.unwrap_or(i32::MAX)))
+        .inner_join(users::table)
+        .select((posts::all_columns, USER_COLUMNS))
+        .limit(limit);
+
+    let posts_with_user = sql_span(&query).in_scope(|| query.load::<(Post, User)>(conn))?;
+    with_comments(conn, posts_with_user)
+}
+
+#[instrument(skip(conn))]
+pub fn users_posts(conn: &ReadConnection, user_id: i32) -> Result<Vec<UserPostEntry>> {
+    let conn: &SqliteConnection = conn;
+    let query = posts::table
         .filter(posts::user_id.eq(user_id))
         .order(posts::id.desc())
-        .select(posts::all_columns)
-        .load::<Post>(conn)?;
+        .select(posts::all_columns);
+    let mut posts = sql_span(&query).in_scope(|| query.load::<Post>(conn))?;
+    count_reactions(conn, &mut posts)?;
 
-    let comments = Comment::belonging_to(&posts)
+    let query = Comment::belonging_to(&posts)
         .inner_join(users::table)
-        .select((comments::all_columns, (users::id, users::username)))
-        .load::<(Comment, User)>(conn)?
-        .grouped_by(&posts);
+        .select((comments::all_columns, USER_COLUMNS));
+    let mut comments = sql_span(&query).in_scope(|| query.load::<(Comment, User)>(conn))?;
+    count_reactions(conn, comments.iter_mut().map(|(comment, _)| comment))?;
+    let comments = comments.grouped_by(&posts);
 
     Ok(posts.into_iter().zip(comments).collect())
 }
 
+// Follows
+#[instrument(skip(conn))]
+pub fn follow(conn: &WriteConnection, follower_id: i32, followee_id: i32) -> Result<Follow> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        let insert = diesel::insert_into(follows::table).values((
+            follows::follower_id.eq(follower_id),
+            follows::followee_id.eq(followee_id),
+            follows::created_at.eq(Utc::now().naive_utc()),
+        ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
+
+        let query = follows::table.find((follower_id, followee_id));
+        sql_span(&query)
+            .in_scope(|| query.first(conn))
+            .map_err(Into::into)
+    })
+}
+
+#[instrument(skip(conn))]
+pub fn unfollow(conn: &WriteConnection, follower_id: i32, followee_id: i32) -> Result<()> {
+    let conn: &SqliteConnection = conn;
+    let delete = diesel::delete(follows::table.find((follower_id, followee_id)));
+    match sql_span(&delete).in_scope(|| delete.execute(conn))? {
+        0 => Err(AppError::RecordNotFound),
+        _ => Ok(()),
+    }
+}
+
+// The users followed by the user, most recently followed first
+#[instrument(skip(conn))]
+pub fn following(conn: &ReadConnection, user_id: i32) -> Result<Vec<User>> {
+    let conn: &SqliteConnection = conn;
+    let query = follows::table
+        .inner_join(users::table.on(users::id.eq(follows::followee_id)))
+        .filter(follows::follower_id.eq(user_id))
+        .order(follows::created_at.desc())
+        .select(USER_COLUMNS);
+    sql_span(&query)
+        .in_scope(|| query.load(conn))
+        .map_err(Into::into)
+}
+
+#[instrument(skip(conn))]
+pub fn followers(conn: &ReadConnection, user_id: i32) -> Result<Vec<User>> {
+    let conn: &SqliteConnection = conn;
+    let query = follows::table
+        .inner_join(users::table.on(users::id.eq(follows::follower_id)))
+        .filter(follows::followee_id.eq(user_id))
+        .order(follows::created_at.desc())
+        .select(USER_COLUMNS);
+    sql_span(&query)
+        .in_scope(|| query.load(conn))
+        .map_err(Into::into)
+}
+
 // Comment
-pub fn create_comment(conn: &SqliteConnection, user_id: i32, post_id: i32, body: &str) -> Result<Comment> {
+#[instrument(skip(conn, body))]
+pub fn create_comment(conn: &WriteConnection, user_id: i32, post_id: i32, body: &str) -> Result<Comment> {
+    let conn: &SqliteConnection = conn;
     conn.transaction(|| {
-        diesel::insert_into(comments::table)
+        let now = Utc::now().naive_utc();
+        let insert = diesel::insert_into(comments::table)
             .values((
                 comments::user_id.eq(user_id),
                 comments::post_id.eq(post_id),
-                comments::body.eq(body)
-            ))
-            .execute(conn)?;
+                comments::body.eq(body),
+                comments::created_at.eq(now),
+                comments::updated_at.eq(now),
+            ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
 
-        comments::table
+        let query = comments::table
             .order(comments::id.desc())
             .select(comments::all_columns)
-            .first(conn)
-            .map_err(Into::into)
+            .limit(1);
+        let comment: Comment = sql_span(&query).in_scope(|| query.first(conn))?;
+
+        log_change(conn, Entity::Comment, comment.id, Operation::Created)?;
+        enqueue_event(conn, Event::CommentCreated, &comment)?;
+        notify_author(conn, &comment)?;
+        Ok(comment)
     })
 }
 
-pub fn post_comments(conn: &SqliteConnection, post_id: i32) -> Result<Vec<(Comment, User)>> {
-    comments::table
+#[instrument(skip(conn))]
+pub fn post_comments(conn: &ReadConnection, post_id: i32) -> Result<Vec<CommentWithAuthor>> {
+    let conn: &SqliteConnection = conn;
+    let query = comments::table
         .filter(comments::post_id.eq(post_id))
         .inner_join(users::table)
-        .select((comments::all_columns, (users::id, users::username)))
-        .order(comments::id.desc())
-        .load::<(Comment, User)>(conn)
-        .map_err(Into::into)
+        .select((comments::all_columns, USER_COLUMNS))
+        .order(comments::id.desc());
+    let mut comments = sql_span(&query).in_scope(|| query.load::<(Comment, User)>(conn))?;
+    count_reactions(conn, comments.iter_mut().map(|(comment, _)| comment))?;
+    Ok(comments)
 }
 
-pub fn user_comments(conn: &SqliteConnection, user_id: i32)
+#[instrument(skip(conn))]
+pub fn user_comments(conn: &ReadConnection, user_id: i32)
                      -> Result<Vec<(Comment, PostWithComment)>> {
-    comments::table
+    let conn: &SqliteConnection = conn;
+    let query = comments::table
         .filter(comments::user_id.eq(user_id))
         .inner_join(posts::table)
         .select((
             comments::all_columns,
             (posts::id, posts::title, posts::published)
+        ));
+    sql_span(&query)
+        .in_scope(|| query.load::<(Comment, PostWithComment)>(conn))
+        .map_err(Into::into)
+}
+// Reactions
+impl ReactionKind {
+    pub const ALL: [ReactionKind; 6] = [
+        ReactionKind::Like,
+        ReactionKind::Love,
+        ReactionKind::Laugh,
+        ReactionKind::Wow,
+        ReactionKind::Sad,
+        ReactionKind::Angry,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReactionKind::Like => "like",
+            ReactionKind::Love => "love",
+            ReactionKind::Laugh => "laugh",
+            ReactionKind::Wow => "wow",
+            ReactionKind::Sad => "sad",
+            ReactionKind::Angry => "angry",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        ReactionKind::ALL.iter().copied().find(|kind| kind.as_str() == s)
+    }
+}
+
+// Posts and comments, which carry their reaction counts in the listings
+trait Reactable {
+    const TARGET: Entity;
+
+    fn id(&self) -> i32;
+    fn set_reactions(&mut self, reactions: Reactions);
+}
+
+impl Reactable for Post {
+    const TARGET: Entity = Entity::Post;
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_reactions(&mut self, reactions: Reactions) {
+        self.reactions = Some(reactions);
+    }
+}
+
+impl Reactable for Comment {
+    const TARGET: Entity = Entity::Comment;
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_reactions(&mut self, reactions: Reactions) {
+        self.reactions = Some(reactions);
+    }
+}
+
+// Fills in the reaction counts of the records with one query for all of them
+fn count_reactions<'a, T, I>(conn: &SqliteConnection, records: I) -> Result<()>
+    where
+        T: Reactable + 'a,
+        I: IntoIterator<Item=&'a mut T>,
+{
+    let records: Vec<&mut T> = records.into_iter().collect();
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let ids: Vec<i32> = records.iter().map(|record| record.id()).collect();
+    let query = reactions::table
+        .filter(reactions::target.eq(T::TARGET.as_str()))
+        .filter(reactions::target_id.eq_any(ids))
+        .group_by((reactions::target_id, reactions::kind))
+        // count_star() can't be selected next to the grouped columns in diesel 1.4
+        .select((reactions::target_id, reactions::kind, sql::<BigInt>("COUNT(*)")));
+    let rows = sql_span(&query).in_scope(|| query.load::<(i32, String, i64)>(conn))?;
+
+    let mut counts: HashMap<i32, Reactions> = HashMap::new();
+    for (target_id, kind, count) in rows {
+        if let Some(kind) = ReactionKind::parse(&kind) {
+            counts.entry(target_id).or_default().insert(kind, count);
+        }
+    }
+    for record in records {
+        let reactions = counts.remove(&record.id()).unwrap_or_default();
+        record.set_reactions(reactions);
+    }
+    Ok(())
+}
+
+fn target_exists(conn: &SqliteConnection, target: Entity, target_id: i32) -> Result<bool> {
+    let query = match target {
+        Entity::Post => diesel::select(exists(posts::table.find(target_id))).into_boxed(),
+        Entity::Comment => diesel::select(exists(comments::table.find(target_id))).into_boxed(),
+        Entity::User => return Ok(false),
+    };
+    sql_span(&query)
+        .in_scope(|| query.get_result(conn))
+        .map_err(Into::into)
+}
+
+// `target` is a post or a comment, reacting twice with the same kind is a RecordAlreadyExist
+#[instrument(skip(conn))]
+pub fn react(conn: &WriteConnection, user_id: i32, target: Entity, target_id: i32, kind: ReactionKind) -> Result<Reaction> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        if !target_exists(conn, target, target_id)? {
+            return Err(AppError::RecordNotFound);
+        }
+
+        let reaction = Reaction {
+            user_id,
+            target,
+            target_id,
+            kind,
+            created_at: Utc::now().naive_utc(),
+        };
+        let insert = diesel::insert_into(reactions::table).values((
+            reactions::target.eq(target.as_str()),
+            reactions::target_id.eq(target_id),
+            reactions::kind.eq(kind.as_str()),
+            reactions::user_id.eq(user_id),
+            reactions::created_at.eq(reaction.created_at),
+        ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
+        Ok(reaction)
+    })
+}
+
+#[instrument(skip(conn))]
+pub fn unreact(conn: &WriteConnection, user_id: i32, target: Entity, target_id: i32, kind: ReactionKind) -> Result<()> {
+    let conn: &SqliteConnection = conn;
+    let delete = diesel::delete(reactions::table.find((target.as_str(), target_id, kind.as_str(), user_id)));
+    match sql_span(&delete).in_scope(|| delete.execute(conn))? {
+        0 => Err(AppError::RecordNotFound),
+        _ => Ok(()),
+    }
+}
+
+// Table versions
+#[instrument(skip(conn))]
+pub fn table_versions(conn: &ReadConnection, tables: &[&str]) -> Result<Vec<TableVersion>> {
+    let conn: &SqliteConnection = conn;
+    let query = table_versions::table
+        .filter(table_versions::name.eq_any(tables.to_vec()))
+        .order(table_versions::name);
+    sql_span(&query)
+        .in_scope(|| query.load(conn))
+        .map_err(Into::into)
+}
+
+// Changes
+impl Entity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Entity::User => "user",
+            Entity::Post => "post",
+            Entity::Comment => "comment",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        [Entity::User, Entity::Post, Entity::Comment]
+            .iter()
+            .copied()
+            .find(|entity| entity.as_str() == s)
+    }
+}
+
+impl Operation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Operation::Created => "created",
+            Operation::Updated => "updated",
+            Operation::Deleted => "deleted",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        [Operation::Created, Operation::Updated, Operation::Deleted]
+            .iter()
+            .copied()
+            .find(|operation| operation.as_str() == s)
+    }
+}
+
+// Called in the transaction of the write, so the log never misses a change nor reports one that
+// was rolled back. Writes are serialized by SQLite, ids are handed out in commit order.
+fn log_change(conn: &SqliteConnection, entity: Entity, entity_id: i32, operation: Operation) -> Result<()> {
+    let insert = diesel::insert_into(changes::table).values((
+        changes::entity.eq(entity.as_str()),
+        changes::entity_id.eq(entity_id),
+        changes::operation.eq(operation.as_str()),
+        changes::created_at.eq(Utc::now().naive_utc()),
+    ));
+    sql_span(&insert).in_scope(|| insert.execute(conn))?;
+    Ok(())
+}
+
+// Current state of the records, by id
+fn by_id<T: serde::Serialize>(records: Vec<T>, id: fn(&T) -> i32) -> HashMap<i32, Value> {
+    records
+        .into_iter()
+        .map(|record| (id(&record), serde_json::to_value(&record).unwrap_or(Value::Null)))
+        .collect()
+}
+
+#[instrument(skip(conn))]
+pub fn changes_since(conn: &ReadConnection, since: i64, limit: i64) -> Result<Vec<Change>> {
+    let conn: &SqliteConnection = conn;
+    let query = changes::table
+        .filter(changes::id.gt(since))
+        .order(changes::id)
+        .limit(limit);
+    let rows = sql_span(&query).in_scope(|| query.load::<ChangeRow>(conn))?;
+
+    let ids = |entity: Entity| -> Vec<i32> {
+        rows.iter()
+            .filter(|row| row.entity == entity.as_str())
+            .map(|row| row.entity_id)
+            .collect()
+    };
+    let query = users::table
+        .filter(users::id.eq_any(ids(Entity::User)))
+        .select(PROFILE_COLUMNS);
+    let users = by_id(sql_span(&query).in_scope(|| query.load::<Profile>(conn))?, |user| user.id);
+    let query = posts::table
+        .filter(posts::id.eq_any(ids(Entity::Post)))
+        .filter(posts::published.eq(true));
+    let posts = by_id(sql_span(&query).in_scope(|| query.load::<Post>(conn))?, |post| post.id);
+    let query = comments::table.filter(comments::id.eq_any(ids(Entity::Comment)));
+    let comments = by_id(sql_span(&query).in_scope(|| query.load::<Comment>(conn))?, |comment| comment.id);
+
+    rows.into_iter()
+        .map(|row| {
+            let (entity, operation) = match (Entity::parse(&row.entity), Operation::parse(&row.operation)) {
+                (Some(entity), Some(operation)) => (entity, operation),
+                _ => {
+                    let e = format!("unknown change {} {}", row.operation, row.entity);
+                    return Err(AppError::DatabaseError(diesel::result::Error::DeserializationError(e.into())));
+                }
+            };
+            let records = match entity {
+                Entity::User => &users,
+                Entity::Post => &posts,
+                Entity::Comment => &comments,
+            };
+            Ok(Change {
+                id: row.id,
+                entity,
+                entity_id: row.entity_id,
+                operation,
+                created_at: row.created_at,
+                data: records.get(&row.entity_id).cloned(),
+            })
+        })
+        .collect()
+}
+
+// Webhooks
+impl Event {
+    pub const ALL: [Event; 3] = [Event::UserCreated, Event::PostPublished, Event::CommentCreated];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::UserCreated => "user.created",
+            Event::PostPublished => "post.published",
+            Event::CommentCreated => "comment.created",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Event::ALL.iter().copied().find(|event| event.as_str() == s)
+    }
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            url: row.url,
+            secret: Some(row.secret),
+            events: row.events.split(',').filter_map(Event::parse).collect(),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a, T> {
+    event: Event,
+    #[serde(with = "crate::timestamp")]
+    created_at: NaiveDateTime,
+    data: &'a T,
+}
+
+// Queues a delivery of the event to every webhook subscribed to it, in the transaction of the
+// write that caused it so the event is sent if and only if the write is committed
+fn enqueue_event<T: serde::Serialize>(conn: &SqliteConnection, event: Event, data: &T) -> Result<()> {
+    let query = webhooks::table.select((webhooks::id, webhooks::events));
+    let subscribed: Vec<i32> = sql_span(&query)
+        .in_scope(|| query.load::<(i32, String)>(conn))?
+        .into_iter()
+        .filter(|(_, events)| events.split(',').any(|name| name == event.as_str()))
+        .map(|(id, _)| id)
+        .collect();
+    if subscribed.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now().naive_utc();
+    let payload = serde_json::to_string(&Payload { event, created_at: now, data })
+        .map_err(|e| AppError::DatabaseError(diesel::result::Error::SerializationError(e.into())))?;
+    for webhook_id in subscribed {
+        let insert = diesel::insert_into(webhook_deliveries::table).values((
+            webhook_deliveries::webhook_id.eq(webhook_id),
+            webhook_deliveries::event.eq(event.as_str()),
+            webhook_deliveries::payload.eq(payload.as_str()),
+            webhook_deliveries::next_attempt_at.eq(now),
+            webhook_deliveries::created_at.eq(now),
+        ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
+    }
+    Ok(())
+}
+
+#[instrument(skip(conn, secret))]
+pub fn create_webhook(conn: &WriteConnection, url: &str, secret: &str, events: &[Event]) -> Result<Webhook> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        let events: Vec<_> = events.iter().map(|event| event.as_str()).collect();
+        let insert = diesel::insert_into(webhooks::table).values((
+            webhooks::url.eq(url),
+            webhooks::secret.eq(secret),
+            webhooks::events.eq(events.join(",")),
+            webhooks::created_at.eq(Utc::now().naive_utc()),
+        ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
+
+        let query = webhooks::table.order(webhooks::id.desc()).limit(1);
+        let row: WebhookRow = sql_span(&query).in_scope(|| query.first(conn))?;
+        Ok(row.into())
+    })
+}
+
+#[instrument(skip(conn))]
+pub fn webhooks(conn: &ReadConnection) -> Result<Vec<Webhook>> {
+    let conn: &SqliteConnection = conn;
+    let query = webhooks::table.order(webhooks::id);
+    let rows = sql_span(&query).in_scope(|| query.load::<WebhookRow>(conn))?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Webhook {
+            secret: None,
+            ..row.into()
+        })
+        .collect())
+}
+
+// Its pending deliveries are dropped with it
+#[instrument(skip(conn))]
+pub fn delete_webhook(conn: &WriteConnection, webhook_id: i32) -> Result<()> {
+    let conn: &SqliteConnection = conn;
+    let delete = diesel::delete(webhooks::table.find(webhook_id));
+    match sql_span(&delete).in_scope(|| delete.execute(conn))? {
+        0 => Err(AppError::RecordNotFound),
+        _ => Ok(()),
+    }
+}
+
+#[instrument(skip(conn))]
+pub fn webhook_deliveries(conn: &ReadConnection, webhook_id: i32) -> Result<Vec<Delivery>> {
+    let conn: &SqliteConnection = conn;
+    let query = webhook_deliveries::table
+        .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+        .select((
+            webhook_deliveries::id,
+            webhook_deliveries::webhook_id,
+            webhook_deliveries::event,
+            webhook_deliveries::attempts,
+            webhook_deliveries::next_attempt_at,
+            webhook_deliveries::last_error,
+            webhook_deliveries::delivered_at,
+            webhook_deliveries::failed_at,
+            webhook_deliveries::created_at,
+        ))
+        .order(webhook_deliveries::id.desc())
+        .limit(100);
+    sql_span(&query)
+        .in_scope(|| query.load(conn))
+        .map_err(Into::into)
+}
+
+#[instrument(skip(conn))]
+pub fn pending_deliveries(conn: &ReadConnection, limit: i64) -> Result<Vec<PendingDelivery>> {
+    let conn: &SqliteConnection = conn;
+    let now = Utc::now().naive_utc();
+    let query = webhook_deliveries::table
+        .inner_join(webhooks::table)
+        .filter(webhook_deliveries::delivered_at.is_null())
+        .filter(webhook_deliveries::failed_at.is_null())
+        .filter(webhook_deliveries::next_attempt_at.le(now))
+        // the deliveries after one waiting to be retried wait too, so they stay in order
+        .filter(
+            sql::<Bool>(
+                "NOT EXISTS (SELECT 1 FROM webhook_deliveries AS earlier \
+                 WHERE earlier.webhook_id = webhook_deliveries.webhook_id \
+                 AND earlier.id < webhook_deliveries.id \
+                 AND earlier.delivered_at IS NULL AND earlier.failed_at IS NULL \
+                 AND earlier.next_attempt_at > ",
+            )
+            .bind::<Timestamp, _>(now)
+            .sql(")"),
+        )
+        .select((
+            webhook_deliveries::id,
+            webhook_deliveries::webhook_id,
+            webhook_deliveries::event,
+            webhook_deliveries::payload,
+            webhook_deliveries::attempts,
+            webhooks::url,
+            webhooks::secret,
+        ))
+        .order(webhook_deliveries::id)
+        .limit(limit);
+    sql_span(&query)
+        .in_scope(|| query.load(conn))
+        .map_err(Into::into)
+}
+
+#[instrument(skip(conn))]
+pub fn record_attempt(conn: &WriteConnection, delivery_id: i32, attempt: &Attempt) -> Result<()> {
+    let conn: &SqliteConnection = conn;
+    retry::record_attempt!(conn, webhook_deliveries, delivered_at, delivery_id, attempt)
+}
+
+// Mail
+impl Template {
+    pub const ALL: [Template; 3] = [Template::CommentAdded, Template::PostPublished, Template::ConfirmSubscription];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Template::CommentAdded => "comment_added",
+            Template::PostPublished => "post_published",
+            Template::ConfirmSubscription => "confirm_subscription",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Template::ALL.iter().copied().find(|template| template.as_str() == s)
+    }
+}
+
+// Like enqueue_event, the message is only sent if the write causing it is committed
+fn enqueue_mail(conn: &SqliteConnection, recipient: &str, template: Template, data: &Value) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let insert = diesel::insert_into(outbox::table).values((
+        outbox::recipient.eq(recipient),
+        outbox::template.eq(template.as_str()),
+        outbox::data.eq(data.to_string()),
+        outbox::next_attempt_at.eq(now),
+        outbox::created_at.eq(now),
+    ));
+    sql_span(&insert).in_scope(|| insert.execute(conn))?;
+    Ok(())
+}
+
+// Authors aren't told about their own comments, nor when they left no address
+fn notify_author(conn: &SqliteConnection, comment: &Comment) -> Result<()> {
+    let query = posts::table
+        .find(comment.post_id)
+        .inner_join(user_emails::table.on(user_emails::user_id.eq(posts::user_id)))
+        .select((posts::user_id, posts::title, user_emails::email));
+    let (_, title, email) = match sql_span(&query)
+        .in_scope(|| query.first::<(i32, String, String)>(conn).optional())?
+    {
+        Some(author) if author.0 != comment.user_id => author,
+        _ => return Ok(()),
+    };
+
+    let query = users::table.find(comment.user_id).select(users::username);
+    let commenter: String = sql_span(&query).in_scope(|| query.first(conn))?;
+    let data = json!({
+        "post_id": comment.post_id,
+        "title": title,
+        "commenter": commenter,
+        "body": comment.body,
+    });
+    enqueue_mail(conn, &email, Template::CommentAdded, &data)
+}
+
+fn notify_subscribers(conn: &SqliteConnection, post: &Post) -> Result<()> {
+    let query = subscribers::table
+        .filter(subscribers::confirmed_at.is_not_null())
+        .select((subscribers::email, subscribers::token));
+    let subscribers = sql_span(&query).in_scope(|| query.load::<(String, String)>(conn))?;
+    if subscribers.is_empty() {
+        return Ok(());
+    }
+
+    let query = users::table.find(post.user_id).select(users::username);
+    let author: String = sql_span(&query).in_scope(|| query.first(conn))?;
+    for (email, token) in subscribers {
+        let data = json!({
+            "post_id": post.id,
+            "title": post.title,
+            "author": author,
+            "body": post.body,
+            "token": token,
+        });
+        enqueue_mail(conn, &email, Template::PostPublished, &data)?;
+    }
+    Ok(())
+}
+
+const SUBSCRIBER_COLUMNS: (
+    subscribers::id,
+    subscribers::email,
+    subscribers::created_at,
+    subscribers::confirmed_at,
+) = (
+    subscribers::id,
+    subscribers::email,
+    subscribers::created_at,
+    subscribers::confirmed_at,
+);
+
+// The subscriber is only sent the posts once they confirmed with the link of the message queued
+// along
+#[instrument(skip(conn, email, token))]
+pub fn create_subscriber(conn: &WriteConnection, email: &str, token: &str) -> Result<Subscriber> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        let insert = diesel::insert_into(subscribers::table).values((
+            subscribers::email.eq(email),
+            subscribers::token.eq(token),
+            subscribers::created_at.eq(Utc::now().naive_utc()),
+        ));
+        sql_span(&insert).in_scope(|| insert.execute(conn))?;
+        enqueue_mail(conn, email, Template::ConfirmSubscription, &json!({ "token": token }))?;
+
+        let query = subscribers::table
+            .order(subscribers::id.desc())
+            .select(SUBSCRIBER_COLUMNS)
+            .limit(1);
+        sql_span(&query)
+            .in_scope(|| query.first(conn))
+            .map_err(Into::into)
+    })
+}
+
+// Confirming again keeps the time of the first confirmation
+#[instrument(skip(conn, token))]
+pub fn confirm_subscriber(conn: &WriteConnection, token: &str) -> Result<Subscriber> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        let update = diesel::update(
+            subscribers::table
+                .filter(subscribers::token.eq(token))
+                .filter(subscribers::confirmed_at.is_null()),
+        )
+            .set(subscribers::confirmed_at.eq(Utc::now().naive_utc()));
+        sql_span(&update).in_scope(|| update.execute(conn))?;
+
+        let query = subscribers::table
+            .filter(subscribers::token.eq(token))
+            .select(SUBSCRIBER_COLUMNS);
+        sql_span(&query)
+            .in_scope(|| query.first(conn))
+            .map_err(Into::into)
+    })
+}
+
+// The messages still queued for the subscriber aren't sent, the other mail to the address, about
+// the comments on the posts of a user having it, is
+#[instrument(skip(conn, token))]
+pub fn delete_subscriber(conn: &WriteConnection, token: &str) -> Result<()> {
+    let conn: &SqliteConnection = conn;
+    conn.transaction(|| {
+        let query = subscribers::table
+            .filter(subscribers::token.eq(token))
+            .select(subscribers::email);
+        let email: String = match sql_span(&query).in_scope(|| query.first(conn).optional())? {
+            Some(email) => email,
+            None => return Err(AppError::RecordNotFound),
+        };
+        let delete = diesel::delete(subscribers::table.filter(subscribers::token.eq(token)));
+        sql_span(&delete).in_scope(|| delete.execute(conn))?;
+
+        let templates = [Template::PostPublished.as_str(), Template::ConfirmSubscription.as_str()];
+        let delete = diesel::delete(
+            outbox::table
+                .filter(outbox::recipient.eq(&email))
+                .filter(outbox::template.eq_any(&templates[..]))
+                .filter(outbox::sent_at.is_null())
+                .filter(outbox::failed_at.is_null()),
+        );
+        sql_span(&delete).in_scope(|| delete.execute(conn))?;
+        Ok(())
+    })
+}
+
+#[instrument(skip(conn))]
+pub fn pending_mail(conn: &ReadConnection, limit: i64) -> Result<Vec<PendingMail>> {
+    let conn: &SqliteConnection = conn;
+    let query = outbox::table
+        .filter(outbox::sent_at.is_null())
+        .filter(outbox::failed_at.is_null())
+        .filter(outbox::next_attempt_at.le(Utc::now().naive_utc()))
+        .select((
+            outbox::id,
+            outbox::recipient,
+            outbox::template,
+            outbox::data,
+            outbox::attempts,
         ))
-        .load::<(Comment, PostWithComment)>(conn)
+        .order(outbox::id)
+        .limit(limit);
+    sql_span(&query)
+        .in_scope(|| query.load(conn))
         .map_err(Into::into)
-}
\ No newline at end of file
+}
+
+#[instrument(skip(conn))]
+pub fn record_send(conn: &WriteConnection, mail_id: i32, attempt: &Attempt) -> Result<()> {
+    let conn: &SqliteConnection = conn;
+    retry::record_attempt!(conn, outbox, sent_at, mail_id, attempt)
+}
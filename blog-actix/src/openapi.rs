@@ -0,0 +1,329 @@
+use crate::errors::ErrorResponse;
+use crate::routes;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{Schema, SchemaObject, SingleOrVec};
+use schemars::visit::{visit_schema_object, Visitor};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+// Builds the OpenAPI document from the descriptions each routes module keeps next to its
+// `configure` function.
+pub fn document() -> Value {
+    let mut spec = Spec::new();
+    routes::users::openapi(&mut spec);
+    routes::follows::openapi(&mut spec);
+    routes::posts::openapi(&mut spec);
+    routes::comments::openapi(&mut spec);
+    routes::reactions::openapi(&mut spec);
+    routes::changes::openapi(&mut spec);
+    routes::streams::openapi(&mut spec);
+    routes::subscribers::openapi(&mut spec);
+    routes::admin::openapi(&mut spec);
+    routes::webhooks::openapi(&mut spec);
+    routes::health::openapi(&mut spec);
+    spec.into_document()
+}
+
+pub struct Spec {
+    gen: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+pub struct PathItem<'a> {
+    spec: &'a mut Spec,
+    path: &'static str,
+    params: Vec<Value>,
+    bearer_auth: bool,
+    conditional: bool,
+}
+
+// The handlers return tuples, which OpenAPI 3.1 describes with `prefixItems` while schemars
+// still emits the older `items: [...]` form.
+#[derive(Debug, Clone)]
+struct TuplesAsPrefixItems;
+
+impl Visitor for TuplesAsPrefixItems {
+    fn visit_schema_object(&mut self, schema: &mut SchemaObject) {
+        visit_schema_object(self, schema);
+
+        if let Some(array) = &mut schema.array {
+            if let Some(SingleOrVec::Vec(items)) = &array.items {
+                schema
+                    .extensions
+                    .insert("prefixItems".to_owned(), json!(items));
+                array.items = Some(SingleOrVec::Single(Box::new(Schema::Bool(false))));
+                array.additional_items = None;
+            }
+        }
+    }
+}
+
+impl Spec {
+    fn new() -> Self {
+        let gen = SchemaSettings::draft2019_09()
+            .with(|settings| {
+                settings.definitions_path = "#/components/schemas/".to_owned();
+                settings.meta_schema = None;
+            })
+            .into_generator();
+
+        Spec {
+            gen,
+            paths: Map::new(),
+        }
+    }
+
+    pub fn path(&mut self, path: &'static str) -> PathItem<'_> {
+        PathItem {
+            spec: self,
+            path,
+            params: vec![],
+            bearer_auth: false,
+            conditional: false,
+        }
+    }
+
+    // schemars only runs visitors for root schemas, so the ones used here are visited by hand
+    fn schema_for<T: JsonSchema>(&mut self) -> Schema {
+        let mut schema = self.gen.subschema_for::<T>();
+        TuplesAsPrefixItems.visit_schema(&mut schema);
+        schema
+    }
+
+    fn into_document(mut self) -> Value {
+        let error = self.schema_for::<ErrorResponse>();
+        for item in self.paths.values_mut() {
+            for operation in item.as_object_mut().into_iter().flat_map(|o| o.values_mut()) {
+                operation["responses"]["default"] = json!({
+                    "description": "Error",
+                    "content": { "application/json": { "schema": error } },
+                });
+            }
+        }
+
+        let mut schemas = self.gen.take_definitions();
+        for schema in schemas.values_mut() {
+            TuplesAsPrefixItems.visit_schema(schema);
+        }
+
+        json!({
+            "openapi": "3.1.0",
+            "info": {
+                "title": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer" },
+                },
+            },
+        })
+    }
+}
+
+impl<'a> PathItem<'a> {
+    pub fn param<T: JsonSchema>(mut self, name: &str) -> Self {
+        self.params.push(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": self.spec.schema_for::<T>(),
+        }));
+        self
+    }
+
+    // Optional query string parameter
+    pub fn query<T: JsonSchema>(mut self, name: &str) -> Self {
+        self.params.push(json!({
+            "name": name,
+            "in": "query",
+            "schema": self.spec.schema_for::<T>(),
+        }));
+        self
+    }
+
+    pub fn bearer_auth(mut self) -> Self {
+        self.bearer_auth = true;
+        self
+    }
+
+    // The GET answers 304 when the client's copy still matches If-None-Match or If-Modified-Since
+    pub fn conditional(mut self) -> Self {
+        self.conditional = true;
+        self
+    }
+
+    pub fn get<Res: JsonSchema>(self, summary: &str) -> Self {
+        self.operation::<Res>("get", summary, None)
+    }
+
+    pub fn post<Res: JsonSchema>(self, summary: &str) -> Self {
+        self.operation::<Res>("post", summary, None)
+    }
+
+    pub fn post_json<Req: JsonSchema, Res: JsonSchema>(self, summary: &str) -> Self {
+        let request = self.spec.schema_for::<Req>();
+        self.operation::<Res>("post", summary, Some(request))
+    }
+
+    pub fn patch_json<Req: JsonSchema, Res: JsonSchema>(self, summary: &str) -> Self {
+        let request = self.spec.schema_for::<Req>();
+        self.operation::<Res>("patch", summary, Some(request))
+    }
+
+    // Answered with 204 and no body
+    pub fn delete(self, summary: &str) -> Self {
+        self.no_content("delete", summary)
+    }
+
+    pub fn post_no_content(self, summary: &str) -> Self {
+        self.no_content("post", summary)
+    }
+
+    fn no_content(self, method: &str, summary: &str) -> Self {
+        let item = self.operation::<()>(method, summary, None);
+        let operation = &mut item.spec.paths[item.path][method];
+        operation["responses"] = json!({ "204": { "description": "No Content" } });
+        item
+    }
+
+    // A page for browsers rather than JSON
+    pub fn get_html(self, summary: &str) -> Self {
+        let item = self.operation::<()>("get", summary, None);
+        let response = &mut item.spec.paths[item.path]["get"]["responses"]["200"];
+        response["content"] = json!({ "text/html": { "schema": { "type": "string" } } });
+        item
+    }
+
+    // Server-Sent Events, each carrying a Res in its data
+    pub fn event_stream<Res: JsonSchema>(self, summary: &str) -> Self {
+        let item = self.operation::<Res>("get", summary, None);
+        let response = &mut item.spec.paths[item.path]["get"]["responses"]["200"];
+        let schema = response["content"]["application/json"].take();
+        response["content"] = json!({ "text/event-stream": schema });
+        item
+    }
+
+    fn operation<Res: JsonSchema>(self, method: &str, summary: &str, request: Option<Schema>) -> Self {
+        let response = self.spec.schema_for::<Res>();
+
+        let mut operation = json!({
+            "summary": summary,
+            "parameters": self.params,
+            "responses": {
+                "200": {
+                    "description": "OK",
+                    "content": { "application/json": { "schema": response } },
+                },
+            },
+        });
+        if let Some(request) = request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": request } },
+            });
+        }
+        if self.conditional && method == "get" {
+            for name in &["If-None-Match", "If-Modified-Since"] {
+                operation["parameters"]
+                    .as_array_mut()
+                    .unwrap()
+                    .push(json!({ "name": name, "in": "header", "schema": { "type": "string" } }));
+            }
+            operation["responses"]["304"] = json!({ "description": "Not Modified" });
+        }
+        if self.bearer_auth {
+            operation["security"] = json!([{ "bearer": [] }]);
+        }
+
+        self.spec
+            .paths
+            .entry(self.path)
+            .or_insert_with(|| json!({}))[method] = operation;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::document;
+    use crate::routes::{self, REGISTERED};
+    use actix_web::dev::{Body, Service, ServiceResponse};
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{test, web, App, Error, HttpResponse};
+
+    const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+    // served next to the API the document describes
+    const UNDOCUMENTED: [&str; 3] = ["/openapi.json", "/docs", "/metrics"];
+
+    fn app() -> impl Service<Request=actix_http::Request, Response=ServiceResponse<Body>, Error=Error> {
+        test::init_service(
+            App::new()
+                .configure(routes::users::configure)
+                .configure(routes::follows::configure)
+                .configure(routes::posts::configure)
+                .configure(routes::comments::configure)
+                .configure(routes::reactions::configure)
+                .configure(routes::changes::configure)
+                .configure(routes::streams::configure)
+                .configure(routes::subscribers::configure)
+                .configure(routes::docs::configure)
+                .configure(routes::health::configure)
+                .configure(routes::metrics::configure)
+                .configure(routes::admin::configure)
+                .configure(routes::webhooks::configure)
+                .default_service(web::route().to(|| HttpResponse::new(StatusCode::IM_A_TEAPOT))),
+        )
+    }
+
+    // Fails when a documented operation has no handler, or when a documented path
+    // accepts a method the document does not mention.
+    #[test]
+    fn spec_matches_registered_handlers() {
+        let mut app = app();
+
+        let doc = document();
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            let uri = path
+                .replace("{id}", "1")
+                .replace("{name}", "frank")
+                .replace("{token}", "abc")
+                .replace("{followee_id}", "2")
+                .replace("{user_id}", "2")
+                .replace("{kind}", "like");
+
+            for method in METHODS.iter() {
+                let req = test::TestRequest::with_uri(&uri)
+                    .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                    .to_request();
+                let status = test::call_service(&mut app, req).status();
+
+                assert_ne!(status, StatusCode::IM_A_TEAPOT, "{} is not routed", path);
+                if item.get(*method).is_some() {
+                    assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {} has no handler", method, path);
+                } else {
+                    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {} is not documented", method, path);
+                }
+            }
+        }
+    }
+
+    // Fails when a route is registered without being described in the document
+    #[test]
+    fn registered_routes_documented() {
+        app();
+
+        let doc = document();
+        let registered = REGISTERED.with(|paths| paths.borrow().clone());
+        assert!(registered.contains("/posts"));
+        for path in registered {
+            assert!(
+                doc["paths"].get(path).is_some() || UNDOCUMENTED.contains(&path),
+                "{} is not documented",
+                path
+            );
+        }
+    }
+}
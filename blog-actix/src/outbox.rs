@@ -0,0 +1,162 @@
+use crate::errors::AppError;
+use crate::mail::{Mailer, Message};
+use crate::models::{self, PendingMail, Template};
+use crate::retry::{Queued, Retries};
+use crate::Pools;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use server_common::prometheus::{register_int_counter_vec, IntCounterVec};
+use std::sync::Arc;
+use server_common::shutdown::Periodic;
+use std::time::Duration;
+
+// Messages sent per pass over the outbox
+const BATCH_SIZE: i64 = 100;
+
+lazy_static! {
+    static ref MAIL_ATTEMPTS: IntCounterVec = register_int_counter_vec!(
+        "mail_attempts_total",
+        "Attempts at sending the messages of the outbox, by template and result",
+        &["template", "result"]
+    )
+    .unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub struct MailConfig {
+    // From of the messages, `Blog <blog@example.com>` for instance
+    pub from: String,
+    // where the links of the messages point to
+    pub base_url: String,
+    pub poll_interval: Duration,
+    pub max_attempts: i32,
+    // wait before the second attempt, doubled after every failure
+    pub backoff: Duration,
+}
+
+impl MailConfig {
+    pub fn new(from: String, base_url: String) -> Self {
+        MailConfig {
+            from,
+            base_url,
+            poll_interval: Duration::from_secs(5),
+            max_attempts: 8,
+            backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+// Sends the messages queued in the outbox with the mailer, away from the request handlers
+#[derive(Clone)]
+pub struct Outbox {
+    pools: Pools,
+    config: MailConfig,
+    mailer: Arc<dyn Mailer>,
+}
+
+impl Outbox {
+    pub fn new(pools: Pools, config: MailConfig, mailer: Arc<dyn Mailer>) -> Self {
+        Outbox { pools, config, mailer }
+    }
+
+    pub fn schedule(&self) -> Periodic {
+        let outbox = self.clone();
+        Periodic::spawn("Outbox", self.config.poll_interval, move || {
+            if let Err(e) = outbox.send_due() {
+                log::error!("Sending mail failed: {}", e);
+            }
+        })
+    }
+
+    // Makes an attempt at every message due, returns how many were sent
+    pub fn send_due(&self) -> Result<usize, AppError> {
+        let pending = models::pending_mail(&self.pools.reader()?, BATCH_SIZE)?;
+
+        let retries = Retries {
+            name: "mail",
+            counter: &MAIL_ATTEMPTS,
+            success: "sent",
+            max_attempts: self.config.max_attempts,
+            backoff: self.config.backoff,
+        };
+        // each message is recorded as soon as it's sent, so a restart doesn't send it again
+        let outcomes = pending.into_iter().map(|mail| {
+            let outcome = self
+                .render(&mail)
+                .and_then(|message| self.mailer.send(&message).map_err(|e| e.to_string()));
+            (mail, outcome)
+        });
+        retries.record(outcomes, |id, attempt| models::record_send(&self.pools.writer()?, id, attempt))
+    }
+
+    fn render(&self, mail: &PendingMail) -> Result<Message, String> {
+        let template = Template::parse(&mail.template)
+            .ok_or_else(|| format!("unknown template {}", mail.template))?;
+        let mut data: Value = serde_json::from_str(&mail.data).map_err(|e| e.to_string())?;
+        data["base_url"] = self.config.base_url.trim_end_matches('/').into();
+
+        let (subject, body) = render(template, &data);
+        let mut headers = vec![];
+        if let (Template::PostPublished, Some(token)) = (template, data["token"].as_str()) {
+            // RFC 8058 one-click unsubscribe, mail clients POST to the link
+            let url = format!("{}/subscribers/{}/unsubscribe", data["base_url"].as_str().unwrap_or_default(), token);
+            headers.push(("List-Unsubscribe".to_owned(), format!("<{}>", url)));
+            headers.push(("List-Unsubscribe-Post".to_owned(), "List-Unsubscribe=One-Click".to_owned()));
+        }
+
+        Ok(Message {
+            from: self.config.from.clone(),
+            to: mail.recipient.clone(),
+            subject,
+            body,
+            headers,
+        })
+    }
+}
+
+// The subject, on the first line of the templates, and the body, with the {{name}} placeholders
+// replaced by the fields of `data`
+fn render(template: Template, data: &Value) -> (String, String) {
+    let source = match template {
+        Template::CommentAdded => include_str!("../templates/comment_added.txt"),
+        Template::PostPublished => include_str!("../templates/post_published.txt"),
+        Template::ConfirmSubscription => include_str!("../templates/confirm_subscription.txt"),
+    };
+
+    // one pass, so values looking like placeholders are left alone
+    let mut text = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        text.push_str(&rest[..start]);
+        match &data[&rest[start + 2..end]] {
+            Value::String(s) => text.push_str(s),
+            Value::Null => {}
+            value => text.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 2..];
+    }
+    text.push_str(rest);
+
+    let mut parts = text.splitn(2, "\n\n");
+    let subject = parts.next().unwrap_or_default();
+    let body = parts.next().unwrap_or_default();
+    (subject.trim_start_matches("Subject: ").to_owned(), body.to_owned())
+}
+
+impl Queued for PendingMail {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn attempts(&self) -> i32 {
+        self.attempts
+    }
+
+    fn kind(&self) -> &str {
+        &self.template
+    }
+}
@@ -0,0 +1,126 @@
+use crate::errors::AppError;
+use crate::models::Attempt;
+use chrono::{NaiveDateTime, Utc};
+use server_common::prometheus::IntCounterVec;
+use std::time::Duration;
+
+// Doubling the backoff stops there
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+// An item of a queue emptied by a background worker, a webhook delivery or a message of the outbox
+pub(crate) trait Queued {
+    fn id(&self) -> i32;
+    // made before this one
+    fn attempts(&self) -> i32;
+    // what the attempts are counted by, the event or the template
+    fn kind(&self) -> &str;
+}
+
+// What the outbox and the webhook deliveries have in common: an item failing is tried again after
+// a backoff doubled every time, until `max_attempts` were made
+pub(crate) struct Retries {
+    // of the items in the log lines
+    pub name: &'static str,
+    // by kind and result
+    pub counter: &'static IntCounterVec,
+    // result counted for the items that went through
+    pub success: &'static str,
+    pub max_attempts: i32,
+    pub backoff: Duration,
+}
+
+impl Retries {
+    // Records the outcome of the attempts as they are made, returns how many went through
+    pub(crate) fn record<T, I, F>(&self, outcomes: I, mut record: F) -> Result<usize, AppError>
+        where
+            T: Queued,
+            I: IntoIterator<Item=(T, Result<(), String>)>,
+            F: FnMut(i32, &Attempt) -> Result<(), AppError>,
+    {
+        let mut succeeded = 0;
+        for (item, outcome) in outcomes {
+            let (attempt, result) = match outcome {
+                Ok(()) => {
+                    succeeded += 1;
+                    (Attempt::Delivered, self.success)
+                }
+                Err(error) if item.attempts() + 1 >= self.max_attempts => {
+                    log::warn!("Giving up {} {}: {}", self.name, item.id(), error);
+                    (Attempt::Failed { error }, "failed")
+                }
+                Err(error) => {
+                    let at = retry_at(self.backoff, item.attempts());
+                    (Attempt::Retry { error, at }, "retry")
+                }
+            };
+            self.counter.with_label_values(&[item.kind(), result]).inc();
+            record(item.id(), &attempt)?;
+        }
+        Ok(succeeded)
+    }
+}
+
+// Records an attempt at an item of a queue table: `$table` has the attempts, last_error,
+// next_attempt_at and failed_at columns, `$done` is set once the item went through. Expects
+// diesel's prelude in scope.
+macro_rules! record_attempt {
+    ($conn:expr, $table:ident, $done:ident, $id:expr, $attempt:expr) => {{
+        let now = chrono::Utc::now().naive_utc();
+        let item = $table::table.find($id);
+        let attempts = $table::attempts.eq($table::attempts + 1);
+        let updated = match $attempt {
+            $crate::models::Attempt::Delivered => {
+                let update = diesel::update(item).set((attempts, $table::$done.eq(now)));
+                $crate::db::sql_span(&update).in_scope(|| update.execute($conn))
+            }
+            $crate::models::Attempt::Retry { error, at } => {
+                let update = diesel::update(item).set((
+                    attempts,
+                    $table::last_error.eq(error),
+                    $table::next_attempt_at.eq(at),
+                ));
+                $crate::db::sql_span(&update).in_scope(|| update.execute($conn))
+            }
+            $crate::models::Attempt::Failed { error } => {
+                let update = diesel::update(item).set((
+                    attempts,
+                    $table::last_error.eq(error),
+                    $table::failed_at.eq(now),
+                ));
+                $crate::db::sql_span(&update).in_scope(|| update.execute($conn))
+            }
+        };
+        updated.map(|_| ()).map_err($crate::errors::AppError::from)
+    }};
+}
+pub(crate) use record_attempt;
+
+// When to make the next attempt after `attempts` failed ones
+fn retry_at(backoff: Duration, attempts: i32) -> NaiveDateTime {
+    let wait = 2u32
+        .checked_pow(attempts.max(0) as u32)
+        .and_then(|factor| backoff.checked_mul(factor))
+        .map_or(MAX_BACKOFF, |wait| wait.min(MAX_BACKOFF));
+    Utc::now().naive_utc() + chrono::Duration::from_std(wait).unwrap_or_else(|_| chrono::Duration::days(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_at, MAX_BACKOFF};
+    use chrono::Utc;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_doubled_up_to_the_maximum() {
+        let now = Utc::now().naive_utc();
+        let wait = |backoff, attempts| (retry_at(backoff, attempts) - now).num_seconds();
+
+        assert_eq!(wait(Duration::from_secs(10), 0), 10);
+        assert_eq!(wait(Duration::from_secs(10), 3), 80);
+        let max = MAX_BACKOFF.as_secs() as i64;
+        assert_eq!(wait(Duration::from_secs(10), 20), max);
+        // would overflow the multiplication
+        assert_eq!(wait(Duration::from_secs(10), 40), max);
+        assert_eq!(wait(Duration::from_secs(u64::MAX / 2), 4), max);
+    }
+}
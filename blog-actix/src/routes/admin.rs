@@ -0,0 +1,44 @@
+use crate::backup::{Backups, Snapshot};
+use crate::errors::AppError;
+use crate::openapi::Spec;
+use crate::routes::{convert, resource};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::{self, Either};
+use futures::Future;
+use ring::constant_time::verify_slices_are_equal;
+use server_common::metrics::block;
+
+// Token of the admin routes that aren't part of the backups
+pub struct AdminToken(pub String);
+
+// An empty token never matches, the comparison takes the same time wherever the values differ
+pub(crate) fn authorized(req: &HttpRequest, token: &str) -> bool {
+    !token.is_empty()
+        && req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "))
+            .is_some_and(|value| verify_slices_are_equal(value, token.as_bytes()).is_ok())
+}
+
+fn create_backup(
+    req: HttpRequest,
+    backups: web::Data<Backups>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    if !authorized(&req, backups.admin_token()) {
+        return Either::A(future::err(AppError::Unauthorized));
+    }
+
+    Either::B(block(move || backups.snapshot()).then(convert))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/admin/backups").route(web::post().to_async(create_backup)));
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/admin/backups")
+        .bearer_auth()
+        .post::<Snapshot>("Take a database snapshot, only available when backups are enabled");
+}
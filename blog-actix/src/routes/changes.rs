@@ -0,0 +1,61 @@
+use crate::errors::AppError;
+use crate::openapi::Spec;
+use crate::routes::{convert, resource};
+use crate::{models, Pools};
+use actix_web::{web, HttpResponse};
+use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+struct ChangesQuery {
+    since: Option<String>,
+    limit: Option<i64>,
+}
+
+// A page of the changes log. `next` is sent back as `since` to get the following changes, it
+// stays the same while nothing was written.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Changes {
+    pub changes: Vec<models::Change>,
+    pub next: String,
+    // more changes were written after the last one of this page
+    pub more: bool,
+}
+
+fn changes(
+    query: web::Query<ChangesQuery>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let query = query.into_inner();
+    block(move || {
+        // tokens are the id of the last change seen, clients shouldn't rely on that
+        let since = match &query.since {
+            Some(token) => token.parse().map_err(|_| AppError::InvalidSyncToken)?,
+            None => 0,
+        };
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        // one more than asked tells whether there is another page
+        let mut changes = models::changes_since(&pools.reader()?, since, limit + 1)?;
+        let more = changes.len() as i64 > limit;
+        changes.truncate(limit as usize);
+        let next = changes.last().map_or(since, |change| change.id).to_string();
+        Ok(Changes { changes, next, more })
+    })
+        .then(convert)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/changes").route(web::get().to_async(changes)));
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/changes")
+        .query::<String>("since")
+        .query::<i64>("limit")
+        .get::<Changes>("List the users, posts and comments written after a sync token");
+}
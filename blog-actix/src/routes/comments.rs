@@ -1,11 +1,19 @@
+use crate::cache::Listings;
+use crate::conditional::{self, Preconditions};
 use crate::errors::AppError;
-use crate::routes::convert;
-use crate::{models, Pool};
-use actix_web::{web, HttpResponse};
-use diesel::prelude::*;
+use crate::openapi::Spec;
+use crate::routes::{convert, resource};
+use crate::streams::{Streams, Topic};
+use crate::{models, Pools};
+use actix_web::{web, HttpRequest, HttpResponse};
 use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
 
-#[derive(Debug, Serialize, Deserialize)]
+// Tables a post's comments are read from, any write to them changes their ETag
+const LISTING_TABLES: [&str; 3] = ["comments", "reactions", "users"];
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct CommentInput {
     user_id: i32,
     body: String,
@@ -14,28 +22,45 @@ struct CommentInput {
 fn add_comment(
     post_id: web::Path<i32>,
     comment: web::Json<CommentInput>,
-    pool: web::Data<Pool>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+    streams: web::Data<Streams>,
 ) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
+    block(move || {
+        let conn = &pools.writer()?;
         let data = comment.into_inner();
 
-        models::create_comment(conn, data.user_id, post_id.into_inner(), data.body.as_str())
+        let comment = models::create_comment(conn, data.user_id, post_id.into_inner(), data.body.as_str())?;
+        listings.clear();
+        if streams.has_subscribers(Topic::Comments(comment.post_id)) {
+            // the comment is committed, failing to push it doesn't fail the request
+            match models::find_user(&pools.reader()?, models::UserKey::ID(comment.user_id)) {
+                Ok(author) => streams.comment_added(&comment, &author),
+                Err(e) => log::warn!("Comment {} not streamed: {}", comment.id, e),
+            }
+        }
+        Ok(comment)
     })
         .then(convert)
 }
 
-fn post_comments(post_id: web::Path<i32>, pool: web::Data<Pool>) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
-        models::post_comments(conn, post_id.into_inner())
+fn post_comments(
+    req: HttpRequest,
+    post_id: web::Path<i32>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let preconditions = Preconditions::new(&req);
+    block(move || {
+        let conn = &pools.reader()?;
+        let versions = models::table_versions(conn, &LISTING_TABLES)?;
+        preconditions.evaluate(&versions, || models::post_comments(conn, post_id.into_inner()))
     })
-        .then(convert)
+        .then(conditional::convert)
 }
 
-fn user_comments(user_id: web::Path<i32>, pool: web::Data<Pool>) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
+fn user_comments(user_id: web::Path<i32>, pools: web::Data<Pools>) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || {
+        let conn = &pools.reader()?;
         models::user_comments(conn, user_id.into_inner())
     })
         .then(convert)
@@ -43,10 +68,21 @@ fn user_comments(user_id: web::Path<i32>, pool: web::Data<Pool>) -> impl Future<
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg
-        .service(web::resource("/users/{id}/comments").route(web::get().to_async(user_comments)))
+        .service(resource("/users/{id}/comments").route(web::get().to_async(user_comments)))
         .service(
-            web::resource("/posts/{id}/comments")
+            resource("/posts/{id}/comments")
                 .route(web::post().to_async(add_comment))
                 .route(web::get().to_async(post_comments))
         );
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/users/{id}/comments")
+        .param::<i32>("id")
+        .get::<Vec<(models::Comment, models::PostWithComment)>>("List a user's comments");
+    spec.path("/posts/{id}/comments")
+        .param::<i32>("id")
+        .post_json::<CommentInput, models::Comment>("Comment on a post")
+        .conditional()
+        .get::<Vec<models::CommentWithAuthor>>("List the comments of a post");
 }
\ No newline at end of file
@@ -0,0 +1,99 @@
+use crate::openapi;
+use crate::routes::resource;
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+use url::Url;
+
+// Copy of swagger-ui-dist the viewer is loaded from when no other one is configured
+pub const DEFAULT_SWAGGER_UI: &str = "https://unpkg.com/swagger-ui-dist@5";
+
+// The Swagger UI page, loading its script and stylesheet from a copy of swagger-ui-dist
+#[derive(Clone, Debug)]
+pub struct SwaggerUi {
+    page: String,
+    policy: String,
+}
+
+impl SwaggerUi {
+    // The assets are either on another origin, like the default CDN, or a path on this one
+    pub fn new(assets_url: &str) -> Self {
+        let assets_url = assets_url.trim_end_matches('/');
+        let source = match Url::parse(assets_url) {
+            Ok(url) => url.origin().ascii_serialization(),
+            Err(_) => "'self'".to_owned(),
+        };
+
+        SwaggerUi {
+            page: SWAGGER_UI.replace("{assets}", assets_url),
+            // Swagger UI is started by an inline script, which the policy applied to the JSON
+            // routes would block
+            policy: format!(
+                "default-src 'none'; script-src {source} 'unsafe-inline'; style-src {source}; \
+                 img-src https: data:; connect-src 'self'; frame-ancestors 'none'",
+                source = source
+            ),
+        }
+    }
+}
+
+impl Default for SwaggerUi {
+    fn default() -> Self {
+        SwaggerUi::new(DEFAULT_SWAGGER_UI)
+    }
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>blog-actix API</title>
+    <link rel="stylesheet" href="{assets}/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="{assets}/swagger-ui-bundle.js"></script>
+<script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+</script>
+</body>
+</html>
+"##;
+
+fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(openapi::document())
+}
+
+fn swagger_ui(swagger_ui: web::Data<SwaggerUi>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .header(header::CONTENT_SECURITY_POLICY, swagger_ui.policy.as_str())
+        .body(swagger_ui.page.clone())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/openapi.json").route(web::get().to(openapi_json)))
+        .service(resource("/docs").route(web::get().to(swagger_ui)));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assets_loaded_from_configured_origin() {
+        let swagger_ui = SwaggerUi::new("https://assets.example/swagger-ui-dist@5/");
+        assert!(swagger_ui
+            .page
+            .contains(r#"src="https://assets.example/swagger-ui-dist@5/swagger-ui-bundle.js""#));
+        assert!(swagger_ui.policy.contains("script-src https://assets.example 'unsafe-inline'"));
+        assert!(!swagger_ui.policy.contains("unpkg"));
+    }
+
+    #[test]
+    fn assets_loaded_from_path() {
+        let swagger_ui = SwaggerUi::new("/assets/swagger-ui");
+        assert!(swagger_ui.page.contains(r#"href="/assets/swagger-ui/swagger-ui.css""#));
+        assert!(swagger_ui.policy.contains("style-src 'self';"));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::errors::AppError;
+use crate::openapi::Spec;
+use crate::routes::admin::AdminToken;
+use crate::routes::users::{check_owner, credentials};
+use crate::routes::{convert, resource};
+use crate::{models, Pools};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::{self, Either};
+use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct FollowInput {
+    user_id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedQuery {
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+// A page of a user's feed. `next` is sent back as `before` to get the older posts, there are none
+// left when it is missing.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Feed {
+    pub posts: Vec<models::PostEntry>,
+    pub next: Option<String>,
+}
+
+// With the follower's token, or the admin token, as for editing the follower's profile
+fn follow(
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+    input: web::Json<FollowInput>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let credentials = match credentials(&req, admin_token) {
+        Ok(credentials) => credentials,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    let (follower_id, followee_id) = (user_id.into_inner(), input.user_id);
+    if follower_id == followee_id {
+        return Either::A(future::err(AppError::CannotFollowSelf));
+    }
+
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            check_owner(conn, follower_id, &credentials)?;
+            models::follow(conn, follower_id, followee_id)
+        })
+            .then(convert),
+    )
+}
+
+// Same credentials as follow
+fn unfollow(
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let credentials = match credentials(&req, admin_token) {
+        Ok(credentials) => credentials,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    let (follower_id, followee_id) = path.into_inner();
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            check_owner(conn, follower_id, &credentials)?;
+            models::unfollow(conn, follower_id, followee_id)
+        })
+            .map(|_| HttpResponse::NoContent().finish())
+            .from_err(),
+    )
+}
+
+fn following(user_id: web::Path<i32>, pools: web::Data<Pools>) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || models::following(&pools.reader()?, user_id.into_inner())).then(convert)
+}
+
+fn followers(user_id: web::Path<i32>, pools: web::Data<Pools>) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || models::followers(&pools.reader()?, user_id.into_inner())).then(convert)
+}
+
+fn feed(
+    user_id: web::Path<i32>,
+    query: web::Query<FeedQuery>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let query = query.into_inner();
+    block(move || {
+        // cursors are the id of the last post of a page, clients shouldn't rely on that
+        let before = match &query.before {
+            Some(cursor) => Some(cursor.parse().map_err(|_| AppError::InvalidCursor)?),
+            None => None,
+        };
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let conn = &pools.reader()?;
+        let user = models::find_user(conn, models::UserKey::ID(user_id.into_inner()))?;
+        // one more than asked tells whether there is another page
+        let mut posts = models::feed(conn, user.id, before, limit + 1)?;
+        let next = if posts.len() as i64 > limit {
+            posts.truncate(limit as usize);
+            posts.last().map(|((post, _), _)| post.id.to_string())
+        } else {
+            None
+        };
+        Ok(Feed { posts, next })
+    })
+        .then(convert)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        resource("/users/{id}/following")
+            .route(web::post().to_async(follow))
+            .route(web::get().to_async(following)),
+    )
+        .service(
+            resource("/users/{id}/following/{followee_id}").route(web::delete().to_async(unfollow)),
+        )
+        .service(resource("/users/{id}/followers").route(web::get().to_async(followers)))
+        .service(resource("/users/{id}/feed").route(web::get().to_async(feed)));
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/users/{id}/following")
+        .param::<i32>("id")
+        .get::<Vec<models::User>>("List the users a user follows")
+        .bearer_auth()
+        .post_json::<FollowInput, models::Follow>("Follow another user, with the follower's token");
+    spec.path("/users/{id}/following/{followee_id}")
+        .param::<i32>("id")
+        .param::<i32>("followee_id")
+        .bearer_auth()
+        .delete("Stop following a user, with the follower's token");
+    spec.path("/users/{id}/followers")
+        .param::<i32>("id")
+        .get::<Vec<models::User>>("List the followers of a user");
+    spec.path("/users/{id}/feed")
+        .param::<i32>("id")
+        .query::<String>("before")
+        .query::<i64>("limit")
+        .get::<Feed>("List the published posts of the users a user follows, newest first");
+}
@@ -0,0 +1,73 @@
+use crate::errors::AppError;
+use crate::openapi::Spec;
+use crate::routes::{convert, resource};
+use crate::{db, Pools};
+use actix_web::{web, HttpResponse};
+use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Status {
+    pub status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Version {
+    pub name: String,
+    pub version: String,
+    pub git_hash: String,
+    // latest migration run on the database, None when it can't be reached
+    pub schema_version: Option<String>,
+}
+
+fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Status {
+        status: "ok".to_owned(),
+    })
+}
+
+fn readyz(pools: web::Data<Pools>) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || {
+        let conn = &pools.reader()?;
+        let pending = db::pending_migrations(conn)?;
+        if !pending.is_empty() {
+            return Err(AppError::MigrationsPending(pending));
+        }
+
+        Ok(Status {
+            status: "ready".to_owned(),
+        })
+    })
+        .then(convert)
+}
+
+fn version(pools: web::Data<Pools>) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || {
+        let schema_version = pools
+            .reader()
+            .and_then(|conn| db::schema_version(&conn))
+            .unwrap_or(None);
+
+        Ok::<_, AppError>(Version {
+            name: env!("CARGO_PKG_NAME").to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            git_hash: env!("GIT_HASH").to_owned(),
+            schema_version,
+        })
+    })
+        .then(convert)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/healthz").route(web::get().to(healthz)))
+        .service(resource("/readyz").route(web::get().to_async(readyz)))
+        .service(resource("/version").route(web::get().to_async(version)));
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/healthz").get::<Status>("Check that the process is alive");
+    spec.path("/readyz")
+        .get::<Status>("Check that the database is reachable and its migrations are current");
+    spec.path("/version").get::<Version>("Deployed version and database schema version");
+}
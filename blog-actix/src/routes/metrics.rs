@@ -0,0 +1,35 @@
+use crate::routes::resource;
+use crate::Pools;
+use actix_web::{web, HttpResponse};
+use lazy_static::lazy_static;
+use server_common::metrics;
+use server_common::prometheus::{register_int_gauge_vec, IntGaugeVec};
+
+lazy_static! {
+    static ref POOL_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "db_pool_connections",
+        "Connections held by the database pools",
+        &["pool", "state"]
+    )
+    .unwrap();
+}
+
+// The pool gauges are read when Prometheus scrapes, the request metrics are kept by the
+// Metrics middleware. Not part of the OpenAPI document, like /openapi.json it isn't JSON.
+fn metrics(pools: web::Data<Pools>) -> HttpResponse {
+    for (pool, state) in pools.states().iter() {
+        let idle = state.idle_connections;
+        POOL_CONNECTIONS
+            .with_label_values(&[pool, "idle"])
+            .set(idle.into());
+        POOL_CONNECTIONS
+            .with_label_values(&[pool, "in_use"])
+            .set((state.connections - idle).into());
+    }
+
+    metrics::render()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/metrics").route(web::get().to(metrics)));
+}
@@ -1,11 +1,19 @@
+use crate::cache::Listings;
+use crate::conditional::{self, Preconditions, Validators};
 use crate::errors::AppError;
-use crate::routes::convert;
-use crate::{models, Pool};
-use actix_web::{web, HttpResponse};
-use diesel::prelude::*;
+use crate::openapi::Spec;
+use crate::routes::{convert, resource};
+use crate::streams::{Streams, Topic};
+use crate::{models, Pools};
+use actix_web::{web, HttpRequest, HttpResponse};
 use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
 
-#[derive(Debug, Serialize, Deserialize)]
+// Tables the post listings are read from, any write to them changes their ETag
+const LISTING_TABLES: [&str; 4] = ["comments", "posts", "reactions", "users"];
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct PostInput {
     title: String,
     body: String
@@ -14,56 +22,100 @@ struct PostInput {
 fn add_post(
     user_id: web::Path<i32>,
     post: web::Json<PostInput>,
-    pool: web::Data<Pool>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
 ) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
+    block(move || {
         let key = models::UserKey::ID(user_id.into_inner());
 
-        models::find_user(conn, key).and_then(|user| {
+        let post = models::find_user(&pools.reader()?, key).and_then(|user| {
+            let conn = &pools.writer()?;
             let post = post.into_inner();
             models::create_post(conn, &user, post.title.as_str(), post.body.as_str())
-        })
+        })?;
+        listings.post_changed(&post);
+        Ok(post)
     })
         .then(convert)
 }
 
 fn publish_post(
     post_id: web::Path<i32>,
-    pool: web::Data<Pool>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+    streams: web::Data<Streams>,
 ) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
-        models::publish_post(conn, post_id.into_inner())
+    block(move || {
+        let conn = &pools.writer()?;
+        let (post, first_published) = models::publish_post(conn, post_id.into_inner())?;
+        listings.post_changed(&post);
+        if first_published && streams.has_subscribers(Topic::PublishedPosts) {
+            match models::find_user(&pools.reader()?, models::UserKey::ID(post.user_id)) {
+                Ok(author) => streams.post_published(&post, &author),
+                Err(e) => log::warn!("Post {} not streamed: {}", post.id, e),
+            }
+        }
+        Ok(post)
     })
         .then(convert)
 }
 
 fn users_posts(
+    req: HttpRequest,
     user_id: web::Path<i32>,
-    pool: web::Data<Pool>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
 ) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
-        models::users_posts(conn, user_id.into_inner())
+    let preconditions = Preconditions::new(&req);
+    let user_id = user_id.into_inner();
+    block(move || {
+        let (validators, posts) = listings.users_posts(user_id, |primary| {
+            let conn = &if primary { pools.primary_reader()? } else { pools.reader()? };
+            let versions = models::table_versions(conn, &LISTING_TABLES)?;
+            Ok((Validators::new(&versions), models::users_posts(conn, user_id)?))
+        })?;
+        Ok(preconditions.check(validators, posts))
     })
-        .then(convert)
+        .then(conditional::convert)
 }
 
-fn all_posts(pool: web::Data<Pool>) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
-        models::all_posts(conn)
+fn all_posts(
+    req: HttpRequest,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let preconditions = Preconditions::new(&req);
+    block(move || {
+        let (validators, posts) = listings.all_posts(|primary| {
+            let conn = &if primary { pools.primary_reader()? } else { pools.reader()? };
+            let versions = models::table_versions(conn, &LISTING_TABLES)?;
+            Ok((Validators::new(&versions), models::all_posts(conn)?))
+        })?;
+        Ok(preconditions.check(validators, posts))
     })
-        .then(convert)
+        .then(conditional::convert)
 }
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
-        web::resource("/users/{id}/posts")
+        resource("/users/{id}/posts")
             .route(web::post().to_async(add_post))
             .route(web::get().to_async(users_posts))
     )
-        .service(web::resource("/posts").route(web::get().to_async(all_posts)))
-        .service(web::resource("/posts/{id}/publish").route(web::post().to_async(publish_post)));
+        .service(resource("/posts").route(web::get().to_async(all_posts)))
+        .service(resource("/posts/{id}/publish").route(web::post().to_async(publish_post)));
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/users/{id}/posts")
+        .param::<i32>("id")
+        .post_json::<PostInput, models::Post>("Create a draft post for a user")
+        .conditional()
+        .get::<Vec<models::UserPostEntry>>("List a user's posts with their comments");
+    spec.path("/posts")
+        .conditional()
+        .get::<Vec<models::PostEntry>>("List published posts with their authors and comments");
+    spec.path("/posts/{id}/publish")
+        .param::<i32>("id")
+        .post::<models::Post>("Publish a post");
 }
\ No newline at end of file
@@ -0,0 +1,151 @@
+use crate::cache::Listings;
+use crate::errors::AppError;
+use crate::models::{self, Entity, ReactionKind};
+use crate::openapi::Spec;
+use crate::routes::admin::AdminToken;
+use crate::routes::users::{check_owner, credentials};
+use crate::routes::{convert, resource};
+use crate::Pools;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::{self, Either};
+use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct ReactionInput {
+    user_id: i32,
+    kind: ReactionKind,
+}
+
+// With the reacting user's token, or the admin token, as for following
+fn react(
+    req: &HttpRequest,
+    target: Entity,
+    target_id: i32,
+    input: ReactionInput,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let credentials = match credentials(req, admin_token) {
+        Ok(credentials) => credentials,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            check_owner(conn, input.user_id, &credentials)?;
+            let reaction = models::react(conn, input.user_id, target, target_id, input.kind)?;
+            listings.clear();
+            Ok(reaction)
+        })
+            .then(convert),
+    )
+}
+
+// Same credentials as react
+fn unreact(
+    req: &HttpRequest,
+    target: Entity,
+    (target_id, user_id, kind): (i32, i32, String),
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let credentials = match credentials(req, admin_token) {
+        Ok(credentials) => credentials,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            check_owner(conn, user_id, &credentials)?;
+            // nobody reacted with a kind that doesn't exist
+            let kind = ReactionKind::parse(&kind).ok_or(AppError::RecordNotFound)?;
+            models::unreact(conn, user_id, target, target_id, kind)?;
+            listings.clear();
+            Ok(())
+        })
+            .map(|_| HttpResponse::NoContent().finish())
+            .from_err(),
+    )
+}
+
+fn react_to_post(
+    req: HttpRequest,
+    post_id: web::Path<i32>,
+    input: web::Json<ReactionInput>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    react(&req, Entity::Post, post_id.into_inner(), input.into_inner(), admin_token, pools, listings)
+}
+
+fn unreact_to_post(
+    req: HttpRequest,
+    path: web::Path<(i32, i32, String)>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    unreact(&req, Entity::Post, path.into_inner(), admin_token, pools, listings)
+}
+
+fn react_to_comment(
+    req: HttpRequest,
+    comment_id: web::Path<i32>,
+    input: web::Json<ReactionInput>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    react(&req, Entity::Comment, comment_id.into_inner(), input.into_inner(), admin_token, pools, listings)
+}
+
+fn unreact_to_comment(
+    req: HttpRequest,
+    path: web::Path<(i32, i32, String)>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    unreact(&req, Entity::Comment, path.into_inner(), admin_token, pools, listings)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/posts/{id}/reactions").route(web::post().to_async(react_to_post)))
+        .service(
+            resource("/posts/{id}/reactions/{user_id}/{kind}")
+                .route(web::delete().to_async(unreact_to_post)),
+        )
+        .service(resource("/comments/{id}/reactions").route(web::post().to_async(react_to_comment)))
+        .service(
+            resource("/comments/{id}/reactions/{user_id}/{kind}")
+                .route(web::delete().to_async(unreact_to_comment)),
+        );
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/posts/{id}/reactions")
+        .param::<i32>("id")
+        .bearer_auth()
+        .post_json::<ReactionInput, models::Reaction>("React to a post, with the user's token");
+    spec.path("/posts/{id}/reactions/{user_id}/{kind}")
+        .param::<i32>("id")
+        .param::<i32>("user_id")
+        .param::<ReactionKind>("kind")
+        .bearer_auth()
+        .delete("Take back a reaction to a post, with the user's token");
+    spec.path("/comments/{id}/reactions")
+        .param::<i32>("id")
+        .bearer_auth()
+        .post_json::<ReactionInput, models::Reaction>("React to a comment, with the user's token");
+    spec.path("/comments/{id}/reactions/{user_id}/{kind}")
+        .param::<i32>("id")
+        .param::<i32>("user_id")
+        .param::<ReactionKind>("kind")
+        .bearer_auth()
+        .delete("Take back a reaction to a comment, with the user's token");
+}
@@ -0,0 +1,37 @@
+use crate::errors::AppError;
+use crate::openapi::Spec;
+use crate::routes::resource;
+use crate::streams::{Streams, Topic};
+use crate::{models, Pools};
+use actix_web::{web, HttpResponse};
+use futures::Future;
+use server_common::metrics::block;
+
+fn comment_stream(
+    post_id: web::Path<i32>,
+    pools: web::Data<Pools>,
+    streams: web::Data<Streams>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || models::find_post(&pools.reader()?, post_id.into_inner()))
+        .from_err()
+        .map(move |post| streams.subscribe(Topic::Comments(post.id)))
+}
+
+fn post_stream(streams: web::Data<Streams>) -> HttpResponse {
+    streams.subscribe(Topic::PublishedPosts)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/posts/stream").route(web::get().to(post_stream)))
+        .service(
+            resource("/posts/{id}/comments/stream").route(web::get().to_async(comment_stream)),
+        );
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/posts/stream")
+        .event_stream::<(models::Post, models::User)>("Stream the posts as they are published");
+    spec.path("/posts/{id}/comments/stream")
+        .param::<i32>("id")
+        .event_stream::<models::CommentWithAuthor>("Stream the new comments of a post");
+}
@@ -0,0 +1,107 @@
+use crate::errors::AppError;
+use crate::mail::valid_email;
+use crate::openapi::Spec;
+use crate::routes::{convert, resource};
+use crate::{models, tokens, Pools};
+use actix_web::{web, HttpResponse};
+use futures::future::{self, Either};
+use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct SubscriberInput {
+    email: String,
+}
+
+fn subscribe(
+    input: web::Json<SubscriberInput>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let email = input.into_inner().email;
+    if !valid_email(&email) {
+        return Either::A(future::err(AppError::InvalidEmail));
+    }
+
+    Either::B(
+        block(move || {
+            // only sent in the messages, so only the owner of the address can unsubscribe it
+            let token = tokens::generate();
+            models::create_subscriber(&pools.writer()?, &email, &token)
+        })
+            .then(convert),
+    )
+}
+
+// Page the links of the messages open. Mail scanners and prefetchers follow links too, so opening
+// one only asks the reader to confirm, with a form POSTing back to the same URL.
+fn form_page(title: &str, button: &str) -> HttpResponse {
+    let page = FORM_PAGE.replace("{title}", title).replace("{button}", button);
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(page)
+}
+
+const FORM_PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{title}</title>
+</head>
+<body>
+<h1>{title}</h1>
+<form method="post">
+    <button type="submit">{button}</button>
+</form>
+</body>
+</html>
+"##;
+
+fn confirm_page() -> HttpResponse {
+    form_page("Confirm your subscription to the new posts", "Confirm")
+}
+
+fn unsubscribe_page() -> HttpResponse {
+    form_page("Unsubscribe from the new posts", "Unsubscribe")
+}
+
+fn confirm(
+    token: web::Path<String>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || models::confirm_subscriber(&pools.writer()?, &token.into_inner())).then(convert)
+}
+
+fn unsubscribe(
+    token: web::Path<String>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    block(move || models::delete_subscriber(&pools.writer()?, &token.into_inner()))
+        .map(|_| HttpResponse::NoContent().finish())
+        .from_err()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(resource("/subscribers").route(web::post().to_async(subscribe)))
+        .service(
+            resource("/subscribers/{token}/confirm")
+                .route(web::get().to(confirm_page))
+                .route(web::post().to_async(confirm)),
+        )
+        .service(
+            resource("/subscribers/{token}/unsubscribe")
+                .route(web::get().to(unsubscribe_page))
+                .route(web::post().to_async(unsubscribe)),
+        );
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/subscribers")
+        .post_json::<SubscriberInput, models::Subscriber>("Subscribe an address to the new posts, once confirmed");
+    spec.path("/subscribers/{token}/confirm")
+        .param::<String>("token")
+        .get_html("Page confirming a subscription, the link of the confirmation message")
+        .post::<models::Subscriber>("Confirm a subscription with the token of the confirmation message");
+    spec.path("/subscribers/{token}/unsubscribe")
+        .param::<String>("token")
+        .get_html("Page unsubscribing, the link of the messages")
+        .post_no_content("Unsubscribe with the token of the links in the messages");
+}
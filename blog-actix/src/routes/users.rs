@@ -1,54 +1,270 @@
+use crate::cache::Listings;
+use crate::db::WriteConnection;
 use crate::errors::AppError;
-use crate::routes::convert;
-use crate::{models, Pool};
-use actix_web::{web, HttpResponse};
+use crate::mail::valid_email;
+use crate::models::{self, ProfileChanges};
+use crate::openapi::Spec;
+use crate::routes::admin::{authorized, AdminToken};
+use crate::routes::{convert, resource};
+use crate::tokens::{self, TokenUsers};
+use crate::Pools;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::{self, Either};
 use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
+use url::Url;
 
-#[derive(Debug, Serialize, Deserialize)]
+const MAX_USERNAME: usize = 50;
+const MAX_DISPLAY_NAME: usize = 50;
+const MAX_BIO: usize = 500;
+const MAX_URL: usize = 2000;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct UserInput {
     username: String,
+    // where comments on the user's posts are notified, never shown
+    email: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct RenameInput {
+    username: String,
+}
+
+// Header of the response to POST /users carrying the user's token. The token is only sent back
+// this once, it is needed to edit the profile.
+pub const TOKEN_HEADER: &str = "x-user-token";
+
+// A new token for a user, the previous one no longer works
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UserToken {
+    pub token: String,
+}
+
+// Usernames are part of the /users/find/{name} path
+fn valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.chars().count() <= MAX_USERNAME
+        && !username.chars().any(|c| c.is_whitespace() || c.is_control() || c == '/')
+}
+
+// The hash of the token a request acting for a user comes with, None when it has the admin token
+pub(crate) fn credentials(req: &HttpRequest, admin_token: Option<web::Data<AdminToken>>) -> Result<Option<String>, AppError> {
+    if admin_token.is_some_and(|token| authorized(req, &token.0)) {
+        return Ok(None);
+    }
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| Some(tokens::hash(token)))
+        .ok_or(AppError::Unauthorized)
+}
+
+// Fails unless the credentials are the user's, or the admin token
+pub(crate) fn check_owner(conn: &WriteConnection, user_id: i32, credentials: &Option<String>) -> Result<(), AppError> {
+    match credentials {
+        Some(hash) if models::token_hash(conn, user_id)?.as_ref() != Some(hash) => Err(AppError::Unauthorized),
+        _ => Ok(()),
+    }
+}
+
+fn validate(changes: &ProfileChanges) -> Result<(), AppError> {
+    let too_long = |field: &Option<String>, max: usize| field.as_ref().is_some_and(|value| value.chars().count() > max);
+    if too_long(&changes.display_name, MAX_DISPLAY_NAME) {
+        return Err(AppError::InvalidProfile(format!("display_name is over {} characters", MAX_DISPLAY_NAME)));
+    }
+    if too_long(&changes.bio, MAX_BIO) {
+        return Err(AppError::InvalidProfile(format!("bio is over {} characters", MAX_BIO)));
+    }
+
+    for (name, field) in [("website", &changes.website), ("avatar_url", &changes.avatar_url)] {
+        // empty clears the field
+        let value = match field.as_deref() {
+            Some(value) if !value.is_empty() => value,
+            _ => continue,
+        };
+        let valid = value.len() <= MAX_URL
+            && Url::parse(value).is_ok_and(|url| ["http", "https"].contains(&url.scheme()) && url.host_str().is_some());
+        if !valid {
+            return Err(AppError::InvalidProfile(format!("{} must be an http or https URL", name)));
+        }
+    }
+    Ok(())
 }
 
 fn create_user(
     item: web::Json<UserInput>,
-    pool: web::Data<Pool>,
+    pools: web::Data<Pools>,
+    token_users: web::Data<TokenUsers>,
 ) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
-        let username = item.into_inner().username;
-        models::create_user(conn, username.as_str())
-    })
-        .then(convert)
+    let item = item.into_inner();
+    if !valid_username(&item.username) {
+        return Either::A(future::err(AppError::InvalidUsername));
+    }
+    if item.email.as_deref().is_some_and(|email| !valid_email(email)) {
+        return Either::A(future::err(AppError::InvalidEmail));
+    }
+
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            let token = tokens::generate();
+            let hash = tokens::hash(&token);
+            let user = models::create_user(conn, item.username.as_str(), item.email.as_deref(), &hash)?;
+            token_users.set(user.id, hash);
+            Ok((user, token))
+        })
+            .from_err()
+            .map(|(user, token)| HttpResponse::Ok().header(TOKEN_HEADER, token).json(user)),
+    )
 }
 
 fn find_user(
     name: web::Path<String>,
-    pool: web::Data<Pool>,
+    pools: web::Data<Pools>,
 ) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
+    block(move || {
+        let conn = &pools.reader()?;
         let name = name.into_inner();
         let key = models::UserKey::Username(name.as_str());
-        models::find_user(conn, key)
+        models::find_profile(conn, key)
     })
         .then(convert)
 }
 
 fn get_user(
     user_id: web::Path<i32>,
-    pool: web::Data<Pool>,
+    pools: web::Data<Pools>,
 ) -> impl Future<Item=HttpResponse, Error=AppError> {
-    web::block(move || {
-        let conn = &pool.get().unwrap();
+    block(move || {
+        let conn = &pools.reader()?;
         let id = user_id.into_inner();
         let key = models::UserKey::ID(id);
-        models::find_user(conn, key)
+        models::find_profile(conn, key)
     })
         .then(convert)
 }
 
+// Only with the user's own token, or the admin token when one is configured
+fn update_profile(
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+    changes: web::Json<ProfileChanges>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let changes = changes.into_inner();
+    let credentials = match credentials(&req, admin_token).and_then(|c| validate(&changes).map(|_| c)) {
+        Ok(credentials) => credentials,
+        Err(e) => return Either::A(future::err(e)),
+    };
+
+    let user_id = user_id.into_inner();
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            check_owner(conn, user_id, &credentials)?;
+            let profile = models::update_profile(conn, user_id, &changes)?;
+            listings.clear();
+            Ok(profile)
+        })
+            .then(convert),
+    )
+}
+
+// Same credentials as update_profile
+fn rename_user(
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+    input: web::Json<RenameInput>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    listings: web::Data<Listings>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let credentials = match credentials(&req, admin_token) {
+        Ok(credentials) => credentials,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    let input = input.into_inner();
+    if !valid_username(&input.username) {
+        return Either::A(future::err(AppError::InvalidUsername));
+    }
+
+    let user_id = user_id.into_inner();
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            check_owner(conn, user_id, &credentials)?;
+            let profile = models::rename_user(conn, user_id, &input.username)?;
+            listings.clear();
+            Ok(profile)
+        })
+            .then(convert),
+    )
+}
+
+// With the admin token, for the users created before they got tokens and the ones who lost theirs,
+// or with the user's token, to replace it
+fn issue_token(
+    req: HttpRequest,
+    user_id: web::Path<i32>,
+    admin_token: Option<web::Data<AdminToken>>,
+    pools: web::Data<Pools>,
+    token_users: web::Data<TokenUsers>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    let credentials = match credentials(&req, admin_token) {
+        Ok(credentials) => credentials,
+        Err(e) => return Either::A(future::err(e)),
+    };
+
+    let user_id = user_id.into_inner();
+    Either::B(
+        block(move || {
+            let conn = &pools.writer()?;
+            check_owner(conn, user_id, &credentials)?;
+            let token = tokens::generate();
+            let hash = tokens::hash(&token);
+            models::set_token_hash(conn, user_id, &hash)?;
+            token_users.set(user_id, hash);
+            Ok(UserToken { token })
+        })
+            .then(convert),
+    )
+}
+
 pub fn configure(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/users").route(web::post().to_async(create_user)))
-        .service(web::resource("/users/find/{name}").route(web::get().to_async(find_user)))
-        .service(web::resource("/users/{id}").route(web::get().to_async(get_user)));
-}
\ No newline at end of file
+    cfg.service(resource("/users").route(web::post().to_async(create_user)))
+        .service(resource("/users/find/{name}").route(web::get().to_async(find_user)))
+        .service(
+            resource("/users/{id}")
+                .route(web::get().to_async(get_user))
+                .route(web::patch().to_async(update_profile)),
+        )
+        .service(resource("/users/{id}/rename").route(web::post().to_async(rename_user)))
+        .service(resource("/users/{id}/token").route(web::post().to_async(issue_token)));
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/users")
+        .post_json::<UserInput, models::User>("Create a user, its token is in the X-User-Token header");
+    spec.path("/users/find/{name}")
+        .param::<String>("name")
+        .get::<models::Profile>("Find a user by username");
+    spec.path("/users/{id}")
+        .param::<i32>("id")
+        .get::<models::Profile>("Find a user by id")
+        .bearer_auth()
+        .patch_json::<ProfileChanges, models::Profile>("Edit a user's profile, with the user's token");
+    spec.path("/users/{id}/rename")
+        .param::<i32>("id")
+        .bearer_auth()
+        .post_json::<RenameInput, models::Profile>("Change a user's username, with the user's token");
+    spec.path("/users/{id}/token")
+        .param::<i32>("id")
+        .bearer_auth()
+        .post::<UserToken>("Replace a user's token, with the user's token or the admin token");
+}
@@ -0,0 +1,130 @@
+use crate::errors::AppError;
+use crate::models::{self, Event};
+use crate::openapi::Spec;
+use crate::routes::admin::{authorized, AdminToken};
+use crate::routes::{convert, resource};
+use crate::{tokens, Pools};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::{self, Either};
+use futures::Future;
+use schemars::JsonSchema;
+use server_common::metrics::block;
+use url::Url;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct WebhookInput {
+    url: String,
+    events: Vec<Event>,
+    // generated when missing, either way it is only sent back once
+    secret: Option<String>,
+}
+
+fn validate(input: &WebhookInput) -> Result<(), AppError> {
+    let url = Url::parse(&input.url).map_err(|e| AppError::InvalidWebhook(e.to_string()))?;
+    if !["http", "https"].contains(&url.scheme()) || url.host_str().is_none() {
+        return Err(AppError::InvalidWebhook("the URL must be http or https".to_owned()));
+    }
+    if input.events.is_empty() {
+        return Err(AppError::InvalidWebhook("no events".to_owned()));
+    }
+    if input.secret.as_ref().is_some_and(|secret| secret.is_empty()) {
+        return Err(AppError::InvalidWebhook("empty secret".to_owned()));
+    }
+    Ok(())
+}
+
+fn create_webhook(
+    req: HttpRequest,
+    input: web::Json<WebhookInput>,
+    token: web::Data<AdminToken>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    if !authorized(&req, &token.0) {
+        return Either::A(future::err(AppError::Unauthorized));
+    }
+    let input = input.into_inner();
+    if let Err(e) = validate(&input) {
+        return Either::A(future::err(e));
+    }
+
+    Either::B(
+        block(move || {
+            let secret = input.secret.unwrap_or_else(tokens::generate);
+            models::create_webhook(&pools.writer()?, &input.url, &secret, &input.events)
+        })
+            .then(convert),
+    )
+}
+
+fn list_webhooks(
+    req: HttpRequest,
+    token: web::Data<AdminToken>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    if !authorized(&req, &token.0) {
+        return Either::A(future::err(AppError::Unauthorized));
+    }
+
+    Either::B(block(move || models::webhooks(&pools.reader()?)).then(convert))
+}
+
+fn delete_webhook(
+    req: HttpRequest,
+    webhook_id: web::Path<i32>,
+    token: web::Data<AdminToken>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    if !authorized(&req, &token.0) {
+        return Either::A(future::err(AppError::Unauthorized));
+    }
+
+    Either::B(
+        block(move || models::delete_webhook(&pools.writer()?, webhook_id.into_inner()))
+            .map(|_| HttpResponse::NoContent().finish())
+            .from_err(),
+    )
+}
+
+fn webhook_deliveries(
+    req: HttpRequest,
+    webhook_id: web::Path<i32>,
+    token: web::Data<AdminToken>,
+    pools: web::Data<Pools>,
+) -> impl Future<Item=HttpResponse, Error=AppError> {
+    if !authorized(&req, &token.0) {
+        return Either::A(future::err(AppError::Unauthorized));
+    }
+
+    Either::B(
+        block(move || models::webhook_deliveries(&pools.reader()?, webhook_id.into_inner()))
+            .then(convert),
+    )
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        resource("/admin/webhooks")
+            .route(web::post().to_async(create_webhook))
+            .route(web::get().to_async(list_webhooks)),
+    )
+        .service(resource("/admin/webhooks/{id}").route(web::delete().to_async(delete_webhook)))
+        .service(
+            resource("/admin/webhooks/{id}/deliveries")
+                .route(web::get().to_async(webhook_deliveries)),
+        );
+}
+
+pub fn openapi(spec: &mut Spec) {
+    spec.path("/admin/webhooks")
+        .bearer_auth()
+        .post_json::<WebhookInput, models::Webhook>("Register a webhook, only available when an admin token is set")
+        .get::<Vec<models::Webhook>>("List the webhooks, without their secrets");
+    spec.path("/admin/webhooks/{id}")
+        .param::<i32>("id")
+        .bearer_auth()
+        .delete("Remove a webhook and its pending deliveries");
+    spec.path("/admin/webhooks/{id}/deliveries")
+        .param::<i32>("id")
+        .bearer_auth()
+        .get::<Vec<models::Delivery>>("List the latest deliveries of a webhook");
+}
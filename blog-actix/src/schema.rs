@@ -1,9 +1,44 @@
+table! {
+    changes (id) {
+        id -> BigInt,
+        entity -> Text,
+        entity_id -> Integer,
+        operation -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     comments (id) {
         id -> Integer,
         user_id -> Integer,
         post_id -> Integer,
         body -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    follows (follower_id, followee_id) {
+        follower_id -> Integer,
+        followee_id -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    outbox (id) {
+        id -> Integer,
+        recipient -> Text,
+        template -> Text,
+        data -> Text,
+        attempts -> Integer,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        sent_at -> Nullable<Timestamp>,
+        failed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
     }
 }
 
@@ -14,6 +49,54 @@ table! {
         title -> Text,
         body -> Text,
         published -> Bool,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        published_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    reactions (target, target_id, kind, user_id) {
+        target -> Text,
+        target_id -> Integer,
+        kind -> Text,
+        user_id -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    subscribers (id) {
+        id -> Integer,
+        email -> Text,
+        token -> Text,
+        created_at -> Timestamp,
+        confirmed_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    table_versions (name) {
+        name -> Text,
+        version -> BigInt,
+        updated_at -> BigInt,
+    }
+}
+
+table! {
+    user_emails (user_id) {
+        user_id -> Integer,
+        email -> Text,
+    }
+}
+
+table! {
+    username_history (id) {
+        id -> Integer,
+        user_id -> Integer,
+        username -> Text,
+        username_key -> Text,
+        renamed_at -> Timestamp,
     }
 }
 
@@ -21,15 +104,62 @@ table! {
     users (id) {
         id -> Integer,
         username -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        display_name -> Nullable<Text>,
+        bio -> Nullable<Text>,
+        website -> Nullable<Text>,
+        avatar_url -> Nullable<Text>,
+        token_hash -> Nullable<Text>,
+        username_key -> Nullable<Text>,
+    }
+}
+
+table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        webhook_id -> Integer,
+        event -> Text,
+        payload -> Text,
+        attempts -> Integer,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+        failed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Integer,
+        url -> Text,
+        secret -> Text,
+        events -> Text,
+        created_at -> Timestamp,
     }
 }
 
 joinable!(comments -> posts (post_id));
 joinable!(comments -> users (user_id));
 joinable!(posts -> users (user_id));
+joinable!(reactions -> users (user_id));
+joinable!(user_emails -> users (user_id));
+joinable!(username_history -> users (user_id));
+joinable!(webhook_deliveries -> webhooks (webhook_id));
 
 allow_tables_to_appear_in_same_query!(
+    changes,
     comments,
+    follows,
+    outbox,
     posts,
+    reactions,
+    subscribers,
+    table_versions,
+    user_emails,
+    username_history,
     users,
+    webhook_deliveries,
+    webhooks,
 );
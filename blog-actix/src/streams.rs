@@ -0,0 +1,145 @@
+use crate::models::{Comment, Post, User};
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::Bytes;
+use actix_web::{error, HttpResponse};
+use futures::sync::mpsc::{self, Sender};
+use futures::Stream;
+use lazy_static::lazy_static;
+use server_common::prometheus::{register_int_gauge_vec, IntGaugeVec};
+use std::sync::{Arc, Mutex};
+use server_common::shutdown::Periodic;
+use std::time::Duration;
+
+lazy_static! {
+    static ref SUBSCRIBERS: IntGaugeVec = register_int_gauge_vec!(
+        "stream_subscribers",
+        "Clients connected to the event streams, by stream",
+        &["stream"]
+    )
+    .unwrap();
+}
+
+// Events held for a client before it is dropped, a client this far behind is reading slower than
+// the events come, and would otherwise make the server hold on to all of them
+const BUFFERED_EVENTS: usize = 64;
+
+// Sent when nothing else was, so proxies keep the connection open and the ones closed by clients
+// are noticed
+const HEARTBEAT: &[u8] = b":\n\n";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Topic {
+    // the comments of one post
+    Comments(i32),
+    PublishedPosts,
+}
+
+impl Topic {
+    fn stream(self) -> &'static str {
+        match self {
+            Topic::Comments(_) => "comments",
+            Topic::PublishedPosts => "posts",
+        }
+    }
+}
+
+struct Subscriber {
+    topic: Topic,
+    sender: Sender<Bytes>,
+}
+
+// Server-Sent Events streams of the new comments of a post and of the newly published posts,
+// shared by the workers. Events are pushed by the handlers once the write is committed, clients
+// missing some while reconnecting catch up with the listings.
+#[derive(Clone, Default)]
+pub struct Streams {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl Streams {
+    pub fn new() -> Self {
+        Streams::default()
+    }
+
+    pub fn schedule(&self, heartbeat: Duration) -> Periodic {
+        let streams = self.clone();
+        Periodic::spawn("Stream heartbeats", heartbeat, move || {
+            streams.send(|_| true, Bytes::from_static(HEARTBEAT));
+        })
+    }
+
+    // The response streaming the events of the topic until the client goes away
+    pub(crate) fn subscribe(&self, topic: Topic) -> HttpResponse {
+        let (sender, receiver) = mpsc::channel(BUFFERED_EVENTS);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.push(Subscriber { topic, sender });
+        SUBSCRIBERS.with_label_values(&[topic.stream()]).inc();
+
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .set(CacheControl(vec![CacheDirective::NoCache]))
+            // or nginx holds the events back
+            .header("X-Accel-Buffering", "no")
+            .streaming(receiver.map_err(|()| error::ErrorInternalServerError("event stream closed")))
+    }
+
+    pub(crate) fn has_subscribers(&self, topic: Topic) -> bool {
+        let subscribers = self.subscribers.lock().unwrap();
+        subscribers.iter().any(|subscriber| subscriber.topic == topic)
+    }
+
+    // Sent as listed by GET /posts/{id}/comments
+    pub(crate) fn comment_added(&self, comment: &Comment, author: &User) {
+        let topic = Topic::Comments(comment.post_id);
+        self.send(|t| t == topic, event("comment", comment.id, &(comment, author)));
+    }
+
+    // Sent as listed by GET /posts, without the comments
+    pub(crate) fn post_published(&self, post: &Post, author: &User) {
+        self.send(|t| t == Topic::PublishedPosts, event("post", post.id, &(post, author)));
+    }
+
+    // Drops the subscribers whose client is gone or fell behind, ending their response. The ones
+    // that fell behind reconnect and catch up with the listings.
+    fn send<F: Fn(Topic) -> bool>(&self, to: F, message: Bytes) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain_mut(|subscriber| {
+            if !to(subscriber.topic) || subscriber.sender.try_send(message.clone()).is_ok() {
+                return true;
+            }
+            SUBSCRIBERS.with_label_values(&[subscriber.topic.stream()]).dec();
+            false
+        });
+    }
+}
+
+fn event<T: serde::Serialize>(name: &str, id: i32, data: &T) -> Bytes {
+    // serde_json doesn't break lines, the data fits on the one `data:` line
+    let data = serde_json::to_string(data).unwrap_or_default();
+    Bytes::from(format!("event: {}\nid: {}\ndata: {}\n\n", name, id, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Streams, Topic, BUFFERED_EVENTS, HEARTBEAT};
+    use actix_web::web::Bytes;
+
+    #[test]
+    fn subscribers_falling_behind_dropped() {
+        let streams = Streams::new();
+        // the response isn't read from
+        let _posts = streams.subscribe(Topic::PublishedPosts);
+        let _comments = streams.subscribe(Topic::Comments(1));
+
+        for _ in 0..BUFFERED_EVENTS {
+            streams.send(|topic| topic == Topic::PublishedPosts, Bytes::from_static(HEARTBEAT));
+        }
+        assert!(streams.has_subscribers(Topic::PublishedPosts));
+
+        for _ in 0..2 {
+            streams.send(|topic| topic == Topic::PublishedPosts, Bytes::from_static(HEARTBEAT));
+        }
+        assert!(!streams.has_subscribers(Topic::PublishedPosts));
+        assert!(streams.has_subscribers(Topic::Comments(1)));
+    }
+}
@@ -0,0 +1,41 @@
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serializer};
+
+// The timestamps are stored without a time zone, so they are sent as RFC 3339 with a Z for
+// clients not to read them as local time
+pub fn serialize<S: Serializer>(timestamp: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+    let utc = Utc.from_utc_datetime(timestamp);
+    serializer.serialize_str(&utc.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDateTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    DateTime::parse_from_rfc3339(&s)
+        .map(|timestamp| timestamp.naive_utc())
+        .map_err(serde::de::Error::custom)
+}
+
+// For the timestamps of events that may not have happened yet
+pub mod option {
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        timestamp: &Option<NaiveDateTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match timestamp {
+            Some(timestamp) => super::serialize(timestamp, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NaiveDateTime>, D::Error> {
+        #[derive(Deserialize)]
+        struct Timestamp(#[serde(with = "super")] NaiveDateTime);
+
+        Ok(Option::<Timestamp>::deserialize(deserializer)?.map(|timestamp| timestamp.0))
+    }
+}
@@ -0,0 +1,50 @@
+use crate::errors::AppError;
+use crate::models;
+use diesel::SqliteConnection;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// 256 random bits in hex, for the users' and subscribers' tokens and the secrets of the webhooks
+// registered without one
+pub fn generate() -> String {
+    let mut token = [0; 32];
+    SystemRandom::new()
+        .fill(&mut token)
+        .expect("No system random number generator");
+    hex(&token)
+}
+
+// What is stored of a token, the token itself is only known to its owner
+pub fn hash(token: &str) -> String {
+    hex(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// The user holding each token, by hash, so the rate limiter tells who a request is from without
+// querying the database on the event loop. Loaded when the server starts, the handlers handing
+// out tokens keep it up to date. Clones share the map.
+#[derive(Clone, Default)]
+pub struct TokenUsers(Arc<RwLock<HashMap<String, i32>>>);
+
+impl TokenUsers {
+    pub fn load(conn: &SqliteConnection) -> Result<Self, AppError> {
+        let users = models::token_hashes(conn)?.into_iter().collect();
+        Ok(TokenUsers(Arc::new(RwLock::new(users))))
+    }
+
+    pub fn user(&self, hash: &str) -> Option<i32> {
+        self.0.read().unwrap().get(hash).copied()
+    }
+
+    // The token the user had before stops counting as theirs
+    pub fn set(&self, user_id: i32, hash: String) {
+        let mut users = self.0.write().unwrap();
+        users.retain(|_, id| *id != user_id);
+        users.insert(hash, user_id);
+    }
+}
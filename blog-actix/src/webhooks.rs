@@ -0,0 +1,200 @@
+use crate::errors::AppError;
+use crate::models::{self, PendingDelivery};
+use crate::retry::{Queued, Retries};
+use crate::{tokens, Pools};
+use actix_rt::System;
+use actix_web::http::header;
+use awc::{Client, Connector};
+use futures::future::{self, Either};
+use futures::{stream, Future, Stream};
+use lazy_static::lazy_static;
+use ring::{digest, hmac};
+use rustls::ClientConfig;
+use server_common::prometheus::{register_int_counter_vec, IntCounterVec};
+use std::sync::Arc;
+use server_common::shutdown::Periodic;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Deliveries attempted per pass over the queue
+const BATCH_SIZE: i64 = 100;
+// Webhooks sent to at the same time
+const CONCURRENCY: usize = 16;
+
+lazy_static! {
+    static ref WEBHOOK_ATTEMPTS: IntCounterVec = register_int_counter_vec!(
+        "webhook_attempts_total",
+        "Attempts at delivering webhook events, by event and result",
+        &["event", "result"]
+    )
+    .unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    // how often the queue is checked for deliveries due
+    pub poll_interval: Duration,
+    // a delivery still failing after this many attempts is given up
+    pub max_attempts: i32,
+    // wait before the second attempt, doubled after every failure
+    pub backoff: Duration,
+    // for connecting, and for the whole request
+    pub timeout: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            poll_interval: Duration::from_secs(1),
+            max_attempts: 8,
+            backoff: Duration::from_secs(10),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+// Sends the queued deliveries to the webhooks. Each request carries the event in X-Blog-Event, the
+// id of the delivery in X-Blog-Delivery, so retries can be told apart from new events, and
+// X-Blog-Signature, the HMAC-SHA256 of `{X-Blog-Timestamp}.{body}` keyed with the webhook secret.
+#[derive(Clone)]
+pub struct Webhooks {
+    pools: Pools,
+    config: WebhookConfig,
+    tls: Arc<ClientConfig>,
+}
+
+impl Webhooks {
+    pub fn new(pools: Pools, config: WebhookConfig) -> Self {
+        let mut tls = ClientConfig::new();
+        tls.root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+        Webhooks {
+            pools,
+            config,
+            tls: Arc::new(tls),
+        }
+    }
+
+    pub fn schedule(&self) -> Periodic {
+        let webhooks = self.clone();
+        Periodic::spawn("Webhook deliveries", self.config.poll_interval, move || {
+            if let Err(e) = webhooks.deliver_due() {
+                log::error!("Webhook deliveries failed: {}", e);
+            }
+        })
+    }
+
+    // Makes an attempt at every delivery due, returns how many succeeded. The deliveries of a
+    // webhook are sent one after the other, in the order of the events, while the webhooks are
+    // sent to concurrently so a slow one only holds up its own. A failed delivery holds up the
+    // ones after it until it goes through or is given up.
+    pub fn deliver_due(&self) -> Result<usize, AppError> {
+        let pending = models::pending_deliveries(&self.pools.reader()?, BATCH_SIZE)?;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let mut queues: Vec<Vec<PendingDelivery>> = vec![];
+        for delivery in pending {
+            match queues.iter_mut().find(|queue| queue[0].webhook_id == delivery.webhook_id) {
+                Some(queue) => queue.push(delivery),
+                None => queues.push(vec![delivery]),
+            }
+        }
+
+        // the failures end up in the outcomes, the future itself never fails
+        let outcomes = System::new("webhooks")
+            .block_on(future::lazy(|| {
+                let client = self.client();
+                stream::iter_ok(queues)
+                    .map(move |queue| self.send_all(client.clone(), queue))
+                    .buffer_unordered(CONCURRENCY)
+                    .concat2()
+            }))
+            .unwrap_or_default();
+
+        let retries = Retries {
+            name: "webhook delivery",
+            counter: &WEBHOOK_ATTEMPTS,
+            success: "delivered",
+            max_attempts: self.config.max_attempts,
+            backoff: self.config.backoff,
+        };
+        retries.record(outcomes, |id, attempt| models::record_attempt(&self.pools.writer()?, id, attempt))
+    }
+
+    fn client(&self) -> Client {
+        let connector = Connector::new()
+            .rustls(self.tls.clone())
+            .timeout(self.config.timeout)
+            .finish();
+        Client::build()
+            .connector(connector)
+            .timeout(self.config.timeout)
+            .header(header::USER_AGENT, concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+            .finish()
+    }
+
+    // Sends the deliveries of a webhook in order up to the first failing, with the outcome of
+    // each sent
+    fn send_all(
+        &self,
+        client: Client,
+        queue: Vec<PendingDelivery>,
+    ) -> impl Future<Item=Vec<(PendingDelivery, Result<(), String>)>, Error=()> {
+        stream::iter_ok(queue).fold(vec![], move |mut outcomes: Vec<(PendingDelivery, Result<(), String>)>, delivery| {
+            // left due, the next pass skips it while the failed one waits for its retry
+            if outcomes.last().is_some_and(|(_, outcome)| outcome.is_err()) {
+                return Either::A(future::ok(outcomes));
+            }
+            Either::B(send(&client, &delivery).then(move |outcome| {
+                outcomes.push((delivery, outcome));
+                Ok(outcomes)
+            }))
+        })
+    }
+}
+
+// The response isn't read, only its status, and the connection isn't kept for the next delivery
+fn send(client: &Client, delivery: &PendingDelivery) -> impl Future<Item=(), Error=String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    client
+        .post(&delivery.url)
+        .force_close()
+        .content_type("application/json")
+        .header("X-Blog-Event", delivery.event.as_str())
+        .header("X-Blog-Delivery", delivery.id.to_string())
+        .header("X-Blog-Timestamp", timestamp.to_string())
+        .header("X-Blog-Signature", signature(&delivery.secret, timestamp, &delivery.payload))
+        .send_body(delivery.payload.clone())
+        .then(|response| match response {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("HTTP status {}", response.status().as_u16())),
+            Err(e) => Err(e.to_string()),
+        })
+}
+
+// Value of X-Blog-Signature, receivers compute the same from the body and X-Blog-Timestamp
+pub fn signature(secret: &str, timestamp: u64, payload: &str) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+    let message = format!("{}.{}", timestamp, payload);
+    format!("sha256={}", tokens::hex(hmac::sign(&key, message.as_bytes()).as_ref()))
+}
+
+impl Queued for PendingDelivery {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn attempts(&self) -> i32 {
+        self.attempts
+    }
+
+    fn kind(&self) -> &str {
+        &self.event
+    }
+}
@@ -0,0 +1,119 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::TestRequest;
+use blog_actix::{BackupConfig, Backups, Snapshot};
+use common::{NewUser, ADMIN_TOKEN};
+
+#[test]
+fn create_backup_requires_the_admin_token() {
+    let mut app = common::init();
+
+    let res = app.post("/admin/backups");
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+    let res = app.call(
+        TestRequest::post()
+            .uri("/admin/backups")
+            .header(header::AUTHORIZATION, "Bearer wrong"),
+    );
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn create_backup_keeps_the_newest_snapshots() {
+    let mut app = common::init();
+    NewUser::new().create(&mut app);
+
+    let mut files = vec![];
+    for _ in 0..3 {
+        let res = app.call(
+            TestRequest::post()
+                .uri("/admin/backups")
+                .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN)),
+        );
+        assert_eq!(res.status, StatusCode::OK);
+
+        let snapshot: Snapshot = res.json();
+        assert!(snapshot.size > 0);
+        files.push(snapshot.file);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+
+    let mut kept = std::fs::read_dir(app.dir.path().join("backups"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    kept.sort();
+    assert_eq!(kept, files[1..].to_vec());
+}
+
+fn backups(app_dir: &std::path::Path, retain: usize) -> Backups {
+    let database_url = app_dir.join("blog.sqlite3").to_str().unwrap().to_owned();
+    Backups::new(database_url, BackupConfig {
+        dir: app_dir.join("backups"),
+        interval: None,
+        retain,
+        admin_token: ADMIN_TOKEN.to_owned(),
+    })
+}
+
+#[test]
+fn snapshots_taken_together_get_their_own_files() {
+    let app = common::init();
+    let backups = backups(app.dir.path(), 10);
+
+    let mut files = (0..5).map(|_| backups.snapshot().unwrap().file).collect::<Vec<_>>();
+    files.sort();
+    files.dedup();
+    assert_eq!(files.len(), 5);
+}
+
+#[test]
+fn snapshot_kept_without_retention() {
+    let app = common::init();
+    let backups = backups(app.dir.path(), 0);
+
+    backups.snapshot().unwrap();
+    let snapshot = backups.snapshot().unwrap();
+    assert!(snapshot.size > 0);
+    let kept = std::fs::read_dir(app.dir.path().join("backups"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(kept, vec![snapshot.file]);
+}
+
+#[test]
+fn failed_snapshot_leaves_no_file() {
+    let app = common::init();
+    let not_a_database = app.dir.path().join("notes.txt");
+    std::fs::write(&not_a_database, "not a database".repeat(1000)).unwrap();
+    let backups = Backups::new(not_a_database.to_str().unwrap().to_owned(), BackupConfig {
+        dir: app.dir.path().join("backups"),
+        interval: None,
+        retain: 10,
+        admin_token: ADMIN_TOKEN.to_owned(),
+    });
+
+    assert!(backups.snapshot().is_err());
+    let left = std::fs::read_dir(app.dir.path().join("backups")).unwrap().count();
+    assert_eq!(left, 0);
+}
+
+#[test]
+fn openapi_document_and_viewer() {
+    let mut app = common::init();
+
+    let res = app.get("/openapi.json");
+    assert_eq!(res.status, StatusCode::OK);
+    let doc: serde_json::Value = res.json();
+    assert!(doc["paths"]["/posts"]["get"].is_object());
+
+    let res = app.get("/docs");
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(String::from_utf8_lossy(&res.body).contains("/openapi.json"));
+}
@@ -0,0 +1,121 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use blog_actix::{CacheConfig, PostEntry, UserPostEntry};
+use common::{NewComment, NewPost, NewUser, TestApp};
+use diesel::connection::SimpleConnection;
+use std::thread;
+use std::time::Duration;
+
+// Changes the posts behind the back of the handlers, which the caches don't see
+fn retitle_posts<S>(app: &TestApp<S>, title: &str) {
+    app.pools
+        .writer()
+        .unwrap()
+        .batch_execute(&format!("UPDATE posts SET title = '{}'", title))
+        .unwrap();
+}
+
+fn titles(posts: Vec<PostEntry>) -> Vec<String> {
+    posts.into_iter().map(|((post, _), _)| post.title).collect()
+}
+
+fn user_titles(posts: Vec<UserPostEntry>) -> Vec<String> {
+    posts.into_iter().map(|(post, _)| post.title).collect()
+}
+
+#[test]
+fn listings_served_from_cache() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+    NewPost::new().title("first").published().create(&mut app, &user);
+    let uri = format!("/users/{}/posts", user.id);
+
+    assert_eq!(titles(app.get("/posts").json()), vec!["first"]);
+    assert_eq!(user_titles(app.get(&uri).json()), vec!["first"]);
+
+    retitle_posts(&app, "edited");
+    assert_eq!(titles(app.get("/posts").json()), vec!["first"]);
+    assert_eq!(user_titles(app.get(&uri).json()), vec!["first"]);
+}
+
+#[test]
+fn writes_invalidate_listings() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+    let other = NewUser::new().create(&mut app);
+    let post = NewPost::new().title("first").published().create(&mut app, &user);
+    let uri = format!("/users/{}/posts", user.id);
+    let other_uri = format!("/users/{}/posts", other.id);
+    app.get("/posts");
+    app.get(&uri);
+
+    // a draft only shows in its author's listing
+    NewPost::new().title("draft").create(&mut app, &other);
+    retitle_posts(&app, "edited");
+    assert_eq!(titles(app.get("/posts").json()), vec!["first"]);
+    assert_eq!(user_titles(app.get(&other_uri).json()), vec!["edited"]);
+
+    NewPost::new().title("second").published().create(&mut app, &user);
+    assert_eq!(titles(app.get("/posts").json()), vec!["second", "edited"]);
+    assert_eq!(user_titles(app.get(&uri).json()), vec!["second", "edited"]);
+
+    retitle_posts(&app, "again");
+    NewComment::new().create(&mut app, &post, &other);
+    assert_eq!(titles(app.get("/posts").json()), vec!["again", "again"]);
+    assert_eq!(user_titles(app.get(&uri).json()), vec!["again", "again"]);
+}
+
+#[test]
+fn entries_expire_after_ttl() {
+    let mut app = common::init_with_cache(CacheConfig {
+        capacity: 10,
+        ttl: Duration::from_millis(50),
+    });
+    let user = NewUser::new().create(&mut app);
+    NewPost::new().title("first").published().create(&mut app, &user);
+    app.get("/posts");
+
+    retitle_posts(&app, "edited");
+    assert_eq!(titles(app.get("/posts").json()), vec!["first"]);
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(titles(app.get("/posts").json()), vec!["edited"]);
+}
+
+#[test]
+fn least_recently_used_listing_evicted() {
+    let mut app = common::init_with_cache(CacheConfig {
+        capacity: 2,
+        ttl: Duration::from_secs(60),
+    });
+    let users: Vec<_> = (0..3).map(|_| NewUser::new().create(&mut app)).collect();
+    for user in &users {
+        NewPost::new().title("first").create(&mut app, user);
+    }
+    let uri = |i: usize| format!("/users/{}/posts", users[i].id);
+
+    app.get(&uri(0));
+    app.get(&uri(1));
+    app.get(&uri(0));
+    // makes room by dropping the listing of users[1]
+    app.get(&uri(2));
+
+    retitle_posts(&app, "edited");
+    assert_eq!(user_titles(app.get(&uri(0)).json()), vec!["first"]);
+    assert_eq!(user_titles(app.get(&uri(2)).json()), vec!["first"]);
+    assert_eq!(user_titles(app.get(&uri(1)).json()), vec!["edited"]);
+}
+
+#[test]
+fn hits_and_misses_counted() {
+    let mut app = common::init();
+    app.get("/posts");
+    app.get("/posts");
+
+    let metrics = String::from_utf8(app.get("/metrics").body).unwrap();
+    assert!(metrics.contains(r#"cache_requests_total{cache="all_posts",result="hit"}"#));
+    assert!(metrics.contains(r#"cache_requests_total{cache="all_posts",result="miss"}"#));
+    assert!(metrics.contains(r#"cache_entries{cache="all_posts"}"#));
+}
@@ -0,0 +1,109 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use blog_actix::routes::changes::Changes;
+use blog_actix::{Entity, Operation, Profile};
+use common::{NewComment, NewPost, NewUser};
+use serde_json::json;
+
+#[test]
+fn changes_list_writes_in_order() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+    let comment = NewComment::new().create(&mut app, &post, &frank);
+
+    let res = app.get("/changes");
+    assert_eq!(res.status, StatusCode::OK);
+    let page: Changes = res.json();
+    assert!(!page.more);
+
+    let changes: Vec<_> = page
+        .changes
+        .iter()
+        .map(|change| (change.entity, change.entity_id, change.operation))
+        .collect();
+    assert_eq!(changes, vec![
+        (Entity::User, frank.id, Operation::Created),
+        (Entity::Post, post.id, Operation::Created),
+        (Entity::Post, post.id, Operation::Updated),
+        (Entity::Comment, comment.id, Operation::Created),
+    ]);
+    let ids: Vec<_> = page.changes.iter().map(|change| change.id).collect();
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(page.next, ids[3].to_string());
+
+    // the records are sent as they are now, users with their profile
+    let profile: Profile = app.get(&format!("/users/{}", frank.id)).json();
+    assert_eq!(page.changes[0].data, Some(json!(profile)));
+    assert_eq!(page.changes[1].data.as_ref().unwrap()["published"], true);
+}
+
+#[test]
+fn drafts_not_sent() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let draft = NewPost::new().create(&mut app, &frank);
+
+    let page: Changes = app.get("/changes").json();
+    let change = page.changes.last().unwrap();
+    assert_eq!((change.entity, change.entity_id), (Entity::Post, draft.id));
+    assert_eq!(change.data, None);
+
+    app.post_json(&format!("/posts/{}/publish", draft.id), json!({}));
+    let page: Changes = app.get("/changes").json();
+    assert!(page.changes.last().unwrap().data.is_some());
+}
+
+#[test]
+fn changes_are_paginated() {
+    let mut app = common::init();
+    for _ in 0..3 {
+        NewUser::new().create(&mut app);
+    }
+
+    let page: Changes = app.get("/changes?limit=2").json();
+    assert_eq!(page.changes.len(), 2);
+    assert!(page.more);
+
+    let page: Changes = app.get(&format!("/changes?limit=2&since={}", page.next)).json();
+    assert_eq!(page.changes.len(), 1);
+    assert!(!page.more);
+
+    // nothing new, the token stays the same
+    let next = page.next;
+    let page: Changes = app.get(&format!("/changes?since={}", next)).json();
+    assert!(page.changes.is_empty());
+    assert_eq!(page.next, next);
+
+    let frank = NewUser::new().create(&mut app);
+    let page: Changes = app.get(&format!("/changes?since={}", next)).json();
+    assert_eq!(page.changes.len(), 1);
+    assert_eq!(page.changes[0].entity_id, frank.id);
+}
+
+#[test]
+fn failed_writes_are_not_logged() {
+    let mut app = common::init();
+    NewUser::new().username("frank").create(&mut app);
+
+    let res = app.post_json("/users", json!({ "username": "frank" }));
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = app.post_json("/posts/42/comments", json!({ "user_id": 1, "body": "hi" }));
+    assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let page: Changes = app.get("/changes").json();
+    assert_eq!(page.changes.len(), 1);
+}
+
+#[test]
+fn invalid_sync_token() {
+    let mut app = common::init();
+
+    let res = app.get("/changes?since=yesterday");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "Invalid sync token");
+}
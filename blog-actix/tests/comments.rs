@@ -0,0 +1,83 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use blog_actix::{Comment, CommentWithAuthor, PostWithComment};
+use common::{NewComment, NewPost, NewUser};
+use serde_json::json;
+
+#[test]
+fn add_comment() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+
+    let res = app.post_json(
+        &format!("/posts/{}/comments", post.id),
+        json!({ "user_id": bob.id, "body": "Hi Frank" }),
+    );
+    assert_eq!(res.status, StatusCode::OK);
+
+    let comment: Comment = res.json();
+    assert_eq!(comment.user_id, bob.id);
+    assert_eq!(comment.post_id, post.id);
+    assert_eq!(comment.body, "Hi Frank");
+}
+
+#[test]
+fn add_comment_with_missing_references() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().create(&mut app, &frank);
+
+    let res = app.post_json("/posts/42/comments", json!({ "user_id": frank.id, "body": "Hi" }));
+    assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let res = app.post_json(&format!("/posts/{}/comments", post.id), json!({ "user_id": 42, "body": "Hi" }));
+    assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(res.err(), "This record refers to a record that does not exist");
+}
+
+#[test]
+fn post_comments_newest_first() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+    let first = NewComment::new().create(&mut app, &post, &bob);
+    let second = NewComment::new().body("Thanks Bob").create(&mut app, &post, &frank);
+
+    let res = app.get(&format!("/posts/{}/comments", post.id));
+    assert_eq!(res.status, StatusCode::OK);
+
+    let comments: Vec<CommentWithAuthor> = res.json();
+    let ids = comments.iter().map(|(comment, _)| comment.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![second.id, first.id]);
+    assert_eq!(comments[0].1, frank);
+
+    let res = app.get("/posts/42/comments");
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.json::<Vec<CommentWithAuthor>>().is_empty());
+}
+
+#[test]
+fn user_comments_include_the_post() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    let post = NewPost::new().title("Frank says hello").create(&mut app, &frank);
+    let comment = NewComment::new().create(&mut app, &post, &bob);
+
+    let res = app.get(&format!("/users/{}/comments", bob.id));
+    assert_eq!(res.status, StatusCode::OK);
+
+    let comments: Vec<(Comment, PostWithComment)> = res.json();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].0.id, comment.id);
+    assert_eq!(comments[0].1.id, post.id);
+    assert_eq!(comments[0].1.title, "Frank says hello");
+    assert!(!comments[0].1.published);
+}
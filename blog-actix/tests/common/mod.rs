@@ -0,0 +1,292 @@
+#![allow(dead_code)]
+
+use actix_http::Request;
+use actix_web::dev::{Body, ResponseBody, Service, ServiceResponse};
+use actix_web::http::{header, HeaderMap, Method, StatusCode};
+use actix_web::{test, Error};
+use blog_actix::routes::users::TOKEN_HEADER;
+use blog_actix::{AppState, BackupConfig, Backups, CacheConfig, Comment, Listings, Pools, Post, Streams, SwaggerUi, TokenUsers, User};
+use diesel::{Connection, SqliteConnection};
+use futures::{future, Stream};
+use server_common::cors::Cors;
+use server_common::rate_limit::RateLimit;
+use server_common::security::SecurityHeaders;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tempfile::TempDir;
+
+embed_migrations!("migrations");
+
+pub const ADMIN_TOKEN: &str = "secret";
+pub const ALLOWED_ORIGIN: &str = "https://front.example";
+
+static FIXTURE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// The App Blog::run serves, on a freshly migrated database in a temporary directory
+pub struct TestApp<S> {
+    service: S,
+    pub pools: Pools,
+    // removed together with the database when the test ends
+    pub dir: TempDir,
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+// A user and the token handed out with it
+pub struct Owner {
+    pub user: User,
+    pub token: String,
+}
+
+// The body of a response streaming Server-Sent Events, read one chunk at a time
+pub struct Events {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    body: ResponseBody<Body>,
+}
+
+impl Events {
+    // Waits for the next chunk, which holds one event
+    pub fn next(&mut self) -> String {
+        let body = &mut self.body;
+        let chunk = test::block_on(future::poll_fn(|| body.poll())).unwrap();
+        String::from_utf8(chunk.expect("event stream closed").to_vec()).unwrap()
+    }
+}
+
+impl Response {
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| {
+            panic!("{}: {}", e, String::from_utf8_lossy(&self.body))
+        })
+    }
+
+    // The user POST /users answered with, and the token sent along
+    pub fn owner(&self) -> Owner {
+        Owner {
+            user: self.json(),
+            token: self.headers.get(TOKEN_HEADER).unwrap().to_str().unwrap().to_owned(),
+        }
+    }
+
+    pub fn err(&self) -> String {
+        self.json::<Value>()["err"].as_str().unwrap_or_default().to_owned()
+    }
+}
+
+pub fn init() -> TestApp<impl Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>> {
+    init_with_rate_limit(RateLimit::default())
+}
+
+pub fn init_with_rate_limit(
+    rate_limit: RateLimit,
+) -> TestApp<impl Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>> {
+    init_with(rate_limit, CacheConfig::default())
+}
+
+pub fn init_with_cache(
+    cache: CacheConfig,
+) -> TestApp<impl Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>> {
+    init_with(RateLimit::default(), cache)
+}
+
+fn init_with(
+    rate_limit: RateLimit,
+    cache: CacheConfig,
+) -> TestApp<impl Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>> {
+    let dir = TempDir::new().unwrap();
+    let database_url = dir.path().join("blog.sqlite3").to_str().unwrap().to_owned();
+    let conn = SqliteConnection::establish(&database_url).unwrap();
+    embedded_migrations::run(&conn).unwrap();
+
+    let pools = Pools::new(&database_url, None);
+    let backups = Backups::new(database_url, BackupConfig {
+        dir: dir.path().join("backups"),
+        interval: None,
+        retain: 2,
+        admin_token: ADMIN_TOKEN.to_owned(),
+    });
+
+    let state = AppState {
+        pools: pools.clone(),
+        listings: Listings::new(cache),
+        streams: Streams::new(),
+        token_users: TokenUsers::load(&conn).unwrap(),
+        backups: Some(backups),
+        admin_token: Some(ADMIN_TOKEN.to_owned()),
+        cors: Cors::default().allowed_origin(ALLOWED_ORIGIN),
+        security_headers: SecurityHeaders::default(),
+        rate_limit,
+        swagger_ui: SwaggerUi::default(),
+    };
+    let service = test::init_service(state.app());
+
+    TestApp { service, pools, dir }
+}
+
+impl<S> TestApp<S>
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    pub fn call(&mut self, req: test::TestRequest) -> Response {
+        let res = test::call_service(&mut self.service, req.to_request());
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = test::read_body(res).to_vec();
+        Response { status, headers, body }
+    }
+
+    // Only the headers are read, the events are with Events::next
+    pub fn stream(&mut self, uri: &str) -> Events {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let mut res = test::call_service(&mut self.service, req);
+        Events {
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: res.take_body(),
+        }
+    }
+
+    pub fn get(&mut self, uri: &str) -> Response {
+        self.call(test::TestRequest::get().uri(uri))
+    }
+
+    pub fn post(&mut self, uri: &str) -> Response {
+        self.call(test::TestRequest::post().uri(uri))
+    }
+
+    pub fn post_json(&mut self, uri: &str, body: Value) -> Response {
+        self.call(test::TestRequest::post().uri(uri).set_json(&body))
+    }
+
+    pub fn request(&mut self, method: Method, uri: &str) -> Response {
+        self.call(test::TestRequest::with_uri(uri).method(method))
+    }
+}
+
+// A request acting for the user whose token it is
+pub fn with_token(req: test::TestRequest, token: &str) -> test::TestRequest {
+    req.header(header::AUTHORIZATION, format!("Bearer {}", token))
+}
+
+fn unique(prefix: &str) -> String {
+    format!("{}-{}", prefix, FIXTURE_COUNTER.fetch_add(1, Ordering::SeqCst))
+}
+
+pub struct NewUser {
+    username: String,
+    email: Option<String>,
+}
+
+impl NewUser {
+    pub fn new() -> Self {
+        NewUser {
+            username: unique("user"),
+            email: None,
+        }
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.email = Some(email.to_owned());
+        self
+    }
+
+    pub fn username(mut self, username: &str) -> Self {
+        self.username = username.to_owned();
+        self
+    }
+
+    pub fn create<S>(self, app: &mut TestApp<S>) -> User
+        where
+            S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+    {
+        self.create_owner(app).user
+    }
+
+    // The user along with its token
+    pub fn create_owner<S>(self, app: &mut TestApp<S>) -> Owner
+        where
+            S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+    {
+        let res = app.post_json("/users", json!({ "username": self.username, "email": self.email }));
+        assert_eq!(res.status, StatusCode::OK);
+        res.owner()
+    }
+}
+
+pub struct NewPost {
+    title: String,
+    body: String,
+    published: bool,
+}
+
+impl NewPost {
+    pub fn new() -> Self {
+        NewPost {
+            title: unique("title"),
+            body: unique("body"),
+            published: false,
+        }
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_owned();
+        self
+    }
+
+    pub fn published(mut self) -> Self {
+        self.published = true;
+        self
+    }
+
+    pub fn create<S>(self, app: &mut TestApp<S>, author: &User) -> Post
+        where
+            S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+    {
+        let res = app.post_json(
+            &format!("/users/{}/posts", author.id),
+            json!({ "title": self.title, "body": self.body }),
+        );
+        assert_eq!(res.status, StatusCode::OK);
+        let post: Post = res.json();
+        if !self.published {
+            return post;
+        }
+
+        let res = app.post(&format!("/posts/{}/publish", post.id));
+        assert_eq!(res.status, StatusCode::OK);
+        res.json()
+    }
+}
+
+pub struct NewComment {
+    body: String,
+}
+
+impl NewComment {
+    pub fn new() -> Self {
+        NewComment { body: unique("comment") }
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = body.to_owned();
+        self
+    }
+
+    pub fn create<S>(self, app: &mut TestApp<S>, post: &Post, author: &User) -> Comment
+        where
+            S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+    {
+        let res = app.post_json(
+            &format!("/posts/{}/comments", post.id),
+            json!({ "user_id": author.id, "body": self.body }),
+        );
+        assert_eq!(res.status, StatusCode::OK);
+        res.json()
+    }
+}
@@ -0,0 +1,140 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use blog_actix::UserPostEntry;
+use common::{NewComment, NewPost, NewUser};
+use diesel::connection::SimpleConnection;
+
+fn header<'a>(res: &'a common::Response, name: &str) -> &'a str {
+    res.headers.get(name).unwrap().to_str().unwrap()
+}
+
+fn if_none_match(uri: &str, etag: &str) -> TestRequest {
+    TestRequest::get().uri(uri).header("If-None-Match", etag)
+}
+
+// Last-Modified is only sent once the second of the last write is over, the writes are moved a
+// few seconds back so the tests don't have to wait
+fn backdate_writes<S>(app: &mut common::TestApp<S>) {
+    app.pools
+        .writer()
+        .unwrap()
+        .batch_execute("UPDATE table_versions SET updated_at = updated_at - 5000")
+        .unwrap();
+}
+
+#[test]
+fn listings_send_validators() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &user);
+    backdate_writes(&mut app);
+
+    let uris = [
+        "/posts".to_owned(),
+        format!("/users/{}/posts", user.id),
+        format!("/posts/{}/comments", post.id),
+    ];
+    for uri in &uris {
+        let res = app.get(uri);
+        assert_eq!(res.status, StatusCode::OK, "{}", uri);
+        assert!(header(&res, "etag").starts_with('"'), "{}", uri);
+        assert!(res.headers.contains_key("last-modified"), "{}", uri);
+        assert_eq!(header(&res, "cache-control"), "no-cache");
+    }
+}
+
+#[test]
+fn not_modified_while_the_etag_matches() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &user);
+
+    let res = app.get("/posts");
+    let etag = header(&res, "etag").to_owned();
+
+    let res = app.call(if_none_match("/posts", &etag));
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+    assert!(res.body.is_empty());
+    assert_eq!(header(&res, "etag"), etag);
+
+    // a weak comparison is enough, and any listed tag can match
+    let res = app.call(if_none_match("/posts", &format!("\"other\", W/{}", etag)));
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+    let res = app.call(if_none_match("/posts", "*"));
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+
+    // a new comment changes the listing
+    NewComment::new().create(&mut app, &post, &user);
+    let res = app.call(if_none_match("/posts", &etag));
+    assert_eq!(res.status, StatusCode::OK);
+    assert_ne!(header(&res, "etag"), etag);
+    assert_eq!(res.json::<Vec<blog_actix::PostEntry>>()[0].1.len(), 1);
+}
+
+#[test]
+fn comments_etag_ignores_posts() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &user);
+
+    let uri = format!("/posts/{}/comments", post.id);
+    let res = app.get(&uri);
+    let etag = header(&res, "etag").to_owned();
+
+    NewPost::new().create(&mut app, &user);
+    assert_eq!(app.call(if_none_match(&uri, &etag)).status, StatusCode::NOT_MODIFIED);
+    let res = app.get(&format!("/users/{}/posts", user.id));
+    assert_ne!(header(&res, "etag"), etag);
+}
+
+#[test]
+fn not_modified_since_last_modified() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+
+    backdate_writes(&mut app);
+
+    let uri = format!("/users/{}/posts", user.id);
+    let res = app.get(&uri);
+    let last_modified = header(&res, "last-modified").to_owned();
+
+    let req = TestRequest::get().uri(&uri).header("If-Modified-Since", last_modified.as_str());
+    assert_eq!(app.call(req).status, StatusCode::NOT_MODIFIED);
+
+    let req = TestRequest::get()
+        .uri(&uri)
+        .header("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT");
+    assert_eq!(app.call(req).status, StatusCode::OK);
+
+    // If-None-Match wins when both are sent
+    let req = if_none_match(&uri, "\"stale\"").header("If-Modified-Since", last_modified.as_str());
+    assert_eq!(app.call(req).status, StatusCode::OK);
+}
+
+#[test]
+fn writes_in_the_same_second_not_hidden() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+    NewPost::new().create(&mut app, &user);
+
+    let uri = format!("/users/{}/posts", user.id);
+    let res = app.get(&uri);
+    // only there if the second of the write ended before the request
+    let last_modified = res.headers.get("last-modified").map(|value| value.to_str().unwrap().to_owned());
+
+    // most likely in the same second as the first request
+    NewPost::new().create(&mut app, &user);
+
+    let mut req = TestRequest::get().uri(&uri);
+    if let Some(last_modified) = &last_modified {
+        req = req.header("If-Modified-Since", last_modified.as_str());
+    }
+    let res = app.call(req);
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json::<Vec<UserPostEntry>>().len(), 2);
+}
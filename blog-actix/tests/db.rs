@@ -0,0 +1,32 @@
+use blog_actix::ConnectionOptions;
+use diesel::r2d2::CustomizeConnection;
+use diesel::{Connection, SqliteConnection};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use tempfile::TempDir;
+
+const CONNECTIONS: usize = 8;
+
+// Switching a new database to WAL takes a write lock, the connections set up together wait
+// on the busy timeout instead of failing with SQLITE_BUSY
+#[test]
+fn connections_set_up_together_switch_to_wal() {
+    let dir = TempDir::new().unwrap();
+    let database_url = dir.path().join("blog.sqlite3").to_str().unwrap().to_owned();
+    let barrier = Arc::new(Barrier::new(CONNECTIONS));
+
+    let handles = (0..CONNECTIONS)
+        .map(|_| {
+            let (database_url, barrier) = (database_url.clone(), barrier.clone());
+            thread::spawn(move || {
+                let mut conn = SqliteConnection::establish(&database_url).unwrap();
+                barrier.wait();
+                ConnectionOptions::default().on_acquire(&mut conn).map(|_| conn)
+            })
+        })
+        .collect::<Vec<_>>();
+    let connections = handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>();
+    assert!(connections.iter().all(Result::is_ok));
+    // the file is removed with the last connection
+    assert!(dir.path().join("blog.sqlite3-wal").exists());
+}
@@ -0,0 +1,153 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_http::Request;
+use actix_web::dev::{Body, Service, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::test::TestRequest;
+use actix_web::Error;
+use blog_actix::routes::follows::Feed;
+use blog_actix::{Follow, User};
+use common::{with_token, NewComment, NewPost, NewUser, Owner, TestApp};
+use serde_json::json;
+
+fn follow<S>(app: &mut TestApp<S>, follower: &Owner, followee: &User) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let req = TestRequest::post()
+        .uri(&format!("/users/{}/following", follower.user.id))
+        .set_json(&json!({ "user_id": followee.id }));
+    app.call(with_token(req, &follower.token))
+}
+
+fn unfollow<S>(app: &mut TestApp<S>, follower: &Owner, followee: &User) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let req = TestRequest::delete().uri(&format!("/users/{}/following/{}", follower.user.id, followee.id));
+    app.call(with_token(req, &follower.token))
+}
+
+fn ids(users: Vec<User>) -> Vec<i32> {
+    users.into_iter().map(|user| user.id).collect()
+}
+
+fn titles(feed: &Feed) -> Vec<&str> {
+    feed.posts.iter().map(|((post, _), _)| post.title.as_str()).collect()
+}
+
+#[test]
+fn follow_and_unfollow() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let bob = NewUser::new().create(&mut app);
+
+    let res = follow(&mut app, &frank, &bob);
+    assert_eq!(res.status, StatusCode::OK);
+    let created: Follow = res.json();
+    assert_eq!((created.follower_id, created.followee_id), (frank.user.id, bob.id));
+    assert_eq!(follow(&mut app, &frank, &bob).status, StatusCode::BAD_REQUEST);
+
+    assert_eq!(ids(app.get(&format!("/users/{}/following", frank.user.id)).json()), vec![bob.id]);
+    assert_eq!(ids(app.get(&format!("/users/{}/followers", bob.id)).json()), vec![frank.user.id]);
+    assert!(ids(app.get(&format!("/users/{}/followers", frank.user.id)).json()).is_empty());
+
+    assert_eq!(unfollow(&mut app, &frank, &bob).status, StatusCode::NO_CONTENT);
+    assert_eq!(unfollow(&mut app, &frank, &bob).status, StatusCode::NOT_FOUND);
+    assert!(ids(app.get(&format!("/users/{}/following", frank.user.id)).json()).is_empty());
+}
+
+#[test]
+fn follows_need_the_followers_token() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let bob = NewUser::new().create_owner(&mut app);
+
+    let uri = format!("/users/{}/following", frank.user.id);
+    let res = app.post_json(&uri, json!({ "user_id": bob.user.id }));
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    // bob can't make frank follow him
+    let req = TestRequest::post().uri(&uri).set_json(&json!({ "user_id": bob.user.id }));
+    assert_eq!(app.call(with_token(req, &bob.token)).status, StatusCode::UNAUTHORIZED);
+    assert!(ids(app.get(&uri).json()).is_empty());
+
+    follow(&mut app, &frank, &bob.user);
+    let uri = format!("/users/{}/following/{}", frank.user.id, bob.user.id);
+    assert_eq!(app.request(Method::DELETE, &uri).status, StatusCode::UNAUTHORIZED);
+    let req = TestRequest::delete().uri(&uri);
+    assert_eq!(app.call(with_token(req, &bob.token)).status, StatusCode::UNAUTHORIZED);
+    assert_eq!(ids(app.get(&format!("/users/{}/following", frank.user.id)).json()), vec![bob.user.id]);
+}
+
+#[test]
+fn invalid_follows_rejected() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+
+    let res = follow(&mut app, &frank, &frank.user);
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "Users can't follow themselves");
+
+    let req = TestRequest::post()
+        .uri(&format!("/users/{}/following", frank.user.id))
+        .set_json(&json!({ "user_id": 42 }));
+    assert_eq!(app.call(with_token(req, &frank.token)).status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[test]
+fn feed_lists_the_followed_authors_posts() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    let ann = NewUser::new().create(&mut app);
+    let joe = NewUser::new().create(&mut app);
+    follow(&mut app, &frank, &bob);
+    follow(&mut app, &frank, &ann);
+
+    let post = NewPost::new().title("bob's").published().create(&mut app, &bob);
+    NewPost::new().title("bob's draft").create(&mut app, &bob);
+    NewPost::new().title("joe's").published().create(&mut app, &joe);
+    NewPost::new().title("frank's").published().create(&mut app, &frank.user);
+    NewPost::new().title("ann's").published().create(&mut app, &ann);
+    let comment = NewComment::new().create(&mut app, &post, &joe);
+
+    let feed: Feed = app.get(&format!("/users/{}/feed", frank.user.id)).json();
+    assert_eq!(titles(&feed), vec!["ann's", "bob's"]);
+    assert_eq!(feed.next, None);
+
+    // the shape of GET /posts
+    let ((_, author), comments) = &feed.posts[1];
+    assert_eq!(author, &bob);
+    assert_eq!(comments[0].0.id, comment.id);
+    assert_eq!(comments[0].1, joe);
+}
+
+#[test]
+fn feed_is_paginated() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    follow(&mut app, &frank, &bob);
+    for title in &["first", "second", "third"] {
+        NewPost::new().title(title).published().create(&mut app, &bob);
+    }
+
+    let uri = format!("/users/{}/feed?limit=2", frank.user.id);
+    let feed: Feed = app.get(&uri).json();
+    assert_eq!(titles(&feed), vec!["third", "second"]);
+    let next = feed.next.unwrap();
+
+    // posts published in the meantime don't shift the pages
+    NewPost::new().title("fourth").published().create(&mut app, &bob);
+    let feed: Feed = app.get(&format!("{}&before={}", uri, next)).json();
+    assert_eq!(titles(&feed), vec!["first"]);
+    assert_eq!(feed.next, None);
+
+    let res = app.get(&format!("{}&before=yesterday", uri));
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "Invalid page cursor");
+    assert_eq!(app.get("/users/42/feed").status, StatusCode::NOT_FOUND);
+}
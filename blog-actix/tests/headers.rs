@@ -0,0 +1,85 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::{Method, StatusCode};
+use actix_web::test::TestRequest;
+use common::ALLOWED_ORIGIN;
+
+fn header<'a>(res: &'a common::Response, name: &str) -> Option<&'a str> {
+    res.headers.get(name).map(|value| value.to_str().unwrap())
+}
+
+fn preflight(origin: &str, method: &str) -> TestRequest {
+    TestRequest::with_uri("/posts")
+        .method(Method::OPTIONS)
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", method)
+}
+
+#[test]
+fn preflight_from_allowed_origin() {
+    let mut app = common::init();
+
+    let req = preflight(ALLOWED_ORIGIN, "POST").header("Access-Control-Request-Headers", "Content-Type");
+    let res = app.call(req);
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert_eq!(header(&res, "access-control-allow-origin"), Some(ALLOWED_ORIGIN));
+    assert!(header(&res, "access-control-allow-methods").unwrap().contains("POST"));
+    assert!(header(&res, "access-control-allow-headers").unwrap().contains("content-type"));
+    assert_eq!(header(&res, "access-control-max-age"), Some("3600"));
+    assert_eq!(header(&res, "access-control-allow-credentials"), None);
+}
+
+#[test]
+fn preflight_rejected() {
+    let mut app = common::init();
+
+    let res = app.call(preflight("https://evil.example", "GET"));
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(header(&res, "access-control-allow-origin"), None);
+
+    let res = app.call(preflight(ALLOWED_ORIGIN, "TRACE"));
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+
+    let req = preflight(ALLOWED_ORIGIN, "GET").header("Access-Control-Request-Headers", "X-Secret");
+    let res = app.call(req);
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn cross_origin_requests() {
+    let mut app = common::init();
+
+    let res = app.call(TestRequest::get().uri("/posts").header("Origin", ALLOWED_ORIGIN));
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(header(&res, "access-control-allow-origin"), Some(ALLOWED_ORIGIN));
+    // the token of the new users
+    assert_eq!(header(&res, "access-control-expose-headers"), Some("x-request-id, x-user-token"));
+    assert_eq!(header(&res, "vary"), Some("Origin"));
+
+    // the request is still handled, the browser is the one hiding the response
+    let res = app.call(TestRequest::get().uri("/posts").header("Origin", "https://evil.example"));
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(header(&res, "access-control-allow-origin"), None);
+}
+
+#[test]
+fn security_headers() {
+    let mut app = common::init();
+
+    for res in &[app.get("/posts"), app.get("/users/42")] {
+        assert_eq!(header(res, "x-content-type-options"), Some("nosniff"));
+        assert_eq!(header(res, "x-frame-options"), Some("DENY"));
+        assert_eq!(
+            header(res, "content-security-policy"),
+            Some("default-src 'none'; frame-ancestors 'none'")
+        );
+        assert_eq!(header(res, "strict-transport-security"), None);
+    }
+
+    // the documentation page keeps the policy it needs to load Swagger UI
+    let res = app.get("/docs");
+    assert!(header(&res, "content-security-policy").unwrap().contains("https://unpkg.com"));
+}
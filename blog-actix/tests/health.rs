@@ -0,0 +1,68 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use blog_actix::routes::health::{Status, Version};
+use diesel::connection::SimpleConnection;
+use std::fs;
+use std::path::Path;
+
+// The version diesel records for the newest migration, its directory name without the separators
+fn latest_migration() -> String {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter_map(|name| name.split('_').next().map(|version| version.replace('-', "")))
+        .filter(|version| !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()))
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn healthz() {
+    let mut app = common::init();
+
+    let res = app.get("/healthz");
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json::<Status>().status, "ok");
+}
+
+#[test]
+fn readyz_when_migrations_are_current() {
+    let mut app = common::init();
+
+    let res = app.get("/readyz");
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json::<Status>().status, "ready");
+}
+
+#[test]
+fn readyz_reports_pending_migrations() {
+    let mut app = common::init();
+    app.pools
+        .writer()
+        .unwrap()
+        .batch_execute("DELETE FROM __diesel_schema_migrations WHERE version = '20210307142429'")
+        .unwrap();
+
+    let res = app.get("/readyz");
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.err(), "Database migrations pending: 20210307142429");
+}
+
+#[test]
+fn version_includes_the_schema_version() {
+    let mut app = common::init();
+
+    let res = app.get("/version");
+    assert_eq!(res.status, StatusCode::OK);
+
+    let version: Version = res.json();
+    assert_eq!(version.name, "blog-actix");
+    assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+    assert!(!version.git_hash.is_empty());
+    assert_eq!(version.schema_version, Some(latest_migration()));
+}
@@ -0,0 +1,52 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use serde_json::Value;
+
+fn request_id(res: &common::Response) -> String {
+    res.headers.get("x-request-id").unwrap().to_str().unwrap().to_owned()
+}
+
+#[test]
+fn request_ids_are_generated() {
+    let mut app = common::init();
+
+    let first = request_id(&app.get("/posts"));
+    let second = request_id(&app.get("/posts"));
+    assert_eq!(first.len(), 36);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn request_ids_are_propagated() {
+    let mut app = common::init();
+
+    let res = app.call(TestRequest::get().uri("/posts").header("X-Request-Id", "lb-1234"));
+    assert_eq!(request_id(&res), "lb-1234");
+
+    let res = app.call(TestRequest::get().uri("/posts").header("X-Request-Id", "no spaces\tallowed"));
+    assert_eq!(request_id(&res).len(), 36);
+}
+
+#[test]
+fn app_errors_carry_the_request_id() {
+    let mut app = common::init();
+
+    let res = app.call(TestRequest::get().uri("/users/42").header("X-Request-Id", "support-42"));
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(res.err(), "This record does not exist");
+    assert_eq!(res.json::<Value>()["request_id"], "support-42");
+}
+
+#[test]
+fn successful_responses_are_left_alone() {
+    let mut app = common::init();
+
+    let res = app.get("/posts");
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json::<Value>(), Value::Array(vec![]));
+}
@@ -0,0 +1,328 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use blog_actix::{MailConfig, MaildirMailer, Mailer, Message, Outbox, SmtpConfig, SmtpMailer, Subscriber};
+use common::{NewComment, NewPost, NewUser, TestApp};
+use serde_json::json;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+fn config() -> MailConfig {
+    MailConfig {
+        backoff: Duration::from_secs(0),
+        ..MailConfig::new("Blog <blog@example.com>".to_owned(), "https://blog.example/".to_owned())
+    }
+}
+
+fn send_due<S>(app: &TestApp<S>, mailer: Arc<dyn Mailer>) -> usize {
+    Outbox::new(app.pools.clone(), config(), mailer).send_due().unwrap()
+}
+
+// The messages delivered to the Maildir, oldest first
+fn maildir<S>(app: &TestApp<S>) -> Vec<String> {
+    let dir = app.dir.path().join("mail");
+    let mailer = Arc::new(MaildirMailer::new(dir.clone()).unwrap());
+    send_due(app, mailer);
+    read_maildir(&dir.join("new"))
+}
+
+fn read_maildir(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    // seconds and microseconds, sorting by name is good enough within a test
+    names.sort();
+    names.into_iter().map(|path| fs::read_to_string(path).unwrap()).collect()
+}
+
+fn header<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    let prefix = format!("{}: ", name);
+    message
+        .split("\r\n\r\n")
+        .next()
+        .unwrap()
+        .split("\r\n")
+        .find(|line| line.starts_with(&prefix))
+        .map(|line| &line[prefix.len()..])
+}
+
+fn body(message: &str) -> &str {
+    message.split_once("\r\n\r\n").unwrap().1
+}
+
+#[test]
+fn comments_notify_the_post_author() {
+    let mut app = common::init();
+    let frank = NewUser::new().email("frank@example.com").create(&mut app);
+    let bob = NewUser::new().username("bob").create(&mut app);
+    let post = NewPost::new().title("Hello").published().create(&mut app, &frank);
+
+    NewComment::new().body("Welcome!").create(&mut app, &post, &bob);
+    // not about their own
+    NewComment::new().create(&mut app, &post, &frank);
+
+    let messages = maildir(&app);
+    assert_eq!(messages.len(), 1);
+    let message = &messages[0];
+    assert_eq!(header(message, "From"), Some("Blog <blog@example.com>"));
+    assert_eq!(header(message, "To"), Some("frank@example.com"));
+    assert_eq!(header(message, "Subject"), Some(r#"bob commented on "Hello""#));
+    assert!(body(message).contains("\r\nWelcome!\r\n"));
+    assert!(body(message).contains(&format!("https://blog.example/posts/{}/comments", post.id)));
+
+    // sent once
+    assert_eq!(maildir(&app).len(), 1);
+}
+
+#[test]
+fn emails_are_private() {
+    let mut app = common::init();
+    let frank = NewUser::new().email("frank@example.com").create(&mut app);
+
+    let res = app.get(&format!("/users/{}", frank.id));
+    assert!(!String::from_utf8(res.body).unwrap().contains("frank@example.com"));
+
+    let res = app.post_json("/users", json!({ "username": "bob", "email": "bob" }));
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "Invalid email address");
+}
+
+// The path of the link to the API in the body of a message
+fn link<'a>(message: &'a str, path_end: &str) -> &'a str {
+    body(message)
+        .split("\r\n")
+        .find(|line| line.ends_with(path_end))
+        .unwrap()
+        .trim_start_matches("https://blog.example")
+}
+
+#[test]
+fn subscribers_told_about_new_posts() {
+    let mut app = common::init();
+    let frank = NewUser::new().username("frank").create(&mut app);
+    for email in &["ann@example.com", "joe@example.com", "sue@example.com"] {
+        let res = app.post_json("/subscribers", json!({ "email": email }));
+        assert_eq!(res.status, StatusCode::OK);
+        let subscriber: Subscriber = res.json();
+        assert_eq!(&subscriber.email, email);
+        assert_eq!(subscriber.confirmed_at, None);
+    }
+
+    // sue never confirms
+    let confirmations = maildir(&app);
+    assert_eq!(confirmations.len(), 3);
+    assert_eq!(header(&confirmations[0], "Subject"), Some("Confirm your subscription"));
+    assert_eq!(header(&confirmations[0], "List-Unsubscribe"), None);
+    for confirmation in &confirmations {
+        // only a page with the form, as link scanners open the links too
+        let res = app.get(link(confirmation, "/confirm"));
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.headers.get("content-type").unwrap(), "text/html; charset=utf-8");
+        assert!(String::from_utf8(res.body).unwrap().contains(r#"<form method="post">"#));
+    }
+    for confirmation in &confirmations[..2] {
+        let res = app.post(link(confirmation, "/confirm"));
+        assert_eq!(res.status, StatusCode::OK);
+        let subscriber: Subscriber = res.json();
+        assert_eq!(Some(subscriber.email.as_str()), header(confirmation, "To"));
+        assert!(subscriber.confirmed_at.is_some());
+    }
+    assert_eq!(app.post("/subscribers/guess/confirm").status, StatusCode::NOT_FOUND);
+
+    let draft = NewPost::new().title("Draft").create(&mut app, &frank);
+    assert_eq!(maildir(&app).len(), 3);
+    app.post(&format!("/posts/{}/publish", draft.id));
+    app.post(&format!("/posts/{}/publish", draft.id));
+
+    let messages = maildir(&app)[3..].to_vec();
+    assert_eq!(messages.len(), 2);
+    let recipients: Vec<_> = messages.iter().map(|message| header(message, "To").unwrap()).collect();
+    assert_eq!(recipients, vec!["ann@example.com", "joe@example.com"]);
+    assert_eq!(header(&messages[0], "Subject"), Some("New post: Draft"));
+    assert!(body(&messages[0]).contains(r#"frank just published "Draft""#));
+    assert_eq!(header(&messages[0], "List-Unsubscribe-Post"), Some("List-Unsubscribe=One-Click"));
+
+    // what a mail client does with the unsubscribe link, the one in the body opening a page
+    let uri = header(&messages[0], "List-Unsubscribe")
+        .unwrap()
+        .trim_start_matches("<https://blog.example")
+        .trim_end_matches('>');
+    assert_eq!(link(&messages[0], "/unsubscribe"), uri);
+    assert_eq!(app.get(uri).status, StatusCode::OK);
+    assert_eq!(app.post(uri).status, StatusCode::NO_CONTENT);
+    assert_eq!(app.post(uri).status, StatusCode::NOT_FOUND);
+
+    let post = NewPost::new().published().create(&mut app, &frank);
+    let messages = maildir(&app);
+    assert_eq!(messages.len(), 6);
+    assert_eq!(header(&messages[5], "To"), Some("joe@example.com"));
+    assert!(header(&messages[5], "Subject").unwrap().ends_with(&post.title));
+}
+
+#[test]
+fn unsubscribing_drops_the_queued_messages() {
+    let mut app = common::init();
+    let frank = NewUser::new().email("ann@example.com").create(&mut app);
+    let bob = NewUser::new().username("bob").create(&mut app);
+    app.post_json("/subscribers", json!({ "email": "ann@example.com" }));
+    let confirmation = maildir(&app).pop().unwrap();
+    app.post(link(&confirmation, "/confirm"));
+
+    // queued while the relay is away
+    let post = NewPost::new().title("Post").published().create(&mut app, &frank);
+    NewComment::new().create(&mut app, &post, &bob);
+    let token = link(&confirmation, "/confirm")
+        .trim_start_matches("/subscribers/")
+        .trim_end_matches("/confirm");
+    let res = app.post(&format!("/subscribers/{}/unsubscribe", token));
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    // the mail about the comment isn't the subscription's
+    let messages = maildir(&app);
+    assert_eq!(messages.len(), 2);
+    assert!(header(&messages[1], "Subject").unwrap().ends_with("commented on \"Post\""));
+}
+
+#[test]
+fn invalid_subscribers_rejected() {
+    let mut app = common::init();
+
+    for email in &["", "joe", "joe@example", "joe@example.com\r\nBcc: ann@example.com", "<joe@example.com>"] {
+        let res = app.post_json("/subscribers", json!({ "email": email }));
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{:?}", email);
+    }
+
+    app.post_json("/subscribers", json!({ "email": "joe@example.com" }));
+    let res = app.post_json("/subscribers", json!({ "email": "joe@example.com" }));
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+// Fails the first sends, then keeps what it was given
+struct Flaky {
+    failures: AtomicUsize,
+    sent: Mutex<Vec<Message>>,
+}
+
+impl Mailer for Flaky {
+    fn send(&self, message: &Message) -> io::Result<()> {
+        if self.failures.load(Ordering::SeqCst) > 0 {
+            self.failures.fetch_sub(1, Ordering::SeqCst);
+            return Err(io::Error::other("relay down"));
+        }
+        self.sent.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_sends_retried() {
+    let mut app = common::init();
+    let frank = NewUser::new().email("frank@example.com").create(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    let post = NewPost::new().create(&mut app, &frank);
+    NewComment::new().create(&mut app, &post, &bob);
+
+    let mailer = Arc::new(Flaky {
+        failures: AtomicUsize::new(2),
+        sent: Mutex::new(vec![]),
+    });
+    assert_eq!(send_due(&app, mailer.clone()), 0);
+    assert_eq!(send_due(&app, mailer.clone()), 0);
+    assert_eq!(send_due(&app, mailer.clone()), 1);
+    assert_eq!(send_due(&app, mailer.clone()), 0);
+    assert_eq!(mailer.sent.lock().unwrap()[0].to, "frank@example.com");
+
+    let metrics = String::from_utf8(app.get("/metrics").body).unwrap();
+    assert!(metrics.contains(r#"mail_attempts_total{result="retry",template="comment_added"}"#));
+}
+
+#[test]
+fn smtp_relay() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut lines = vec![];
+        let reply = |stream: &mut std::net::TcpStream, text: &str| {
+            stream.write_all(text.as_bytes()).unwrap();
+        };
+        reply(&mut stream, "220 relay.example ESMTP\r\n");
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end_matches("\r\n").to_owned();
+            lines.push(line.clone());
+            if in_data {
+                if line == "." {
+                    in_data = false;
+                    reply(&mut stream, "250 queued\r\n");
+                }
+                continue;
+            }
+            match line.split(' ').next().unwrap() {
+                "EHLO" => reply(&mut stream, "250-relay.example\r\n250 AUTH PLAIN\r\n"),
+                "AUTH" => reply(&mut stream, "235 ok\r\n"),
+                "DATA" => {
+                    in_data = true;
+                    reply(&mut stream, "354 go ahead\r\n");
+                }
+                "QUIT" => {
+                    reply(&mut stream, "221 bye\r\n");
+                    break;
+                }
+                _ => reply(&mut stream, "250 ok\r\n"),
+            }
+        }
+        lines
+    });
+
+    let mailer = SmtpMailer::new(SmtpConfig {
+        port,
+        ..SmtpConfig::new("127.0.0.1".to_owned())
+    })
+    .unwrap();
+    mailer
+        .send(&Message {
+            from: "Blog <blog@example.com>".to_owned(),
+            to: "frank@example.com".to_owned(),
+            subject: "Héllo".to_owned(),
+            body: "first\n.\nlast".to_owned(),
+            headers: vec![],
+        })
+        .unwrap();
+
+    let lines = server.join().unwrap();
+    assert_eq!(lines[0], "EHLO localhost");
+    assert_eq!(lines[1], "MAIL FROM:<blog@example.com>");
+    assert_eq!(lines[2], "RCPT TO:<frank@example.com>");
+    assert_eq!(lines[3], "DATA");
+    assert!(lines.contains(&"Subject: =?utf-8?B?SMOpbGxv?=".to_owned()));
+    // the lone dot of the body is escaped
+    let body: Vec<_> = lines.iter().skip_while(|line| !line.is_empty()).skip(1).collect();
+    assert_eq!(body, vec!["first", "..", "last", ".", "QUIT"]);
+}
+
+#[test]
+fn smtp_credentials_need_tls() {
+    let config = SmtpConfig {
+        credentials: Some(("blog".to_owned(), "hunter2".to_owned())),
+        ..SmtpConfig::new("relay.example".to_owned())
+    };
+    let error = SmtpMailer::new(config.clone()).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(SmtpMailer::new(SmtpConfig { tls: true, ..config }).is_ok());
+}
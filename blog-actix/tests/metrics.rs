@@ -0,0 +1,40 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use common::NewUser;
+
+#[test]
+fn requests_are_labelled_by_route_pattern() {
+    let mut app = common::init();
+    let user = NewUser::new().create(&mut app);
+
+    assert_eq!(app.get(&format!("/users/{}", user.id)).status, StatusCode::OK);
+    assert_eq!(app.get("/users/find/nobody").status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get("/no/such/route").status, StatusCode::NOT_FOUND);
+
+    let res = app.get("/metrics");
+    assert_eq!(res.status, StatusCode::OK);
+    let metrics = String::from_utf8(res.body).unwrap();
+    assert!(metrics.contains(r#"http_requests_total{method="GET",path="/users/{id}",status="200"}"#));
+    assert!(metrics.contains(r#"http_requests_total{method="GET",path="/users/find/{name}",status="404"}"#));
+    assert!(metrics.contains(r#"http_requests_total{method="GET",path="unmatched",status="404"}"#));
+    assert!(metrics.contains(r#"http_request_duration_seconds_bucket{method="POST",path="/users""#));
+    assert!(!metrics.contains("nobody"));
+}
+
+#[test]
+fn pool_and_blocking_pool_usage_are_exported() {
+    let mut app = common::init();
+    app.get("/posts");
+
+    let res = app.get("/metrics");
+    assert_eq!(res.status, StatusCode::OK);
+    let metrics = String::from_utf8(res.body).unwrap();
+    assert!(metrics.contains(r#"db_pool_connections{pool="read",state="idle"}"#));
+    assert!(metrics.contains(r#"db_pool_connections{pool="write",state="in_use"}"#));
+    assert!(metrics.contains("blocking_tasks_queued"));
+    assert!(metrics.contains("blocking_tasks_running"));
+}
@@ -0,0 +1,73 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::test::TestRequest;
+use common::{with_token, NewComment, NewPost, NewUser};
+use diesel::dsl::sql;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{Connection, RunQueryDsl, SqliteConnection};
+use diesel_migrations::MigrationConnection;
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+fn count(conn: &SqliteConnection, table: &str) -> i64 {
+    sql::<BigInt>(&format!("SELECT COUNT(*) FROM {}", table))
+        .get_result(conn)
+        .unwrap()
+}
+
+fn table_exists(conn: &SqliteConnection, table: &str) -> bool {
+    let query = format!("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '{}'", table);
+    sql::<BigInt>(&query).get_result::<i64>(conn).unwrap() > 0
+}
+
+// Every migration reverts without breaking a reference or losing rows of the tables it keeps, the
+// ones rebuilding users, posts and comments included
+#[test]
+fn migrations_reverted_without_losing_rows() {
+    let mut app = common::init();
+    let frank = NewUser::new().email("frank@example.com").create(&mut app);
+    let bob = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+    NewComment::new().create(&mut app, &post, &bob.user);
+    let req = TestRequest::post()
+        .uri(&format!("/users/{}/following", bob.user.id))
+        .set_json(&json!({ "user_id": frank.id }));
+    app.call(with_token(req, &bob.token));
+    let req = TestRequest::post()
+        .uri(&format!("/posts/{}/reactions", post.id))
+        .set_json(&json!({ "user_id": bob.user.id, "kind": "like" }));
+    app.call(with_token(req, &bob.token));
+
+    let tables = ["users", "posts", "comments", "follows", "reactions", "user_emails"];
+    let database_url = app.dir.path().join("blog.sqlite3");
+    // enforcing foreign keys, as the bundled SQLite does by default
+    let conn = SqliteConnection::establish(database_url.to_str().unwrap()).unwrap();
+    let before: Vec<i64> = tables.iter().map(|table| count(&conn, table)).collect();
+
+    let migrations = Path::new("migrations");
+    let versions = fs::read_dir(migrations)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().is_dir())
+        .count();
+    for _ in 0..versions {
+        let reverted = diesel_migrations::revert_latest_migration_in_directory(&conn, migrations).unwrap();
+        for (table, expected) in tables.iter().zip(&before) {
+            if table_exists(&conn, table) {
+                assert_eq!(count(&conn, table), *expected, "{} after reverting {}", table, reverted);
+            }
+        }
+        let violations: Vec<String> = sql::<Text>("SELECT \"table\" FROM pragma_foreign_key_check")
+            .load(&conn)
+            .unwrap();
+        assert!(violations.is_empty(), "{:?} after reverting {}", violations, reverted);
+    }
+    assert_eq!(conn.latest_run_migration_version().unwrap(), None);
+
+    diesel_migrations::run_pending_migrations_in_directory(&conn, migrations, &mut io::sink()).unwrap();
+    assert!(tables.iter().all(|table| table_exists(&conn, table)));
+}
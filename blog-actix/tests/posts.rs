@@ -0,0 +1,131 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use blog_actix::{Post, PostEntry, UserPostEntry};
+use common::{NewComment, NewPost, NewUser};
+use serde_json::{json, Value};
+
+#[test]
+fn add_post_creates_a_draft() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+
+    let res = app.post_json(
+        &format!("/users/{}/posts", frank.id),
+        json!({ "title": "Frank says hello", "body": "Hello friends" }),
+    );
+    assert_eq!(res.status, StatusCode::OK);
+
+    let post: Post = res.json();
+    assert_eq!(post.user_id, frank.id);
+    assert_eq!(post.title, "Frank says hello");
+    assert!(!post.published);
+}
+
+#[test]
+fn add_post_for_a_missing_user() {
+    let mut app = common::init();
+
+    let res = app.post_json("/users/42/posts", json!({ "title": "Hello", "body": "Hello" }));
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn publish_post() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().create(&mut app, &frank);
+
+    assert_eq!(post.published_at, None);
+
+    let res = app.post(&format!("/posts/{}/publish", post.id));
+    assert_eq!(res.status, StatusCode::OK);
+    let published: Post = res.json();
+    assert!(published.published);
+    assert_eq!(published.created_at, post.created_at);
+    assert!(published.updated_at >= post.updated_at);
+    assert_eq!(published.published_at, Some(published.updated_at));
+
+    // publishing again keeps the first publication time
+    let res = app.post(&format!("/posts/{}/publish", post.id));
+    assert_eq!(res.json::<Post>().published_at, published.published_at);
+
+    let res = app.post("/posts/42/publish");
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn all_posts_lists_published_posts_newest_first() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    let first = NewPost::new().published().create(&mut app, &frank);
+    NewPost::new().create(&mut app, &frank);
+    let second = NewPost::new().published().create(&mut app, &bob);
+    let comment = NewComment::new().create(&mut app, &first, &bob);
+
+    let res = app.get("/posts");
+    assert_eq!(res.status, StatusCode::OK);
+
+    let posts: Vec<PostEntry> = res.json();
+    let ids = posts.iter().map(|((post, _), _)| post.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![second.id, first.id]);
+
+    let ((_, author), comments) = &posts[1];
+    assert_eq!(author, &frank);
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].0.id, comment.id);
+    assert_eq!(comments[0].1, bob);
+}
+
+#[test]
+fn users_posts_include_drafts_and_comments() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let bob = NewUser::new().create(&mut app);
+    let published = NewPost::new().published().create(&mut app, &frank);
+    let draft = NewPost::new().create(&mut app, &frank);
+    NewPost::new().create(&mut app, &bob);
+    NewComment::new().create(&mut app, &published, &bob);
+
+    let res = app.get(&format!("/users/{}/posts", frank.id));
+    assert_eq!(res.status, StatusCode::OK);
+
+    let posts: Vec<UserPostEntry> = res.json();
+    let ids = posts.iter().map(|(post, _)| post.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![draft.id, published.id]);
+    assert!(posts[0].1.is_empty());
+    assert_eq!(posts[1].1[0].1, bob);
+
+    let res = app.get("/users/42/posts");
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.json::<Vec<UserPostEntry>>().is_empty());
+}
+
+#[test]
+fn timestamps_are_utc() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+    let comment = NewComment::new().create(&mut app, &post, &frank);
+    assert!(frank.created_at <= post.created_at);
+    assert!(post.created_at <= comment.created_at);
+    assert_eq!(comment.created_at, comment.updated_at);
+
+    let posts: Value = app.get("/posts").json();
+    let (post, author, comment) = (&posts[0][0][0], &posts[0][0][1], &posts[0][1][0][0]);
+    for timestamp in &[
+        &author["created_at"],
+        &post["created_at"],
+        &post["updated_at"],
+        &post["published_at"],
+        &comment["created_at"],
+    ] {
+        let timestamp = timestamp.as_str().unwrap();
+        assert!(timestamp.ends_with('Z'), "{}", timestamp);
+        assert_eq!(timestamp.len(), "2026-10-19T09:00:00.000Z".len(), "{}", timestamp);
+    }
+}
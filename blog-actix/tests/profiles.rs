@@ -0,0 +1,168 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_http::Request;
+use actix_web::dev::{Body, Service, ServiceResponse};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::test::TestRequest;
+use actix_web::Error;
+use blog_actix::routes::users::UserToken;
+use blog_actix::Profile;
+use common::{NewComment, NewPost, Owner, TestApp, ADMIN_TOKEN};
+use diesel::connection::SimpleConnection;
+use serde_json::{json, Value};
+
+fn create<S>(app: &mut TestApp<S>, username: &str) -> Owner
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let res = app.post_json("/users", json!({ "username": username }));
+    assert_eq!(res.status, StatusCode::OK);
+    res.owner()
+}
+
+fn issue_token<S>(app: &mut TestApp<S>, user_id: i32, token: &str) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let req = TestRequest::post()
+        .uri(&format!("/users/{}/token", user_id))
+        .header(header::AUTHORIZATION, format!("Bearer {}", token));
+    app.call(req)
+}
+
+fn edit<S>(app: &mut TestApp<S>, user_id: i32, token: Option<&str>, changes: Value) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let mut req = TestRequest::with_uri(&format!("/users/{}", user_id))
+        .method(Method::PATCH)
+        .set_json(&changes);
+    if let Some(token) = token {
+        req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    app.call(req)
+}
+
+#[test]
+fn profile_edited_by_its_owner() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank");
+    assert_eq!(frank.token.len(), 64);
+    // the token is only in the header
+    let res = app.post_json("/users", json!({ "username": "bob" }));
+    assert!(res.json::<Value>().get("token").is_none());
+
+    let changes = json!({
+        "display_name": "Frank Herbert",
+        "bio": "Writes about sand",
+        "website": "https://frank.example/",
+        "avatar_url": "https://frank.example/avatar.png",
+    });
+    let res = edit(&mut app, frank.user.id, Some(&frank.token), changes);
+    assert_eq!(res.status, StatusCode::OK);
+    let profile: Profile = res.json();
+    assert_eq!(profile.display_name.as_deref(), Some("Frank Herbert"));
+    assert_eq!(profile.avatar_url.as_deref(), Some("https://frank.example/avatar.png"));
+    assert!(profile.updated_at >= frank.user.updated_at);
+
+    // missing fields are kept, empty ones cleared
+    let res = edit(&mut app, frank.user.id, Some(&frank.token), json!({ "bio": "" }));
+    assert_eq!(res.status, StatusCode::OK);
+
+    let profile: Profile = app.get(&format!("/users/{}", frank.user.id)).json();
+    assert_eq!(profile.bio, None);
+    assert_eq!(profile.website.as_deref(), Some("https://frank.example/"));
+    let found: Profile = app.get("/users/find/frank").json();
+    assert_eq!(found, profile);
+}
+
+#[test]
+fn profiles_need_the_users_token() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank");
+    let bob = create(&mut app, "bob");
+    let changes = json!({ "display_name": "Not Frank" });
+
+    assert_eq!(edit(&mut app, frank.user.id, None, changes.clone()).status, StatusCode::UNAUTHORIZED);
+    let res = edit(&mut app, frank.user.id, Some(&bob.token), changes.clone());
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let res = edit(&mut app, frank.user.id, Some("guess"), changes.clone());
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    // nor does it tell which users exist
+    assert_eq!(edit(&mut app, 42, Some(&bob.token), changes.clone()).status, StatusCode::UNAUTHORIZED);
+    assert_eq!(edit(&mut app, 42, None, json!({ "bio": "x".repeat(501) })).status, StatusCode::UNAUTHORIZED);
+    let profile: Profile = app.get(&format!("/users/{}", frank.user.id)).json();
+    assert_eq!(profile.display_name, None);
+
+    // admins can edit any profile
+    let res = edit(&mut app, frank.user.id, Some(ADMIN_TOKEN), changes.clone());
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(edit(&mut app, 42, Some(ADMIN_TOKEN), changes).status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn invalid_profiles_rejected() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank");
+
+    for changes in [
+        json!({ "display_name": "x".repeat(51) }),
+        json!({ "bio": "x".repeat(501) }),
+        json!({ "website": "frank.example" }),
+        json!({ "avatar_url": "javascript:alert(1)" }),
+    ] {
+        let res = edit(&mut app, frank.user.id, Some(&frank.token), changes.clone());
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", changes);
+        assert!(res.err().starts_with("Invalid profile"));
+    }
+}
+
+#[test]
+fn listings_embed_the_slim_users() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank");
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+    NewComment::new().create(&mut app, &post, &frank.user);
+    assert_eq!(app.get("/posts").status, StatusCode::OK);
+
+    let changes = json!({ "display_name": "Frank Herbert", "bio": "Writes about sand" });
+    let profile: Profile = edit(&mut app, frank.user.id, Some(&frank.token), changes).json();
+
+    // the cached listing is dropped, the author's updated_at moved
+    let posts: Value = app.get("/posts").json();
+    let author = &posts[0][0][1];
+    assert_eq!(author["updated_at"], json!(profile)["updated_at"]);
+    assert!(author.get("bio").is_none());
+    assert!(posts[0][1][0][1].get("display_name").is_none());
+}
+
+#[test]
+fn tokens_issued_to_the_users_without_one() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank");
+    let bob = create(&mut app, "bob");
+    // as created before users had tokens
+    app.pools
+        .writer()
+        .unwrap()
+        .batch_execute(&format!("UPDATE users SET token_hash = NULL WHERE id = {}", frank.user.id))
+        .unwrap();
+    let changes = json!({ "bio": "Writes about sand" });
+    assert_eq!(edit(&mut app, frank.user.id, Some(&frank.token), changes.clone()).status, StatusCode::UNAUTHORIZED);
+
+    assert_eq!(issue_token(&mut app, frank.user.id, &bob.token).status, StatusCode::UNAUTHORIZED);
+    assert_eq!(issue_token(&mut app, 42, ADMIN_TOKEN).status, StatusCode::NOT_FOUND);
+    let res = issue_token(&mut app, frank.user.id, ADMIN_TOKEN);
+    assert_eq!(res.status, StatusCode::OK);
+    let token: UserToken = res.json();
+    assert_eq!(edit(&mut app, frank.user.id, Some(&token.token), changes.clone()).status, StatusCode::OK);
+
+    // replaced by its owner, the old one stops working
+    let renewed: UserToken = issue_token(&mut app, frank.user.id, &token.token).json();
+    assert_ne!(renewed.token, token.token);
+    assert_eq!(edit(&mut app, frank.user.id, Some(&token.token), changes.clone()).status, StatusCode::UNAUTHORIZED);
+    assert_eq!(edit(&mut app, frank.user.id, Some(&renewed.token), changes).status, StatusCode::OK);
+}
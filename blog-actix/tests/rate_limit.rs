@@ -0,0 +1,130 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, Method, StatusCode};
+use blog_actix::routes;
+use blog_actix::routes::users::UserToken;
+use actix_web::test::TestRequest;
+use serde_json::json;
+use server_common::rate_limit::{Limit, RateLimit};
+use std::time::Duration;
+
+// TestRequest has no peer address, the clients are told apart as users instead
+fn client(req: &ServiceRequest) -> Option<String> {
+    let client = req.headers().get("x-client")?;
+    client.to_str().ok().map(str::to_owned)
+}
+
+fn create_user(username: &str, client: &str) -> TestRequest {
+    TestRequest::post()
+        .uri("/users")
+        .header("X-Client", client)
+        .set_json(&json!({ "username": username }))
+}
+
+#[test]
+fn requests_over_the_limit_are_rejected() {
+    let mut app = common::init_with_rate_limit(
+        RateLimit::default()
+            .route(Method::POST, "/users", Limit::per_minute(2))
+            .user(client),
+    );
+
+    assert_eq!(app.call(create_user("first", "a")).status, StatusCode::OK);
+    assert_eq!(app.call(create_user("second", "a")).status, StatusCode::OK);
+
+    let res = app.call(create_user("third", "a"));
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers.get("retry-after").unwrap(), "30");
+    assert_eq!(res.err(), "Too many requests, retry in 30 seconds");
+
+    // every client has its own bucket
+    assert_eq!(app.call(create_user("third", "b")).status, StatusCode::OK);
+    // and the other routes aren't limited
+    assert_eq!(app.get("/users/find/first").status, StatusCode::OK);
+}
+
+#[test]
+fn route_patterns_match_any_parameter() {
+    let mut app = common::init_with_rate_limit(
+        RateLimit::default()
+            .route(Method::POST, "/posts/{id}/comments", Limit::per_minute(1))
+            .user(client),
+    );
+    let user = common::NewUser::new().create(&mut app);
+    let first = common::NewPost::new().published().create(&mut app, &user);
+    let second = common::NewPost::new().published().create(&mut app, &user);
+
+    let comment = |post_id: i32| {
+        TestRequest::post()
+            .uri(&format!("/posts/{}/comments", post_id))
+            .header("X-Client", "a")
+            .set_json(&json!({ "user_id": user.id, "body": "hi" }))
+    };
+    assert_eq!(app.call(comment(first.id)).status, StatusCode::OK);
+    assert_eq!(app.call(comment(second.id)).status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn tokens_refill_over_the_period() {
+    let limit = Limit {
+        requests: 1,
+        period: Duration::from_millis(100),
+    };
+    let mut app = common::init_with_rate_limit(
+        RateLimit::default().route(Method::POST, "/users", limit).user(client),
+    );
+
+    assert_eq!(app.call(create_user("first", "a")).status, StatusCode::OK);
+    let res = app.call(create_user("second", "a"));
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers.get("retry-after").unwrap(), "1");
+
+    std::thread::sleep(Duration::from_millis(120));
+    assert_eq!(app.call(create_user("second", "a")).status, StatusCode::OK);
+}
+
+#[test]
+fn users_limited_by_their_token() {
+    let mut app = common::init_with_rate_limit(
+        RateLimit::default()
+            .route(Method::POST, "/users/{id}/posts", Limit::per_minute(1))
+            .user(routes::rate_limited_user),
+    );
+    let frank = app.post_json("/users", json!({ "username": "frank" })).owner();
+
+    let post = |token: &str| {
+        TestRequest::post()
+            .uri(&format!("/users/{}/posts", frank.user.id))
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .set_json(&json!({ "title": "Dune", "body": "Sand" }))
+    };
+    assert_eq!(app.call(post(&frank.token)).status, StatusCode::OK);
+    assert_eq!(app.call(post(&frank.token)).status, StatusCode::TOO_MANY_REQUESTS);
+
+    // made up tokens don't get a bucket of their own, these requests have no address to fall back
+    // on so they aren't limited at all
+    assert_eq!(app.call(post("made-up")).status, StatusCode::OK);
+    assert_eq!(app.call(post("made-up")).status, StatusCode::OK);
+
+    // a new token keeps the user's bucket, the one it replaces no longer counts as theirs
+    let req = TestRequest::post()
+        .uri(&format!("/users/{}/token", frank.user.id))
+        .header(header::AUTHORIZATION, format!("Bearer {}", frank.token));
+    let issued: UserToken = app.call(req).json();
+    assert_eq!(app.call(post(&issued.token)).status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(app.call(post(&frank.token)).status, StatusCode::OK);
+}
+
+#[test]
+fn limits_from_config() {
+    let limit = RateLimit::default().routes("POST /users=5/60, POST /posts/{id}/comments=2/10");
+    assert!(limit.is_ok());
+
+    for rules in &["POST /users", "POST /users=5", "/users=5/60", "POST /users=0/60"] {
+        assert!(RateLimit::default().routes(rules).is_err(), "{}", rules);
+    }
+}
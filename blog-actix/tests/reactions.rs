@@ -0,0 +1,189 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_http::Request;
+use actix_web::dev::{Body, Service, ServiceResponse};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::test::TestRequest;
+use actix_web::Error;
+use blog_actix::{CommentWithAuthor, Entity, PostEntry, Reaction, ReactionKind, Reactions, UserPostEntry};
+use common::{with_token, NewComment, NewPost, NewUser, Owner, TestApp, ADMIN_TOKEN};
+use diesel::connection::SimpleConnection;
+use diesel::dsl::sql;
+use diesel::sql_types::{Integer, Text};
+use diesel::RunQueryDsl;
+use serde_json::json;
+
+fn react<S>(app: &mut TestApp<S>, target: &str, user: &Owner, kind: &str) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let req = TestRequest::post()
+        .uri(&format!("{}/reactions", target))
+        .set_json(&json!({ "user_id": user.user.id, "kind": kind }));
+    app.call(with_token(req, &user.token))
+}
+
+fn unreact<S>(app: &mut TestApp<S>, uri: &str, user: &Owner) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    app.call(with_token(TestRequest::delete().uri(uri), &user.token))
+}
+
+fn counts(pairs: &[(ReactionKind, i64)]) -> Option<Reactions> {
+    Some(pairs.iter().copied().collect())
+}
+
+#[test]
+fn reactions_counted_in_the_listings() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let bob = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+    let comment = NewComment::new().create(&mut app, &post, &bob.user);
+
+    let res = react(&mut app, &format!("/posts/{}", post.id), &bob, "like");
+    assert_eq!(res.status, StatusCode::OK);
+    let reaction: Reaction = res.json();
+    assert_eq!((reaction.user_id, reaction.target, reaction.target_id), (bob.user.id, Entity::Post, post.id));
+    assert_eq!(reaction.kind, ReactionKind::Like);
+    // a user can react with several kinds, once each
+    react(&mut app, &format!("/posts/{}", post.id), &bob, "love");
+    react(&mut app, &format!("/posts/{}", post.id), &frank, "like");
+    react(&mut app, &format!("/comments/{}", comment.id), &frank, "laugh");
+
+    let posts: Vec<PostEntry> = app.get("/posts").json();
+    let ((listed, _), comments) = &posts[0];
+    assert_eq!(listed.reactions, counts(&[(ReactionKind::Like, 2), (ReactionKind::Love, 1)]));
+    assert_eq!(comments[0].0.reactions, counts(&[(ReactionKind::Laugh, 1)]));
+
+    let posts: Vec<UserPostEntry> = app.get(&format!("/users/{}/posts", frank.user.id)).json();
+    assert_eq!(posts[0].0.reactions, counts(&[(ReactionKind::Like, 2), (ReactionKind::Love, 1)]));
+
+    let comments: Vec<CommentWithAuthor> = app.get(&format!("/posts/{}/comments", post.id)).json();
+    assert_eq!(comments[0].0.reactions, counts(&[(ReactionKind::Laugh, 1)]));
+
+    // not counted outside of the listings
+    let post = NewPost::new().create(&mut app, &frank.user);
+    assert_eq!(post.reactions, None);
+}
+
+#[test]
+fn duplicate_reactions_rejected() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+
+    let target = format!("/posts/{}", post.id);
+    assert_eq!(react(&mut app, &target, &frank, "wow").status, StatusCode::OK);
+    let res = react(&mut app, &target, &frank, "wow");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "This recird violates a unique contraint");
+}
+
+#[test]
+fn reactions_taken_back() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+    let comment = NewComment::new().create(&mut app, &post, &frank.user);
+    react(&mut app, &format!("/posts/{}", post.id), &frank, "sad");
+    react(&mut app, &format!("/comments/{}", comment.id), &frank, "sad");
+
+    let uri = format!("/posts/{}/reactions/{}/sad", post.id, frank.user.id);
+    assert_eq!(unreact(&mut app, &uri, &frank).status, StatusCode::NO_CONTENT);
+    assert_eq!(unreact(&mut app, &uri, &frank).status, StatusCode::NOT_FOUND);
+    let uri = format!("/posts/{}/reactions/{}/meh", post.id, frank.user.id);
+    assert_eq!(unreact(&mut app, &uri, &frank).status, StatusCode::NOT_FOUND);
+
+    let posts: Vec<PostEntry> = app.get("/posts").json();
+    let ((listed, _), comments) = &posts[0];
+    assert_eq!(listed.reactions, counts(&[]));
+    // the comment's reaction is kept
+    assert_eq!(comments[0].0.reactions, counts(&[(ReactionKind::Sad, 1)]));
+}
+
+#[test]
+fn invalid_reactions_rejected() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+
+    let res = react(&mut app, "/posts/42", &frank, "like");
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = react(&mut app, "/comments/42", &frank, "like");
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = react(&mut app, &format!("/posts/{}", post.id), &frank, "meh");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+    let req = TestRequest::post()
+        .uri(&format!("/posts/{}/reactions", post.id))
+        .set_json(&json!({ "user_id": 42, "kind": "like" }));
+    assert_eq!(app.call(with_token(req, ADMIN_TOKEN)).status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[test]
+fn reactions_need_the_users_token() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let bob = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+
+    let uri = format!("/posts/{}/reactions", post.id);
+    let res = app.post_json(&uri, json!({ "user_id": frank.user.id, "kind": "like" }));
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    // bob can't react in frank's name
+    let req = TestRequest::post().uri(&uri).set_json(&json!({ "user_id": frank.user.id, "kind": "like" }));
+    assert_eq!(app.call(with_token(req, &bob.token)).status, StatusCode::UNAUTHORIZED);
+
+    react(&mut app, &format!("/posts/{}", post.id), &frank, "like");
+    let uri = format!("/posts/{}/reactions/{}/like", post.id, frank.user.id);
+    assert_eq!(app.request(Method::DELETE, &uri).status, StatusCode::UNAUTHORIZED);
+    assert_eq!(unreact(&mut app, &uri, &bob).status, StatusCode::UNAUTHORIZED);
+
+    let posts: Vec<PostEntry> = app.get("/posts").json();
+    assert_eq!((posts[0].0).0.reactions, counts(&[(ReactionKind::Like, 1)]));
+}
+
+#[test]
+fn reacting_changes_the_listing_etag() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+
+    let res = app.get("/posts");
+    let etag = res.headers.get(header::ETAG).unwrap().to_str().unwrap().to_owned();
+    react(&mut app, &format!("/posts/{}", post.id), &frank, "like");
+
+    let res = app.call(TestRequest::get().uri("/posts").header(header::IF_NONE_MATCH, etag));
+    assert_eq!(res.status, StatusCode::OK);
+    let posts: Vec<PostEntry> = res.json();
+    assert_eq!((posts[0].0).0.reactions, counts(&[(ReactionKind::Like, 1)]));
+}
+
+#[test]
+fn reactions_deleted_with_their_target() {
+    let mut app = common::init();
+    let frank = NewUser::new().create_owner(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+    let other = NewPost::new().published().create(&mut app, &frank.user);
+    let comment = NewComment::new().create(&mut app, &post, &frank.user);
+    react(&mut app, &format!("/posts/{}", post.id), &frank, "like");
+    react(&mut app, &format!("/posts/{}", other.id), &frank, "like");
+    react(&mut app, &format!("/comments/{}", comment.id), &frank, "like");
+
+    // there is no route deleting them
+    let conn = app.pools.writer().unwrap();
+    conn.batch_execute(&format!(
+        "DELETE FROM comments WHERE id = {}; DELETE FROM posts WHERE id = {};",
+        comment.id, post.id
+    ))
+        .unwrap();
+    let left: Vec<(String, i32)> = sql::<(Text, Integer)>("SELECT target, target_id FROM reactions")
+        .load(&*conn)
+        .unwrap();
+    assert_eq!(left, vec![("post".to_owned(), other.id)]);
+}
@@ -0,0 +1,96 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::{header, StatusCode};
+use blog_actix::{Comment, Post, User};
+use common::{NewComment, NewPost, NewUser};
+use serde::de::DeserializeOwned;
+
+// Splits `event: {name}\nid: {id}\ndata: {json}\n\n`
+fn parse<T: DeserializeOwned>(event: &str) -> (String, String, T) {
+    assert!(event.ends_with("\n\n"), "{:?}", event);
+    let fields: Vec<_> = event
+        .trim_end()
+        .lines()
+        .map(|line| line.split_once(": ").unwrap().1)
+        .collect();
+    assert_eq!(fields.len(), 3, "{:?}", event);
+    (fields[0].to_owned(), fields[1].to_owned(), serde_json::from_str(fields[2]).unwrap())
+}
+
+#[test]
+fn comments_pushed_to_the_post_stream() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+    let other = NewPost::new().published().create(&mut app, &frank);
+
+    let mut events = app.stream(&format!("/posts/{}/comments/stream", post.id));
+    assert_eq!(events.status, StatusCode::OK);
+    assert_eq!(events.headers.get(header::CONTENT_TYPE).unwrap(), "text/event-stream");
+    assert_eq!(events.headers.get(header::CACHE_CONTROL).unwrap(), "no-cache");
+
+    // not this post's
+    NewComment::new().create(&mut app, &other, &frank);
+    let comment = NewComment::new().create(&mut app, &post, &frank);
+
+    let (name, id, (pushed, author)): (_, _, (Comment, User)) = parse(&events.next());
+    assert_eq!(name, "comment");
+    assert_eq!(id, comment.id.to_string());
+    assert_eq!(pushed.body, comment.body);
+    assert_eq!(author, frank);
+}
+
+#[test]
+fn published_posts_pushed_once() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let mut events = app.stream("/posts/stream");
+    assert_eq!(events.status, StatusCode::OK);
+
+    let first = NewPost::new().create(&mut app, &frank);
+    let second = NewPost::new().create(&mut app, &frank);
+    app.post(&format!("/posts/{}/publish", first.id));
+    app.post(&format!("/posts/{}/publish", first.id));
+    app.post(&format!("/posts/{}/publish", second.id));
+
+    let (name, _, (post, author)): (_, _, (Post, User)) = parse(&events.next());
+    assert_eq!(name, "post");
+    assert_eq!(post.id, first.id);
+    assert!(post.published);
+    assert_eq!(author, frank);
+    // the second publication of the first post isn't announced
+    let (_, id, _): (_, _, (Post, User)) = parse(&events.next());
+    assert_eq!(id, second.id.to_string());
+}
+
+#[test]
+fn every_subscriber_gets_the_events() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+    let uri = format!("/posts/{}/comments/stream", post.id);
+    let mut first = app.stream(&uri);
+    let mut second = app.stream(&uri);
+    // gone before anything was sent
+    drop(app.stream(&uri));
+
+    let comment = NewComment::new().create(&mut app, &post, &frank);
+    for events in [&mut first, &mut second] {
+        let (_, id, _): (_, _, (Comment, User)) = parse(&events.next());
+        assert_eq!(id, comment.id.to_string());
+    }
+
+    let metrics = String::from_utf8(app.get("/metrics").body).unwrap();
+    assert!(metrics.contains(r#"stream_subscribers{stream="comments"}"#));
+}
+
+#[test]
+fn missing_post_stream() {
+    let mut app = common::init();
+
+    let res = app.get("/posts/42/comments/stream");
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
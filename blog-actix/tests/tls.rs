@@ -0,0 +1,121 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use actix_web::{App, HttpServer};
+use blog_actix::routes;
+use rustls::internal::pemfile;
+use rustls::{ClientConfig, ClientSession, Session, StreamOwned};
+use server_common::certs::TlsConfig;
+
+type Connection = StreamOwned<ClientSession, TcpStream>;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+}
+
+fn certificate(path: &Path) -> Vec<u8> {
+    let certs = pemfile::certs(&mut BufReader::new(File::open(path).unwrap())).unwrap();
+    certs[0].0.clone()
+}
+
+// Serves /healthz over TLS on a free port
+fn start(config: TlsConfig) -> u16 {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = actix_rt::System::new("tls");
+        HttpServer::new(|| App::new().configure(routes::health::configure))
+            .bind_rustls(("127.0.0.1", port), config.server_config().unwrap())
+            .unwrap()
+            .workers(1)
+            .disable_signals()
+            .start();
+        tx.send(()).unwrap();
+        sys.run()
+    });
+    rx.recv().unwrap();
+    port
+}
+
+fn connect(port: u16, protocol: &str) -> Connection {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_pem_file(&mut BufReader::new(File::open(fixture("ca.pem")).unwrap()))
+        .unwrap();
+    config.set_protocols(&[protocol.as_bytes().to_vec()]);
+
+    let name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    let mut session = ClientSession::new(&Arc::new(config), name);
+    let mut socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    while session.is_handshaking() {
+        session.complete_io(&mut socket).unwrap();
+    }
+    StreamOwned::new(session, socket)
+}
+
+fn served_certificate(port: u16) -> Vec<u8> {
+    let conn = connect(port, "http/1.1");
+    conn.sess.get_peer_certificates().unwrap()[0].0.clone()
+}
+
+#[test]
+fn http1_over_tls() {
+    let port = start(TlsConfig::new(fixture("first.pem"), fixture("first.key")));
+    let mut conn = connect(port, "http/1.1");
+    assert_eq!(Some(&b"http/1.1"[..]), conn.sess.get_alpn_protocol());
+
+    conn.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = vec![];
+    // the server ends with close_notify, which rustls reports as an error
+    let _ = conn.read_to_end(&mut response);
+    let response = String::from_utf8(response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with(r#"{"status":"ok"}"#), "{}", response);
+}
+
+#[test]
+fn http2_negotiated_with_alpn() {
+    let port = start(TlsConfig::new(fixture("first.pem"), fixture("first.key")));
+    let mut conn = connect(port, "h2");
+    assert_eq!(Some(&b"h2"[..]), conn.sess.get_alpn_protocol());
+
+    // connection preface followed by an empty SETTINGS frame
+    conn.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+    conn.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).unwrap();
+
+    // the server answers with its own SETTINGS frame
+    let mut frame = [0; 9];
+    conn.read_exact(&mut frame).unwrap();
+    assert_eq!(4, frame[3]);
+}
+
+#[test]
+fn certificate_reloaded_on_change() {
+    let dir = tempfile::tempdir().unwrap();
+    let cert = dir.path().join("cert.pem");
+    let key = dir.path().join("key.pem");
+    fs::copy(fixture("first.pem"), &cert).unwrap();
+    fs::copy(fixture("first.key"), &key).unwrap();
+
+    let config = TlsConfig::new(&cert, &key).with_reload_interval(Duration::from_millis(20));
+    let port = start(config);
+    assert_eq!(certificate(&fixture("first.pem")), served_certificate(port));
+
+    fs::copy(fixture("second.pem"), &cert).unwrap();
+    fs::copy(fixture("second.key"), &key).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(certificate(&fixture("second.pem")), served_certificate(port));
+
+    // a broken pair is ignored and the previous certificate stays in use
+    fs::write(&cert, "not a certificate").unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(certificate(&fixture("second.pem")), served_certificate(port));
+}
@@ -0,0 +1,68 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use std::sync::Mutex;
+
+use actix_web::http::StatusCode;
+use common::{NewComment, NewPost, NewUser};
+use lazy_static::lazy_static;
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+lazy_static! {
+    // "request > web::block > all_posts > sql" for every span opened, the blocking pool runs on
+    // other threads so the subscriber has to be the global one
+    static ref SPANS: Mutex<Vec<String>> = {
+        let subscriber = tracing_subscriber::registry().with(Recorder);
+        tracing::subscriber::set_global_default(subscriber).unwrap();
+        Mutex::new(vec![])
+    };
+}
+
+struct Recorder;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+    fn on_new_span(&self, _: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let path: Vec<_> = ctx
+            .span_scope(id)
+            .unwrap()
+            .from_root()
+            .map(|span| span.name())
+            .collect();
+        SPANS.lock().unwrap().push(path.join(" > "));
+    }
+}
+
+#[test]
+fn queries_are_traced_under_the_request() {
+    lazy_static::initialize(&SPANS);
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().published().create(&mut app, &frank);
+    NewComment::new().create(&mut app, &post, &frank);
+    SPANS.lock().unwrap().clear();
+
+    assert_eq!(app.get("/posts").status, StatusCode::OK);
+
+    let spans = SPANS.lock().unwrap();
+    assert_eq!(
+        *spans,
+        vec![
+            "request",
+            "request > web::block",
+            "request > web::block > table_versions",
+            "request > web::block > table_versions > sql",
+            "request > web::block > all_posts",
+            // the posts, their reactions, their comments and the comments' reactions
+            "request > web::block > all_posts > sql",
+            "request > web::block > all_posts > sql",
+            "request > web::block > all_posts > sql",
+            "request > web::block > all_posts > sql",
+        ]
+    );
+}
@@ -0,0 +1,185 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_http::Request;
+use actix_web::dev::{Body, Service, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::test::TestRequest;
+use actix_web::Error;
+use blog_actix::{fill_username_keys, PostEntry, Profile};
+use common::{NewPost, TestApp, ADMIN_TOKEN};
+use diesel::connection::SimpleConnection;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use serde_json::json;
+
+fn create<S>(app: &mut TestApp<S>, username: &str) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    app.post_json("/users", json!({ "username": username }))
+}
+
+fn rename<S>(app: &mut TestApp<S>, user_id: i32, token: &str, username: &str) -> common::Response
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let req = TestRequest::post()
+        .uri(&format!("/users/{}/rename", user_id))
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .set_json(&json!({ "username": username }));
+    app.call(req)
+}
+
+// Moves the renames back past the grace period
+fn expire_renames<S>(app: &TestApp<S>) {
+    app.pools
+        .writer()
+        .unwrap()
+        .batch_execute("UPDATE username_history SET renamed_at = datetime(renamed_at, '-31 days')")
+        .unwrap();
+}
+
+#[test]
+fn usernames_unique_regardless_of_case() {
+    let mut app = common::init();
+    let alice = create(&mut app, "Alice").owner();
+
+    let res = create(&mut app, "alice");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "This recird violates a unique contraint");
+
+    let found: Profile = app.get("/users/find/ALICE").json();
+    assert_eq!(found.id, alice.user.id);
+    assert_eq!(found.username, "Alice");
+}
+
+#[test]
+fn usernames_beyond_ascii_unique_regardless_of_case() {
+    let mut app = common::init();
+    let emile = create(&mut app, "Émile").owner();
+
+    let res = create(&mut app, "émile");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "This recird violates a unique contraint");
+
+    let found: Profile = app.get("/users/find/%C3%89MILE").json();
+    assert_eq!(found.id, emile.user.id);
+}
+
+#[test]
+fn keys_filled_in_for_existing_users() {
+    let mut app = common::init();
+    // as left by the migration, the keys of the users already there missing
+    app.pools
+        .writer()
+        .unwrap()
+        .batch_execute(
+            "INSERT INTO users (id, username, created_at, updated_at) VALUES
+                 (1, 'Émile', '2021-01-01 00:00:00', '2021-01-01 00:00:00'),
+                 (2, 'émile', '2021-01-02 00:00:00', '2021-01-02 00:00:00'),
+                 (3, 'Émile-2', '2021-01-03 00:00:00', '2021-01-03 00:00:00');
+             INSERT INTO username_history (user_id, username, username_key, renamed_at)
+             VALUES (3, 'expired', 'expired', datetime('now', '-31 days'));",
+        )
+        .unwrap();
+
+    let filled = fill_username_keys(&app.pools.writer().unwrap()).unwrap();
+    assert_eq!(filled, 3);
+    assert_eq!(fill_username_keys(&app.pools.writer().unwrap()).unwrap(), 0);
+
+    // the name the clashing user would have got is taken, a number is added
+    let found: Profile = app.get("/users/find/%C3%A9mile-2-2").json();
+    assert_eq!(found.id, 2);
+    assert_eq!(found.username, "émile-2-2");
+    // the old name of the renamed user finds the one keeping it
+    assert_eq!(app.get("/users/find/%C3%A9mile").json::<Profile>().id, 1);
+    assert_eq!(app.get("/users/find/%C3%A9mile-2").json::<Profile>().id, 3);
+    assert_eq!(create(&mut app, "ÉMILE").status, StatusCode::BAD_REQUEST);
+
+    let query = sql::<BigInt>("SELECT COUNT(*) FROM username_history WHERE username_key = 'expired'");
+    let remaining: i64 = query.get_result(&*app.pools.writer().unwrap()).unwrap();
+    assert_eq!(remaining, 0);
+}
+
+#[test]
+fn invalid_usernames_rejected() {
+    let mut app = common::init();
+    for username in ["", "two words", "a/b", &"x".repeat(51)] {
+        let res = create(&mut app, username);
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{:?}", username);
+        assert_eq!(res.err(), "Invalid username");
+    }
+
+    let frank = create(&mut app, "frank").owner();
+    let res = rename(&mut app, frank.user.id, &frank.token, "frank herbert");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn renamed_users_found_by_their_old_name() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank").owner();
+    let post = NewPost::new().published().create(&mut app, &frank.user);
+    assert_eq!(app.get("/posts").status, StatusCode::OK);
+
+    let res = rename(&mut app, frank.user.id, &frank.token, "francis");
+    assert_eq!(res.status, StatusCode::OK);
+    let profile: Profile = res.json();
+    assert_eq!(profile.username, "francis");
+
+    assert_eq!(app.get("/users/find/francis").json::<Profile>(), profile);
+    assert_eq!(app.get("/users/find/Frank").json::<Profile>(), profile);
+    let posts: Vec<PostEntry> = app.get("/posts").json();
+    assert_eq!((posts[0].0).0.id, post.id);
+    assert_eq!((posts[0].0).1.username, "francis");
+
+    // the old name is kept for its former owner during the grace period
+    let res = create(&mut app, "FRANK");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+    expire_renames(&app);
+    assert_eq!(app.get("/users/find/frank").status, StatusCode::NOT_FOUND);
+    assert_eq!(create(&mut app, "FRANK").status, StatusCode::OK);
+}
+
+#[test]
+fn old_names_taken_back() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank").owner();
+    let id = frank.user.id;
+
+    assert_eq!(rename(&mut app, id, &frank.token, "francis").status, StatusCode::OK);
+    assert_eq!(rename(&mut app, id, &frank.token, "Frank").status, StatusCode::OK);
+    // only the case changed, nothing to keep
+    assert_eq!(rename(&mut app, id, &frank.token, "FRANK").status, StatusCode::OK);
+
+    let found: Profile = app.get("/users/find/francis").json();
+    assert_eq!(found.username, "FRANK");
+    expire_renames(&app);
+    assert_eq!(app.get("/users/find/francis").status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get("/users/find/frank").json::<Profile>().id, id);
+}
+
+#[test]
+fn renames_need_the_users_token_and_a_free_name() {
+    let mut app = common::init();
+    let frank = create(&mut app, "frank").owner();
+    let bob = create(&mut app, "bob").owner();
+
+    assert_eq!(rename(&mut app, frank.user.id, &bob.token, "francis").status, StatusCode::UNAUTHORIZED);
+    let res = rename(&mut app, frank.user.id, &frank.token, "Bob");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "This recird violates a unique contraint");
+
+    // bob's old name stays his for now
+    assert_eq!(rename(&mut app, bob.user.id, &bob.token, "robert").status, StatusCode::OK);
+    let res = rename(&mut app, frank.user.id, &frank.token, "bob");
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+    let res = rename(&mut app, frank.user.id, ADMIN_TOKEN, "francis");
+    assert_eq!(res.status, StatusCode::OK);
+}
@@ -0,0 +1,68 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_web::http::StatusCode;
+use blog_actix::User;
+use common::NewUser;
+use serde_json::json;
+
+#[test]
+fn create_user_returns_the_new_user() {
+    let mut app = common::init();
+
+    let res = app.post_json("/users", json!({ "username": "Frank" }));
+    assert_eq!(res.status, StatusCode::OK);
+
+    let user: User = res.json();
+    assert_eq!(user.username, "Frank");
+}
+
+#[test]
+fn create_user_rejects_a_duplicate_username() {
+    let mut app = common::init();
+    NewUser::new().username("Frank").create(&mut app);
+
+    let res = app.post_json("/users", json!({ "username": "Frank" }));
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.err(), "This recird violates a unique contraint");
+}
+
+#[test]
+fn create_user_rejects_an_invalid_body() {
+    let mut app = common::init();
+
+    let res = app.post_json("/users", json!({ "name": "Frank" }));
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn find_user_by_username() {
+    let mut app = common::init();
+    let frank = NewUser::new().username("Frank").create(&mut app);
+
+    let res = app.get("/users/find/Frank");
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json::<User>(), frank);
+
+    let res = app.get("/users/find/Steve");
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(res.err(), "This record does not exist");
+}
+
+#[test]
+fn get_user_by_id() {
+    let mut app = common::init();
+    let frank = NewUser::new().create(&mut app);
+
+    let res = app.get(&format!("/users/{}", frank.id));
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json::<User>(), frank);
+
+    let res = app.get("/users/42");
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = app.get("/users/frank");
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,338 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+mod common;
+
+use actix_http::Request;
+use actix_web::dev::{Body, Service, ServiceResponse};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::test::TestRequest;
+use actix_web::Error;
+use blog_actix::{Delivery, Event, Webhook, WebhookConfig, Webhooks};
+use common::{NewComment, NewPost, NewUser, TestApp, ADMIN_TOKEN};
+use diesel::connection::SimpleConnection;
+use ring::{digest, hmac};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Received {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+// Stands in for a consumer of the webhooks, answering with the queued statuses then 200
+struct Receiver {
+    url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+    statuses: Arc<Mutex<VecDeque<u16>>>,
+}
+
+impl Receiver {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks?source=blog", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(vec![]));
+        let statuses = Arc::new(Mutex::new(VecDeque::new()));
+
+        let (log, queue) = (received.clone(), statuses.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                assert_eq!(request_line, "POST /hooks?source=blog HTTP/1.1\r\n");
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    let (name, value) = line.split_at(line.find(':').unwrap());
+                    headers.insert(name.to_lowercase(), value[1..].trim().to_owned());
+                }
+                let length = headers["content-length"].parse().unwrap();
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                log.lock().unwrap().push(Received {
+                    headers,
+                    body: String::from_utf8(body).unwrap(),
+                });
+                let status = queue.lock().unwrap().pop_front().unwrap_or(200);
+                write!(stream, "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+        });
+
+        Receiver { url, received, statuses }
+    }
+
+    fn fail_next(&self, status: u16) {
+        self.statuses.lock().unwrap().push_back(status);
+    }
+
+    fn received(&self) -> std::sync::MutexGuard<'_, Vec<Received>> {
+        self.received.lock().unwrap()
+    }
+}
+
+fn admin(req: TestRequest) -> TestRequest {
+    req.header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+}
+
+fn register<S>(app: &mut TestApp<S>, body: Value) -> Webhook
+    where
+        S: Service<Request=Request, Response=ServiceResponse<Body>, Error=Error>,
+{
+    let res = app.call(admin(TestRequest::post().uri("/admin/webhooks").set_json(&body)));
+    assert_eq!(res.status, StatusCode::OK);
+    res.json()
+}
+
+fn deliver<S>(app: &TestApp<S>, config: WebhookConfig) -> usize {
+    Webhooks::new(app.pools.clone(), config).deliver_due().unwrap()
+}
+
+fn no_backoff() -> WebhookConfig {
+    WebhookConfig {
+        backoff: Duration::from_secs(0),
+        ..WebhookConfig::default()
+    }
+}
+
+#[test]
+fn webhooks_require_the_admin_token() {
+    let mut app = common::init();
+
+    let res = app.post_json("/admin/webhooks", json!({ "url": "http://localhost/", "events": ["user.created"] }));
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let res = app.get("/admin/webhooks");
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let res = app.request(Method::DELETE, "/admin/webhooks/1");
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn webhooks_are_registered_and_removed() {
+    let mut app = common::init();
+
+    let webhook = register(&mut app, json!({ "url": "https://hooks.example/blog", "events": ["post.published"] }));
+    assert_eq!(webhook.events, vec![Event::PostPublished]);
+    // generated, and only shown once
+    assert_eq!(webhook.secret.as_ref().map(String::len), Some(64));
+    let listed: Vec<Webhook> = app.call(admin(TestRequest::get().uri("/admin/webhooks"))).json();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id, webhook.id);
+    assert_eq!(listed[0].secret, None);
+
+    let uri = format!("/admin/webhooks/{}", webhook.id);
+    let res = app.call(admin(TestRequest::with_uri(&uri).method(Method::DELETE)));
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    let res = app.call(admin(TestRequest::with_uri(&uri).method(Method::DELETE)));
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let listed: Vec<Webhook> = app.call(admin(TestRequest::get().uri("/admin/webhooks"))).json();
+    assert!(listed.is_empty());
+}
+
+#[test]
+fn invalid_webhooks_rejected() {
+    let mut app = common::init();
+
+    for body in [
+        json!({ "url": "not a url", "events": ["user.created"] }),
+        json!({ "url": "ftp://hooks.example/", "events": ["user.created"] }),
+        json!({ "url": "https://hooks.example/", "events": [] }),
+        json!({ "url": "https://hooks.example/", "events": ["user.created"], "secret": "" }),
+    ] {
+        let res = app.call(admin(TestRequest::post().uri("/admin/webhooks").set_json(&body)));
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", body);
+        assert!(res.err().starts_with("Invalid webhook"));
+    }
+
+    let body = json!({ "url": "https://hooks.example/", "events": ["post.deleted"] });
+    let res = app.call(admin(TestRequest::post().uri("/admin/webhooks").set_json(&body)));
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn deliveries_are_signed() {
+    let mut app = common::init();
+    let receiver = Receiver::start();
+    register(&mut app, json!({ "url": receiver.url, "events": ["user.created"], "secret": "shh" }));
+
+    let frank = NewUser::new().username("frank").create(&mut app);
+    assert_eq!(deliver(&app, WebhookConfig::default()), 1);
+
+    let received = receiver.received();
+    assert_eq!(received.len(), 1);
+    let Received { headers, body } = &received[0];
+    assert_eq!(headers["x-blog-event"], "user.created");
+    assert_eq!(headers["content-type"], "application/json");
+
+    let payload: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(payload["event"], "user.created");
+    assert_eq!(payload["data"], json!(frank));
+
+    // what a consumer checks
+    let key = hmac::VerificationKey::new(&digest::SHA256, b"shh");
+    let message = format!("{}.{}", headers["x-blog-timestamp"], body);
+    let signature = headers["x-blog-signature"].trim_start_matches("sha256=");
+    let signature: Vec<u8> = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).unwrap())
+        .collect();
+    assert!(hmac::verify(&key, message.as_bytes(), &signature).is_ok());
+    assert!(hmac::verify(&key, b"0.{}", &signature).is_err());
+}
+
+#[test]
+fn only_subscribed_events_delivered() {
+    let mut app = common::init();
+    let receiver = Receiver::start();
+    register(&mut app, json!({ "url": receiver.url, "events": ["post.published", "comment.created"] }));
+
+    let frank = NewUser::new().create(&mut app);
+    let post = NewPost::new().create(&mut app, &frank);
+    assert_eq!(deliver(&app, WebhookConfig::default()), 0);
+
+    // published twice, announced once
+    app.post(&format!("/posts/{}/publish", post.id));
+    app.post(&format!("/posts/{}/publish", post.id));
+    NewComment::new().create(&mut app, &post, &frank);
+    assert_eq!(deliver(&app, WebhookConfig::default()), 2);
+
+    let events: Vec<_> = receiver
+        .received()
+        .iter()
+        .map(|received| received.headers["x-blog-event"].clone())
+        .collect();
+    assert_eq!(events, vec!["post.published", "comment.created"]);
+}
+
+#[test]
+fn failed_deliveries_retried() {
+    let mut app = common::init();
+    let receiver = Receiver::start();
+    let webhook = register(&mut app, json!({ "url": receiver.url, "events": ["user.created"] }));
+    NewUser::new().create(&mut app);
+
+    receiver.fail_next(500);
+    assert_eq!(deliver(&app, no_backoff()), 0);
+    assert_eq!(deliver(&app, no_backoff()), 1);
+    // nothing left to send
+    assert_eq!(deliver(&app, no_backoff()), 0);
+
+    let received = receiver.received();
+    assert_eq!(received.len(), 2);
+    // same delivery both times
+    assert_eq!(received[0].headers["x-blog-delivery"], received[1].headers["x-blog-delivery"]);
+    drop(received);
+
+    let uri = format!("/admin/webhooks/{}/deliveries", webhook.id);
+    let deliveries: Vec<Delivery> = app.call(admin(TestRequest::get().uri(&uri))).json();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].attempts, 2);
+    assert_eq!(deliveries[0].last_error.as_deref(), Some("HTTP status 500"));
+    assert!(deliveries[0].delivered_at.is_some());
+}
+
+#[test]
+fn retries_wait_for_the_backoff() {
+    let mut app = common::init();
+    let receiver = Receiver::start();
+    register(&mut app, json!({ "url": receiver.url, "events": ["user.created"] }));
+    NewUser::new().create(&mut app);
+
+    receiver.fail_next(503);
+    assert_eq!(deliver(&app, WebhookConfig::default()), 0);
+    assert_eq!(deliver(&app, WebhookConfig::default()), 0);
+    assert_eq!(receiver.received().len(), 1);
+}
+
+#[test]
+fn failed_deliveries_hold_up_the_later_ones() {
+    let mut app = common::init();
+    let receiver = Receiver::start();
+    register(&mut app, json!({ "url": receiver.url, "events": ["user.created"] }));
+    let frank = NewUser::new().create(&mut app);
+    let bob = NewUser::new().create(&mut app);
+
+    receiver.fail_next(500);
+    assert_eq!(deliver(&app, WebhookConfig::default()), 0);
+    // bob's waits for frank's retry rather than overtaking it
+    assert_eq!(deliver(&app, WebhookConfig::default()), 0);
+    assert_eq!(receiver.received().len(), 1);
+
+    app.pools
+        .writer()
+        .unwrap()
+        .batch_execute("UPDATE webhook_deliveries SET next_attempt_at = datetime('now', '-1 minute')")
+        .unwrap();
+    assert_eq!(deliver(&app, WebhookConfig::default()), 2);
+    let users: Vec<i64> = receiver
+        .received()
+        .iter()
+        .map(|received| serde_json::from_str::<Value>(&received.body).unwrap()["data"]["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(users, vec![frank.id as i64, frank.id as i64, bob.id as i64]);
+}
+
+#[test]
+fn deliveries_given_up_after_max_attempts() {
+    let mut app = common::init();
+    let receiver = Receiver::start();
+    let webhook = register(&mut app, json!({ "url": receiver.url, "events": ["user.created"] }));
+    NewUser::new().create(&mut app);
+
+    let config = WebhookConfig {
+        max_attempts: 3,
+        ..no_backoff()
+    };
+    for _ in 0..4 {
+        receiver.fail_next(500);
+    }
+    for _ in 0..4 {
+        assert_eq!(deliver(&app, config.clone()), 0);
+    }
+    assert_eq!(receiver.received().len(), 3);
+
+    let uri = format!("/admin/webhooks/{}/deliveries", webhook.id);
+    let deliveries: Vec<Delivery> = app.call(admin(TestRequest::get().uri(&uri))).json();
+    assert_eq!(deliveries[0].attempts, 3);
+    assert!(deliveries[0].failed_at.is_some());
+    assert!(deliveries[0].delivered_at.is_none());
+
+    let metrics = String::from_utf8(app.get("/metrics").body).unwrap();
+    assert!(metrics.contains(r#"webhook_attempts_total{event="user.created",result="failed"}"#));
+}
+
+#[test]
+fn stalled_webhooks_do_not_hold_up_the_others() {
+    let mut app = common::init();
+    // accepts the connections, never answers
+    let stalled = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stalled_url = format!("http://{}/hooks", stalled.local_addr().unwrap());
+    let receiver = Receiver::start();
+    for url in [&stalled_url, &stalled_url, &receiver.url] {
+        register(&mut app, json!({ "url": url, "events": ["user.created"] }));
+    }
+    NewUser::new().create(&mut app);
+
+    let config = WebhookConfig {
+        timeout: Duration::from_secs(1),
+        ..WebhookConfig::default()
+    };
+    let started = Instant::now();
+    assert_eq!(deliver(&app, config), 1);
+    // both stalled webhooks waited on at once
+    assert!(started.elapsed() < Duration::from_millis(1900), "{:?}", started.elapsed());
+    assert_eq!(receiver.received().len(), 1);
+}
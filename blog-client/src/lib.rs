@@ -0,0 +1,372 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use awc::error::{PayloadError, SendRequestError};
+use awc::http::{HeaderMap, Method, StatusCode};
+use awc::{Client, ClientResponse};
+use bytes::Bytes;
+use futures::future::{self, Either, Loop};
+use futures::{Future, Stream};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio_timer::Delay;
+use url::Url;
+
+pub use blog_actix::routes::changes::Changes;
+pub use blog_actix::routes::follows::Feed;
+pub use blog_actix::routes::users::UserToken;
+pub use blog_actix::{
+    Change, Comment, CommentWithAuthor, Entity, ErrorResponse, Follow, Operation, Post, PostEntry,
+    PostWithComment, Profile, ProfileChanges, Reaction, ReactionKind, Reactions, Snapshot, User,
+    UserPostEntry, RECORD_ALREADY_EXIST,
+};
+
+// Post listings embed every comment, so they can be much larger than awc's 64kb default
+const BODY_LIMIT: usize = 16 * 1024 * 1024;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
+// A new user along with its token, which the server only sends back in a header of the answer
+#[derive(Debug, PartialEq)]
+pub struct CreatedUser {
+    pub user: User,
+    pub token: String,
+}
+
+// Mirrors the variants of the server's AppError that can be told apart by the status code
+#[derive(Debug)]
+pub enum Error {
+    RecordAlreadyExist(String),
+    // the other 400s, a field or a query parameter the server refused
+    BadRequest(String),
+    RecordNotFound(String),
+    ReferenceNotFound(String),
+    Unauthorized(String),
+    Unavailable(String),
+    Server(StatusCode, String),
+    Connect(String),
+    Request(String),
+}
+
+impl Error {
+    fn from_response(status: StatusCode, message: String) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST if message == RECORD_ALREADY_EXIST => Error::RecordAlreadyExist(message),
+            StatusCode::BAD_REQUEST => Error::BadRequest(message),
+            StatusCode::NOT_FOUND => Error::RecordNotFound(message),
+            StatusCode::UNPROCESSABLE_ENTITY => Error::ReferenceNotFound(message),
+            StatusCode::UNAUTHORIZED => Error::Unauthorized(message),
+            StatusCode::SERVICE_UNAVAILABLE => Error::Unavailable(message),
+            _ => Error::Server(status, message),
+        }
+    }
+
+    // The server answers 503 only when it could not get a database connection, so nothing was
+    // written and any request can be sent again. Other failures are only retried for reads.
+    fn is_retryable(&self, method: &Method) -> bool {
+        match self {
+            Error::Connect(_) | Error::Unavailable(_) => true,
+            Error::Request(_) => method == Method::GET,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::RecordAlreadyExist(e)
+            | Error::BadRequest(e)
+            | Error::RecordNotFound(e)
+            | Error::ReferenceNotFound(e)
+            | Error::Unauthorized(e)
+            | Error::Unavailable(e) => write!(f, "{}", e),
+            Error::Server(status, e) => write!(f, "Server Error {}: {}", status, e),
+            Error::Connect(e) => write!(f, "Connect Error: {}", e),
+            Error::Request(e) => write!(f, "Request Error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<SendRequestError> for Error {
+    fn from(e: SendRequestError) -> Self {
+        match e {
+            SendRequestError::Connect(e) => Error::Connect(e.to_string()),
+            e => Error::Request(e.to_string()),
+        }
+    }
+}
+
+// What can be reacted to, the server has no reactions to users
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReactionTarget {
+    Post,
+    Comment,
+}
+
+impl ReactionTarget {
+    fn path(self) -> &'static str {
+        match self {
+            ReactionTarget::Post => "posts",
+            ReactionTarget::Comment => "comments",
+        }
+    }
+}
+
+// The futures returned by the client use awc and have to run inside an actix System.
+#[derive(Clone)]
+pub struct BlogClient {
+    client: Client,
+    base_url: String,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl BlogClient {
+    pub fn new(base_url: &str) -> Self {
+        BlogClient {
+            client: Client::default(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+        }
+    }
+
+    // Number of times a failed request is sent again, waiting twice as long before each attempt
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    // Users
+    // The token in the answer is the only way to edit the user's profile later on
+    pub fn create_user(&self, username: &str) -> impl Future<Item=CreatedUser, Error=Error> {
+        let input = json!({ "username": username });
+        self.send_with_headers(Method::POST, self.url(&["users"]), Some(input), None)
+            .and_then(|(user, headers)| {
+                let token = headers
+                    .get(blog_actix::routes::users::TOKEN_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or_else(|| Error::Request("answer without the user's token".to_owned()))?;
+                Ok(CreatedUser { user, token: token.to_owned() })
+            })
+    }
+
+    pub fn find_user(&self, username: &str) -> impl Future<Item=Profile, Error=Error> {
+        self.send(Method::GET, self.url(&["users", "find", username]), None, None)
+    }
+
+    pub fn get_user(&self, user_id: i32) -> impl Future<Item=Profile, Error=Error> {
+        self.send(Method::GET, self.url(&["users", &user_id.to_string()]), None, None)
+    }
+
+    // `token` is the user's, or the admin token
+    pub fn update_profile(&self, user_id: i32, token: &str, changes: &ProfileChanges)
+                          -> impl Future<Item=Profile, Error=Error> {
+        let input = serde_json::to_value(changes).unwrap_or(Value::Null);
+        self.send(Method::PATCH, self.url(&["users", &user_id.to_string()]), Some(input), Some(token.to_owned()))
+    }
+
+    // The old username keeps finding the user for a while, with the same token
+    pub fn rename_user(&self, user_id: i32, token: &str, username: &str) -> impl Future<Item=Profile, Error=Error> {
+        let input = json!({ "username": username });
+        self.send(Method::POST, self.url(&["users", &user_id.to_string(), "rename"]), Some(input), Some(token.to_owned()))
+    }
+
+    // `token` is the user's, or the admin token, the user's previous token stops working
+    pub fn issue_token(&self, user_id: i32, token: &str) -> impl Future<Item=UserToken, Error=Error> {
+        self.send(Method::POST, self.url(&["users", &user_id.to_string(), "token"]), None, Some(token.to_owned()))
+    }
+
+    // Follows, `token` being the follower's, or the admin token
+    pub fn follow(&self, user_id: i32, token: &str, followee_id: i32) -> impl Future<Item=Follow, Error=Error> {
+        let input = json!({ "user_id": followee_id });
+        self.send(Method::POST, self.url(&["users", &user_id.to_string(), "following"]), Some(input), Some(token.to_owned()))
+    }
+
+    pub fn unfollow(&self, user_id: i32, token: &str, followee_id: i32) -> impl Future<Item=(), Error=Error> {
+        let url = self.url(&["users", &user_id.to_string(), "following", &followee_id.to_string()]);
+        self.send(Method::DELETE, url, None, Some(token.to_owned()))
+    }
+
+    pub fn following(&self, user_id: i32) -> impl Future<Item=Vec<User>, Error=Error> {
+        self.send(Method::GET, self.url(&["users", &user_id.to_string(), "following"]), None, None)
+    }
+
+    // A page of the feed, `before` being the `next` cursor of the previous page
+    pub fn feed(&self, user_id: i32, before: Option<&str>) -> impl Future<Item=Feed, Error=Error> {
+        let url = with_query(self.url(&["users", &user_id.to_string(), "feed"]), "before", before);
+        self.send(Method::GET, url, None, None)
+    }
+
+    // Posts
+    pub fn create_post(&self, user_id: i32, title: &str, body: &str) -> impl Future<Item=Post, Error=Error> {
+        let input = json!({ "title": title, "body": body });
+        self.send(Method::POST, self.url(&["users", &user_id.to_string(), "posts"]), Some(input), None)
+    }
+
+    pub fn publish_post(&self, post_id: i32) -> impl Future<Item=Post, Error=Error> {
+        self.send(Method::POST, self.url(&["posts", &post_id.to_string(), "publish"]), None, None)
+    }
+
+    pub fn users_posts(&self, user_id: i32) -> impl Future<Item=Vec<UserPostEntry>, Error=Error> {
+        self.send(Method::GET, self.url(&["users", &user_id.to_string(), "posts"]), None, None)
+    }
+
+    pub fn all_posts(&self) -> impl Future<Item=Vec<PostEntry>, Error=Error> {
+        self.send(Method::GET, self.url(&["posts"]), None, None)
+    }
+
+    // Comments
+    pub fn add_comment(&self, post_id: i32, user_id: i32, body: &str) -> impl Future<Item=Comment, Error=Error> {
+        let input = json!({ "user_id": user_id, "body": body });
+        self.send(Method::POST, self.url(&["posts", &post_id.to_string(), "comments"]), Some(input), None)
+    }
+
+    pub fn post_comments(&self, post_id: i32) -> impl Future<Item=Vec<CommentWithAuthor>, Error=Error> {
+        self.send(Method::GET, self.url(&["posts", &post_id.to_string(), "comments"]), None, None)
+    }
+
+    pub fn user_comments(&self, user_id: i32) -> impl Future<Item=Vec<(Comment, PostWithComment)>, Error=Error> {
+        self.send(Method::GET, self.url(&["users", &user_id.to_string(), "comments"]), None, None)
+    }
+
+    // `token` is the user's, or the admin token
+    pub fn react(&self, target: ReactionTarget, target_id: i32, user_id: i32, token: &str, kind: ReactionKind)
+                 -> impl Future<Item=Reaction, Error=Error> {
+        let input = json!({ "user_id": user_id, "kind": kind });
+        let url = self.url(&[target.path(), &target_id.to_string(), "reactions"]);
+        self.send(Method::POST, url, Some(input), Some(token.to_owned()))
+    }
+
+    pub fn unreact(&self, target: ReactionTarget, target_id: i32, user_id: i32, token: &str, kind: ReactionKind)
+                   -> impl Future<Item=(), Error=Error> {
+        let url = self.url(&[
+            target.path(),
+            &target_id.to_string(),
+            "reactions",
+            &user_id.to_string(),
+            kind.as_str(),
+        ]);
+        self.send(Method::DELETE, url, None, Some(token.to_owned()))
+    }
+
+    // Changes, `since` being the `next` token of the previous page
+    pub fn changes(&self, since: Option<&str>) -> impl Future<Item=Changes, Error=Error> {
+        self.send(Method::GET, with_query(self.url(&["changes"]), "since", since), None, None)
+    }
+
+    // Admin
+    pub fn create_backup(&self, admin_token: &str) -> impl Future<Item=Snapshot, Error=Error> {
+        self.send(Method::POST, self.url(&["admin", "backups"]), None, Some(admin_token.to_owned()))
+    }
+
+    // The base URL followed by the segments, each percent-encoded
+    fn url(&self, segments: &[&str]) -> Result<Url, Error> {
+        let mut url = Url::parse(&self.base_url).map_err(|e| Error::Request(e.to_string()))?;
+        url.path_segments_mut()
+            .map_err(|_| Error::Request(format!("{} can't be a base URL", self.base_url)))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    fn send<T>(
+        &self,
+        method: Method,
+        url: Result<Url, Error>,
+        body: Option<Value>,
+        token: Option<String>,
+    ) -> impl Future<Item=T, Error=Error>
+        where
+            T: DeserializeOwned + 'static,
+    {
+        self.send_with_headers(method, url, body, token).map(|(decoded, _)| decoded)
+    }
+
+    // Same as send, along with the headers of the answer
+    fn send_with_headers<T>(
+        &self,
+        method: Method,
+        url: Result<Url, Error>,
+        body: Option<Value>,
+        token: Option<String>,
+    ) -> impl Future<Item=(T, HeaderMap), Error=Error>
+        where
+            T: DeserializeOwned + 'static,
+    {
+        let url = match url {
+            Ok(url) => url,
+            Err(e) => return Either::A(future::err(e)),
+        };
+        let client = self.client.clone();
+        let retries = self.retries;
+        let backoff = self.backoff;
+
+        Either::B(future::loop_fn(0, move |attempt| {
+            let mut request = client.request(method.clone(), url.as_str());
+            if let Some(token) = &token {
+                request = request.bearer_auth(token);
+            }
+            let sent = match &body {
+                Some(body) => request.send_json(body),
+                None => request.send(),
+            };
+
+            let method = method.clone();
+            sent.map_err(Error::from)
+                .and_then(decode)
+                .then(move |result| match result {
+                    Err(ref e) if attempt < retries && e.is_retryable(&method) => {
+                        let delay = Delay::new(Instant::now() + backoff * 2u32.pow(attempt));
+                        Either::A(delay.then(move |_| Ok(Loop::Continue(attempt + 1))))
+                    }
+                    result => Either::B(future::result(result.map(Loop::Break))),
+                })
+        }))
+    }
+}
+
+// Adds the percent-encoded query parameter when there is a value
+fn with_query(url: Result<Url, Error>, name: &str, value: Option<&str>) -> Result<Url, Error> {
+    url.map(|mut url| {
+        if let Some(value) = value {
+            url.query_pairs_mut().append_pair(name, value);
+        }
+        url
+    })
+}
+
+fn decode<S, T>(mut res: ClientResponse<S>) -> impl Future<Item=(T, HeaderMap), Error=Error>
+    where
+        S: Stream<Item=Bytes, Error=PayloadError> + 'static,
+        T: DeserializeOwned + 'static,
+{
+    let status = res.status();
+    let headers = res.headers().clone();
+    // deletions answer without a body, read as ()
+    if status == StatusCode::NO_CONTENT {
+        let decoded = serde_json::from_value(Value::Null).map_err(|e| Error::Request(e.to_string()));
+        return Either::A(Either::A(future::result(decoded.map(|decoded| (decoded, headers)))));
+    }
+    if status.is_success() {
+        return Either::A(Either::B(
+            res.json::<T>()
+                .limit(BODY_LIMIT)
+                .map(|decoded| (decoded, headers))
+                .map_err(|e| Error::Request(e.to_string())),
+        ));
+    }
+
+    // AppError responses carry an ErrorResponse, errors raised by actix itself are plain text
+    Either::B(res.body().then(move |body| {
+        let body = body.unwrap_or_default();
+        let message = serde_json::from_slice::<ErrorResponse>(&body)
+            .map(|e| e.err)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned());
+        Err(Error::from_response(status, message))
+    }))
+}
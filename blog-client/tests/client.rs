@@ -0,0 +1,208 @@
+#[macro_use]
+extern crate diesel_migrations;
+
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use actix_rt::System;
+use blog_actix::{BackupConfig, Blog};
+use blog_client::{BlogClient, Error, ProfileChanges, ReactionKind, ReactionTarget};
+use diesel::{Connection, SqliteConnection};
+use futures::Future;
+use tempfile::TempDir;
+
+embed_migrations!("../blog-actix/migrations");
+
+const ADMIN_TOKEN: &str = "secret";
+
+struct Server {
+    url: String,
+    // keeps the database and snapshots alive until the test ends
+    _dir: TempDir,
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .unwrap()
+}
+
+// Runs a Blog on a fresh database in a background thread, the server stops with the test process
+fn start_server(delay: Duration) -> Server {
+    let dir = TempDir::new().unwrap();
+    let database_url = dir.path().join("blog.sqlite3").to_str().unwrap().to_owned();
+    let conn = SqliteConnection::establish(&database_url).unwrap();
+    embedded_migrations::run(&conn).unwrap();
+
+    let port = free_port();
+    let backups = BackupConfig {
+        dir: dir.path().join("backups"),
+        interval: None,
+        retain: 2,
+        admin_token: ADMIN_TOKEN.to_owned(),
+    };
+    thread::spawn(move || {
+        thread::sleep(delay);
+        Blog::new(port).with_backups(backups).run(database_url)
+    });
+
+    if delay == Duration::from_secs(0) {
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    Server {
+        url: format!("http://127.0.0.1:{}", port),
+        _dir: dir,
+    }
+}
+
+fn run<F: Future>(f: F) -> Result<F::Item, F::Error> {
+    System::new("blog-client-test").block_on(f)
+}
+
+#[test]
+fn every_route_round_trips_the_models() {
+    let server = start_server(Duration::from_secs(0));
+    let client = BlogClient::new(&server.url);
+
+    let created = run(client.create_user("Frank")).unwrap();
+    let frank = created.user;
+    let bob_created = run(client.create_user("Bob")).unwrap();
+    let bob = bob_created.user;
+    let found = run(client.find_user("Frank")).unwrap();
+    assert_eq!((found.id, found.username.as_str()), (frank.id, "Frank"));
+    assert_eq!(run(client.get_user(bob.id)).unwrap().username, bob.username);
+
+    let post = run(client.create_post(frank.id, "Frank says hello", "Hello friends")).unwrap();
+    assert!(!post.published);
+    assert!(run(client.all_posts()).unwrap().is_empty());
+
+    let post = run(client.publish_post(post.id)).unwrap();
+    assert!(post.published);
+
+    let comment = run(client.add_comment(post.id, bob.id, "Hi Frank")).unwrap();
+    assert_eq!(comment.post_id, post.id);
+
+    let posts = run(client.all_posts()).unwrap();
+    assert_eq!(posts.len(), 1);
+    let ((listed, author), comments) = &posts[0];
+    assert_eq!(listed.id, post.id);
+    assert_eq!(author, &frank);
+    assert_eq!(comments[0].1, bob);
+
+    let users_posts = run(client.users_posts(frank.id)).unwrap();
+    assert_eq!(users_posts[0].0.title, "Frank says hello");
+
+    let post_comments = run(client.post_comments(post.id)).unwrap();
+    assert_eq!(post_comments[0].0.body, "Hi Frank");
+
+    let user_comments = run(client.user_comments(bob.id)).unwrap();
+    assert_eq!(user_comments[0].1.title, "Frank says hello");
+
+    let reaction = run(client.react(ReactionTarget::Comment, comment.id, frank.id, &created.token, ReactionKind::Like)).unwrap();
+    assert_eq!(reaction.target_id, comment.id);
+    let post_comments = run(client.post_comments(post.id)).unwrap();
+    assert_eq!(post_comments[0].0.reactions.as_ref().unwrap()[&ReactionKind::Like], 1);
+    run(client.unreact(ReactionTarget::Comment, comment.id, frank.id, &created.token, ReactionKind::Like)).unwrap();
+
+    let follow = run(client.follow(bob.id, &bob_created.token, frank.id)).unwrap();
+    assert_eq!(follow.followee_id, frank.id);
+    assert_eq!(run(client.following(bob.id)).unwrap(), vec![frank]);
+    let feed = run(client.feed(bob.id, None)).unwrap();
+    assert_eq!((feed.posts[0].0).0.id, post.id);
+    assert!(feed.next.is_none());
+    run(client.unfollow(bob.id, &bob_created.token, follow.followee_id)).unwrap();
+    assert!(run(client.feed(bob.id, None)).unwrap().posts.is_empty());
+
+    let changes = run(client.changes(None)).unwrap();
+    assert_eq!(changes.changes.len(), 5);
+    let changes = run(client.changes(Some(&changes.next))).unwrap();
+    assert!(changes.changes.is_empty());
+
+    let snapshot = run(client.create_backup(ADMIN_TOKEN)).unwrap();
+    assert!(snapshot.size > 0);
+
+    let changes = ProfileChanges {
+        display_name: Some("Frank H.".to_owned()),
+        ..ProfileChanges::default()
+    };
+    let profile = run(client.update_profile(found.id, &created.token, &changes)).unwrap();
+    assert_eq!(profile.display_name.as_deref(), Some("Frank H."));
+    assert_eq!(run(client.get_user(found.id)).unwrap(), profile);
+
+    let renamed = run(client.rename_user(found.id, &created.token, "Francis")).unwrap();
+    assert_eq!(renamed.username, "Francis");
+    assert_eq!(run(client.find_user("frank")).unwrap(), renamed);
+
+    let issued = run(client.issue_token(found.id, &created.token)).unwrap();
+    match run(client.rename_user(found.id, &created.token, "Frank")) {
+        Err(Error::Unauthorized(_)) => {}
+        other => panic!("expected Unauthorized, got {:?}", other),
+    }
+    assert_eq!(run(client.rename_user(found.id, &issued.token, "Frank")).unwrap().username, "Frank");
+}
+
+#[test]
+fn app_errors_are_decoded() {
+    let server = start_server(Duration::from_secs(0));
+    let client = BlogClient::new(&server.url);
+
+    run(client.create_user("Frank")).unwrap();
+    match run(client.create_user("Frank")) {
+        Err(Error::RecordAlreadyExist(_)) => {}
+        other => panic!("expected RecordAlreadyExist, got {:?}", other),
+    }
+    match run(client.create_user("Frank Herbert")) {
+        Err(Error::BadRequest(message)) => assert_eq!(message, "Invalid username"),
+        other => panic!("expected BadRequest, got {:?}", other),
+    }
+    match run(client.get_user(42)) {
+        Err(Error::RecordNotFound(_)) => {}
+        other => panic!("expected RecordNotFound, got {:?}", other),
+    }
+    match run(client.add_comment(42, 1, "Nobody wrote this post")) {
+        Err(Error::ReferenceNotFound(_)) => {}
+        other => panic!("expected ReferenceNotFound, got {:?}", other),
+    }
+    match run(client.create_backup("wrong")) {
+        Err(Error::Unauthorized(_)) => {}
+        other => panic!("expected Unauthorized, got {:?}", other),
+    }
+}
+
+#[test]
+fn path_and_query_encoded() {
+    let server = start_server(Duration::from_secs(0));
+    let client = BlogClient::new(&server.url);
+
+    let created = run(client.create_user("what?#%20")).unwrap();
+    assert_eq!(run(client.find_user("what?#%20")).unwrap().id, created.user.id);
+
+    // sent as is, the & would end the token and the rest would be another parameter
+    match run(client.changes(Some("1&since=2"))) {
+        Err(Error::BadRequest(message)) => assert_eq!(message, "Invalid sync token"),
+        other => panic!("expected BadRequest, got {:?}", other),
+    }
+    match run(client.feed(created.user.id, Some("7#next"))) {
+        Err(Error::BadRequest(message)) => assert_eq!(message, "Invalid page cursor"),
+        other => panic!("expected BadRequest, got {:?}", other),
+    }
+}
+
+#[test]
+fn requests_are_retried_until_the_server_is_up() {
+    let server = start_server(Duration::from_millis(300));
+
+    let client = BlogClient::new(&server.url).retries(6, Duration::from_millis(50));
+    assert!(run(client.all_posts()).unwrap().is_empty());
+
+    let impatient = BlogClient::new("http://127.0.0.1:1").retries(1, Duration::from_millis(1));
+    match run(impatient.all_posts()) {
+        Err(Error::Connect(_)) => {}
+        other => panic!("expected Connect, got {:?}", other),
+    }
+}
@@ -2,18 +2,52 @@
 extern crate actix_web;
 
 use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
-use actix_web::{App, HttpRequest, HttpResponse, HttpServer, middleware, Result, web};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Result, web};
+use actix_web::http::Method;
 use actix_web::error::{Error, InternalError, JsonPayloadError};
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use server_common::cors::Cors;
+use server_common::logging::RequestLog;
+use server_common::metrics::Metrics;
+use server_common::rate_limit::{Limit, RateLimit};
+use server_common::security::SecurityHeaders;
+use server_common::shutdown;
+use server_common::certs::TlsConfig;
+use server_common::prometheus::{register_int_gauge, register_int_gauge_vec, IntGauge, IntGaugeVec};
 
-const LOG_FORMAT: &'static str = r#""%r" %s %b "%{User-Agent}i" %D"#;
 static SERVER_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
+lazy_static! {
+    static ref MESSAGES_STORED: IntGauge = register_int_gauge!(
+        "messages_stored",
+        "Number of messages held in memory"
+    )
+    .unwrap();
+    static ref WORKER_REQUESTS: IntGaugeVec = register_int_gauge_vec!(
+        "worker_request_count",
+        "request_count of each worker, as returned in the responses",
+        &["server_id"]
+    )
+    .unwrap();
+}
+
 pub struct MessageApp {
+    host: String,
     port: u16,
+    storage: Option<PathBuf>,
+    drain_timeout: Duration,
+    tls: Option<TlsConfig>,
+    cors: Cors,
+    security_headers: SecurityHeaders,
+    rate_limit: RateLimit,
 }
 
 #[derive(Serialize)]
@@ -29,6 +63,19 @@ struct AppState {
     messages: Arc<Mutex<Vec<String>>>,
 }
 
+impl AppState {
+    // request_count is per worker, so it is copied to the gauge every time it changes instead
+    // of being read by whichever worker handles /metrics
+    fn count_request(&self) -> usize {
+        let request_count = self.request_count.get() + 1;
+        self.request_count.set(request_count);
+        WORKER_REQUESTS
+            .with_label_values(&[&self.server_id.to_string()])
+            .set(request_count as i64);
+        request_count
+    }
+}
+
 #[derive(Deserialize)]
 struct PostInput {
     message: String
@@ -50,8 +97,7 @@ struct PostError {
 
 #[get("/")]
 fn index(state: web::Data<AppState>) -> Result<web::Json<IndexResponse>> {
-    let request_count = state.request_count.get() + 1;
-    state.request_count.set(request_count);
+    let request_count = state.count_request();
     let ms = state.messages.lock().unwrap();
 
     Ok(web::Json(IndexResponse {
@@ -63,8 +109,7 @@ fn index(state: web::Data<AppState>) -> Result<web::Json<IndexResponse>> {
 
 #[post("/clear")]
 fn clear(state: web::Data<AppState>) -> Result<web::Json<IndexResponse>> {
-    let request_count = state.request_count.get() + 1;
-    state.request_count.set(request_count);
+    let request_count = state.count_request();
     let mut ms = state.messages.lock().unwrap();
     ms.clear();
 
@@ -77,8 +122,7 @@ fn clear(state: web::Data<AppState>) -> Result<web::Json<IndexResponse>> {
 
 
 fn post(msg: web::Json<PostInput>, state: web::Data<AppState>) -> Result<web::Json<PostResponse>> {
-    let request_count = state.request_count.get() + 1;
-    state.request_count.set(request_count);
+    let request_count = state.count_request();
     let mut ms = state.messages.lock().unwrap();
     ms.push(msg.message.clone());
 
@@ -93,8 +137,7 @@ fn post(msg: web::Json<PostInput>, state: web::Data<AppState>) -> Result<web::Js
 fn post_error(err: JsonPayloadError, req: &HttpRequest) -> Error {
     let extns = req.extensions();
     let state = extns.get::<web::Data<AppState>>().unwrap();
-    let request_count = state.request_count.get() + 1;
-    state.request_count.set(request_count);
+    let request_count = state.count_request();
     let post_error = PostError {
         server_id: state.server_id,
         request_count,
@@ -112,8 +155,7 @@ struct LookupResponse {
 
 #[get("/lookup/{index}")]
 fn lookup(state: web::Data<AppState>, idx: web::Path<usize>) -> Result<web::Json<LookupResponse>> {
-    let request_count = state.request_count.get() + 1;
-    state.request_count.set(request_count);
+    let request_count = state.count_request();
     let ms = state.messages.lock().unwrap();
     let result = ms.get(idx.into_inner()).cloned();
     Ok(web::Json(LookupResponse {
@@ -123,23 +165,127 @@ fn lookup(state: web::Data<AppState>, idx: web::Path<usize>) -> Result<web::Json
     }))
 }
 
+#[derive(Serialize)]
+struct StatusResponse {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct VersionResponse {
+    name: &'static str,
+    version: &'static str,
+    git_hash: &'static str,
+}
+
+#[get("/healthz")]
+fn healthz() -> Result<web::Json<StatusResponse>> {
+    Ok(web::Json(StatusResponse { status: "ok" }))
+}
+
+// Messages live in memory, so the server is ready as long as a handler has not panicked
+// while holding the lock
+#[get("/readyz")]
+fn readyz(state: web::Data<AppState>) -> HttpResponse {
+    if state.messages.is_poisoned() {
+        HttpResponse::ServiceUnavailable().json(StatusResponse { status: "message store poisoned" })
+    } else {
+        HttpResponse::Ok().json(StatusResponse { status: "ready" })
+    }
+}
+
+#[get("/version")]
+fn version() -> Result<web::Json<VersionResponse>> {
+    Ok(web::Json(VersionResponse {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+    }))
+}
+
+#[get("/metrics")]
+fn metrics(state: web::Data<AppState>) -> HttpResponse {
+    if let Ok(ms) = state.messages.lock() {
+        MESSAGES_STORED.set(ms.len() as i64);
+    }
+    server_common::metrics::render()
+}
+
 impl MessageApp {
     pub fn new(port: u16) -> Self {
-        MessageApp { port }
+        MessageApp {
+            host: "127.0.0.1".to_owned(),
+            port,
+            storage: None,
+            drain_timeout: shutdown::DEFAULT_DRAIN_TIMEOUT,
+            tls: None,
+            cors: Cors::default(),
+            security_headers: SecurityHeaders::default(),
+            rate_limit: RateLimit::default().route(Method::POST, "/send", Limit::per_minute(60)),
+        }
+    }
+
+    // JSON file the messages are loaded from on start and written to on shutdown
+    pub fn with_storage(mut self, path: PathBuf) -> Self {
+        self.storage = Some(path);
+        self
+    }
+
+    // How long requests in flight get to finish after SIGTERM or SIGINT
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    // Address to listen on, 127.0.0.1 by default
+    pub fn with_host(mut self, host: String) -> Self {
+        self.host = host;
+        self
+    }
+
+    // Serves HTTPS, and HTTP/2 to the clients asking for it, instead of plain HTTP
+    pub fn with_tls(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    // Origins allowed to call the API from a browser, none by default
+    pub fn with_cors(mut self, cors: Cors) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    pub fn with_security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.security_headers = security_headers;
+        self
+    }
+
+    // Replaces the default limit on POST /send
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
     }
 
     pub fn run(&self) -> std::io::Result<()> {
-        println!("Starting http server 127.0.0.1:{}", self.port);
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        println!("Starting {} server {}:{}", scheme, self.host, self.port);
 
-        let messages = Arc::new(Mutex::new(vec![]));
-        HttpServer::new(move || {
+        let messages = Arc::new(Mutex::new(self.load()?));
+        let app_messages = messages.clone();
+        let cors = self.cors.clone();
+        let security_headers = self.security_headers.clone();
+        let rate_limit = self.rate_limit.clone();
+        let factory = move || {
             App::new()
                 .data(AppState {
                     server_id: SERVER_COUNTER.fetch_add(1, Ordering::SeqCst),
                     request_count: Cell::new(0),
-                    messages: messages.clone(),
+                    messages: app_messages.clone(),
                 })
-                .wrap(middleware::Logger::new(LOG_FORMAT))
+                .wrap(rate_limit.clone())
+                .wrap(cors.clone())
+                .wrap(security_headers.clone())
+                .wrap(RequestLog::default())
+                .wrap(Metrics)
                 .service(index)
                 .service(web::resource("/send")
                     .data(web::JsonConfig::default().limit(4096).error_handler(post_error))
@@ -147,9 +293,99 @@ impl MessageApp {
                 )
                 .service(clear)
                 .service(lookup)
+                .service(healthz)
+                .service(readyz)
+                .service(version)
+                .service(metrics)
+        };
+
+        shutdown::run("messages-actix", || {
+            let addr = (self.host.as_str(), self.port);
+            let server = HttpServer::new(factory);
+            let server = match &self.tls {
+                Some(config) => server.bind_rustls(addr, config.server_config()?)?,
+                None => server.bind(addr)?,
+            };
+            Ok(server
+                .workers(8)
+                .shutdown_timeout(self.drain_timeout.as_secs())
+                .disable_signals()
+                .system_exit()
+                .start())
+        })?;
+
+        // a handler that panicked poisoned the lock, what it left behind is still worth keeping
+        let messages = messages.lock().unwrap_or_else(|e| e.into_inner());
+        self.save(&messages)
+    }
+
+    fn load(&self) -> io::Result<Vec<String>> {
+        match &self.storage {
+            Some(path) if path.exists() => {
+                let messages = serde_json::from_slice(&fs::read(path)?)?;
+                Ok(messages)
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    // Written next to the file first, so a crash while saving leaves the previous copy in place
+    fn save(&self, messages: &[String]) -> io::Result<()> {
+        let path = match &self.storage {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec(messages)?)?;
+        fs::rename(&partial, path)?;
+        log::info!("Saved {} messages to {}", messages.len(), path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use std::thread;
+
+    fn readyz_status(messages: Arc<Mutex<Vec<String>>>) -> (StatusCode, serde_json::Value) {
+        let mut app = test::init_service(
+            App::new()
+                .data(AppState {
+                    server_id: 0,
+                    request_count: Cell::new(0),
+                    messages,
+                })
+                .service(readyz),
+        );
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let res = test::call_service(&mut app, req);
+        let status = res.status();
+        (status, serde_json::from_slice(&test::read_body(res)).unwrap())
+    }
+
+    #[test]
+    fn readyz_when_messages_are_available() {
+        let (status, body) = readyz_status(Arc::new(Mutex::new(vec![])));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+    }
+
+    #[test]
+    fn readyz_reports_poisoned_store() {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let holder = messages.clone();
+        thread::spawn(move || {
+            let _ms = holder.lock().unwrap();
+            panic!("handler panicked while holding the messages");
         })
-            .bind(("127.0.0.1", self.port))?
-            .workers(8)
-            .run()
+        .join()
+        .unwrap_err();
+
+        let (status, body) = readyz_status(messages);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "message store poisoned");
     }
-}
\ No newline at end of file
+}
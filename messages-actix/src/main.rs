@@ -1,8 +1,34 @@
 use messages_actix::MessageApp;
+use server_common::cors::Cors;
+use server_common::rate_limit::RateLimit;
+use server_common::security::SecurityHeaders;
+use server_common::certs::TlsConfig;
+use std::env;
+use std::time::Duration;
 
 fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
-    env_logger::init();
-    let app = MessageApp::new(8082);
+    env::set_var("RUST_LOG", "access=info,actix_web=info,server_common=info");
+    server_common::logging::init();
+    let _tracing = server_common::trace::init("messages-actix")?;
+    let port = env::var("PORT").ok().and_then(|port| port.parse().ok()).unwrap_or(8082);
+    let mut app = MessageApp::new(port);
+    if let Ok(host) = env::var("HOST") {
+        app = app.with_host(host);
+    }
+    if let Some(tls) = TlsConfig::from_env() {
+        app = app.with_tls(tls);
+    }
+    app = app
+        .with_cors(Cors::from_env())
+        .with_security_headers(SecurityHeaders::from_env());
+    if let Ok(rules) = env::var("RATE_LIMITS") {
+        app = app.with_rate_limit(RateLimit::default().routes(&rules)?);
+    }
+    if let Ok(path) = env::var("MESSAGES_FILE") {
+        app = app.with_storage(path.into());
+    }
+    if let Some(secs) = env::var("DRAIN_TIMEOUT_SECS").ok().and_then(|secs| secs.parse().ok()) {
+        app = app.with_drain_timeout(Duration::from_secs(secs));
+    }
     app.run()
 }
\ No newline at end of file
@@ -0,0 +1,87 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .unwrap()
+}
+
+// Starts the server binary storing its messages in `storage`, once it accepts connections
+fn start(port: u16, storage: &Path) -> Child {
+    let child = Command::new(env!("CARGO_BIN_EXE_messages-actix"))
+        .env("PORT", port.to_string())
+        .env("MESSAGES_FILE", storage)
+        .env("DRAIN_TIMEOUT_SECS", "5")
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "the server did not start");
+        thread::sleep(Duration::from_millis(10));
+    }
+    // the signal handlers are installed once the System runs, right after the listener
+    thread::sleep(Duration::from_millis(200));
+    child
+}
+
+// Sends SIGTERM and waits for the server to exit on its own
+fn terminate(mut child: Child) {
+    let killed = Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    assert!(killed.success());
+    let status = child.wait().unwrap();
+    assert!(status.success(), "the server exited with {}", status);
+}
+
+fn request(port: u16, method: &str, path: &str, body: Option<Value>) -> Value {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+#[test]
+#[cfg(unix)]
+fn messages_saved_on_sigterm_and_reloaded() {
+    let dir = TempDir::new().unwrap();
+    let storage = dir.path().join("messages.json");
+
+    let port = free_port();
+    let server = start(port, &storage);
+    for message in &["hello", "goodbye"] {
+        request(port, "POST", "/send", Some(serde_json::json!({ "message": message })));
+    }
+    terminate(server);
+
+    let saved: Vec<String> = serde_json::from_slice(&std::fs::read(&storage).unwrap()).unwrap();
+    assert_eq!(saved, vec!["hello", "goodbye"]);
+    assert!(!storage.with_extension("partial").exists());
+
+    let port = free_port();
+    let server = start(port, &storage);
+    assert_eq!(request(port, "GET", "/", None)["message"], serde_json::json!(["hello", "goodbye"]));
+    assert_eq!(request(port, "GET", "/lookup/1", None)["result"], "goodbye");
+    terminate(server);
+}
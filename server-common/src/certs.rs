@@ -0,0 +1,138 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use std::{env, thread};
+
+use log::{info, warn};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{NoClientAuth, ResolvesServerCert, ServerConfig, SignatureScheme};
+
+// How often the certificate files are checked for changes
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+// Where to find the certificate chain and private key, both PEM encoded
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub reload_interval: Duration,
+}
+
+impl TlsConfig {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        TlsConfig {
+            cert: cert.into(),
+            key: key.into(),
+            reload_interval: DEFAULT_RELOAD_INTERVAL,
+        }
+    }
+
+    pub fn with_reload_interval(mut self, reload_interval: Duration) -> Self {
+        self.reload_interval = reload_interval;
+        self
+    }
+
+    // TLS is only enabled when both TLS_CERT and TLS_KEY are set
+    pub fn from_env() -> Option<Self> {
+        let config = TlsConfig::new(env::var_os("TLS_CERT")?, env::var_os("TLS_KEY")?);
+        match env::var("TLS_RELOAD_INTERVAL_SECS").ok().and_then(|secs| secs.parse().ok()) {
+            Some(secs) => Some(config.with_reload_interval(Duration::from_secs(secs))),
+            None => Some(config),
+        }
+    }
+
+    // The config to pass to HttpServer::bind_rustls, which adds h2 and http/1.1 to the ALPN
+    // protocols. Its certificate is replaced when the files change, without restarting.
+    pub fn server_config(&self) -> io::Result<ServerConfig> {
+        let cert = Arc::new(ReloadingCert {
+            key: RwLock::new(load(self)?),
+        });
+        watch(Arc::downgrade(&cert), self.clone());
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = cert;
+        Ok(config)
+    }
+}
+
+// Hands the certificate currently loaded to every handshake
+struct ReloadingCert {
+    key: RwLock<CertifiedKey>,
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(
+        &self,
+        _server_name: Option<webpki::DNSNameRef>,
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<CertifiedKey> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+fn load(config: &TlsConfig) -> io::Result<CertifiedKey> {
+    let invalid = |what: &str, path: &Path| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no valid {} in {}", what, path.display()),
+        )
+    };
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(&config.cert)?))
+        .ok()
+        .filter(|certs| !certs.is_empty())
+        .ok_or_else(|| invalid("certificate", &config.cert))?;
+
+    let pem = fs::read(&config.key)?;
+    let key = pemfile::pkcs8_private_keys(&mut pem.as_slice())
+        .ok()
+        .filter(|keys| !keys.is_empty())
+        .or_else(|| pemfile::rsa_private_keys(&mut pem.as_slice()).ok())
+        .and_then(|keys| keys.into_iter().next())
+        .and_then(|key| sign::any_supported_type(&key).ok())
+        .ok_or_else(|| invalid("private key", &config.key))?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+fn modified(config: &TlsConfig) -> Option<SystemTime> {
+    let cert = fs::metadata(&config.cert).and_then(|m| m.modified()).ok()?;
+    let key = fs::metadata(&config.key).and_then(|m| m.modified()).ok()?;
+    Some(cert.max(key))
+}
+
+// Polls the modification times of the files until the server config is dropped. A change is only
+// picked up once the files stayed the same for an interval, so a certificate written before its
+// key isn't paired with the old key. A pair that fails to load keeps the previous one in use.
+fn watch(cert: Weak<ReloadingCert>, config: TlsConfig) {
+    let mut loaded = modified(&config);
+    let mut seen = loaded;
+    thread::spawn(move || loop {
+        thread::sleep(config.reload_interval);
+        let cert = match cert.upgrade() {
+            Some(cert) => cert,
+            None => return,
+        };
+
+        let current = modified(&config);
+        if current != seen {
+            seen = current;
+            continue;
+        }
+        if current == loaded {
+            continue;
+        }
+
+        loaded = current;
+        match load(&config) {
+            Ok(key) => {
+                *cert.key.write().unwrap() = key;
+                info!("Reloaded TLS certificate {}", config.cert.display());
+            }
+            Err(e) => warn!("Keeping the current TLS certificate, failed to reload: {}", e),
+        }
+    });
+}
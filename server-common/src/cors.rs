@@ -0,0 +1,356 @@
+use std::env;
+use std::rc::Rc;
+use std::time::Duration;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, Either, FutureResult};
+use futures::{Future, Poll};
+
+use crate::logging::REQUEST_ID;
+
+// Which origins may call the API from a browser
+#[derive(Clone, Debug)]
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+// Middleware answering CORS preflight requests and adding the Access-Control headers to the
+// responses sent to allowed origins. No origin is allowed by default, so the browser keeps
+// blocking cross origin calls until some are configured.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    // response headers the browser lets the calling page read
+    exposed_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: Duration,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: Origins::List(vec![]),
+            methods: vec![Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE],
+            headers: vec![
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                HeaderName::from_static(REQUEST_ID),
+            ],
+            exposed_headers: vec![HeaderName::from_static(REQUEST_ID)],
+            credentials: false,
+            max_age: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl Cors {
+    // Origins look like https://example.com:8080, * allows any of them
+    pub fn allowed_origin(mut self, origin: &str) -> Self {
+        match (&mut self.origins, origin) {
+            (_, "*") => self.origins = Origins::Any,
+            (Origins::List(origins), _) => origins.push(origin.trim_end_matches('/').to_owned()),
+            (Origins::Any, _) => {}
+        }
+        self
+    }
+
+    pub fn allowed_methods(mut self, methods: Vec<Method>) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    pub fn allowed_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    // X-Request-Id is always exposed
+    pub fn exposed_header(mut self, header: HeaderName) -> Self {
+        if !self.exposed_headers.contains(&header) {
+            self.exposed_headers.push(header);
+        }
+        self
+    }
+
+    // Lets the browser send cookies and Authorization headers along, to the listed origins only:
+    // with * the responses never allow credentials
+    pub fn allow_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    // How long browsers may cache the answer to a preflight request
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    // Configured from CORS_ALLOWED_ORIGINS and CORS_ALLOWED_METHODS, both comma separated, and
+    // CORS_ALLOW_CREDENTIALS=true
+    pub fn from_env() -> Self {
+        let mut cors = Cors::default();
+        if let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") {
+            for origin in split(&origins) {
+                cors = cors.allowed_origin(origin);
+            }
+        }
+        if let Ok(methods) = env::var("CORS_ALLOWED_METHODS") {
+            let methods = split(&methods).filter_map(|m| m.to_uppercase().parse().ok()).collect();
+            cors = cors.allowed_methods(methods);
+        }
+        if env::var("CORS_ALLOW_CREDENTIALS").map(|v| v == "true").unwrap_or(false) {
+            if let Origins::Any = cors.origins {
+                log::warn!("CORS_ALLOW_CREDENTIALS is ignored when CORS_ALLOWED_ORIGINS is *");
+            }
+            cors = cors.allow_credentials();
+        }
+        cors
+    }
+
+    fn enabled(&self) -> bool {
+        match &self.origins {
+            Origins::Any => true,
+            Origins::List(origins) => !origins.is_empty(),
+        }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match &self.origins {
+            Origins::Any => true,
+            Origins::List(origins) => origins.iter().any(|o| o.eq_ignore_ascii_case(origin)),
+        }
+    }
+
+    fn preflight(&self, req: &ServiceRequest, origin: &str) -> HttpResponse {
+        let method = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|method| method.parse::<Method>().ok());
+        let method_allowed = method.is_some_and(|method| self.methods.contains(&method));
+
+        let requested = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let headers_allowed = split(requested).all(|name| {
+            self.headers.iter().any(|allowed| allowed.as_str().eq_ignore_ascii_case(name))
+        });
+
+        if !self.allows(origin) || !method_allowed || !headers_allowed {
+            return HttpResponse::Forbidden().finish();
+        }
+
+        let mut res = HttpResponse::NoContent().finish();
+        let headers = res.headers_mut();
+        self.add_headers(headers, origin);
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods));
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, join(&self.headers));
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, self.max_age.as_secs().into());
+        res
+    }
+
+    fn add_headers(&self, headers: &mut HeaderMap, origin: &str) {
+        // credentials are only allowed for the listed origins, echoing any origin along with them
+        // would let every site make calls with the user's cookies
+        let (allow_origin, credentials) = match self.origins {
+            Origins::Any => (HeaderValue::from_static("*"), false),
+            Origins::List(_) => match HeaderValue::from_str(origin) {
+                Ok(origin) => (origin, self.credentials),
+                Err(_) => return,
+            },
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if credentials {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+        headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, join(&self.exposed_headers));
+    }
+}
+
+fn split(list: &str) -> impl Iterator<Item=&str> {
+    list.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+    let list = items.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(", ");
+    HeaderValue::from_str(&list).unwrap()
+}
+
+impl<S, B> Transform<S> for Cors
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware {
+            service,
+            cors: Rc::new(self.clone()),
+        })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    cors: Rc<Cors>,
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<FutureResult<Self::Response, Error>, Box<dyn Future<Item=Self::Response, Error=Error>>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if !self.cors.enabled() {
+            return Either::B(Box::new(self.service.call(req)));
+        }
+
+        let origin = req
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        if let Some(origin) = &origin {
+            let preflight = req.method() == Method::OPTIONS
+                && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+            if preflight {
+                let mut res = self.cors.preflight(&req, origin);
+                res.headers_mut().append(header::VARY, HeaderValue::from_static("Origin"));
+                return Either::A(ok(req.into_response(res.into_body())));
+            }
+        }
+
+        let cors = self.cors.clone();
+        Either::B(Box::new(self.service.call(req).map(move |mut res| {
+            let headers = res.headers_mut();
+            // the response depends on the Origin header, caches must not mix them up
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            if let Some(origin) = origin.filter(|origin| cors.allows(origin)) {
+                cors.add_headers(headers, &origin);
+            }
+            res
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cors;
+    use actix_web::http::{header, Method, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    const ORIGIN: &str = "https://front.example";
+
+    fn call(cors: Cors, req: TestRequest) -> actix_web::dev::ServiceResponse {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cors)
+                .service(web::resource("/posts").to(HttpResponse::Ok)),
+        );
+        test::call_service(&mut app, req.to_request())
+    }
+
+    fn preflight(method: &str, headers: &str) -> TestRequest {
+        TestRequest::with_uri("/posts")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, ORIGIN)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+    }
+
+    #[test]
+    fn no_headers_without_allowed_origins() {
+        let res = call(Cors::default(), TestRequest::get().uri("/posts").header(header::ORIGIN, ORIGIN));
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[test]
+    fn allowed_origins_echoed_back() {
+        let cors = Cors::default().allowed_origin("https://front.example/");
+        let res = call(cors.clone(), TestRequest::get().uri("/posts").header(header::ORIGIN, ORIGIN));
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ORIGIN);
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Origin");
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        let req = TestRequest::get().uri("/posts").header(header::ORIGIN, "https://evil.example");
+        let res = call(cors, req);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[test]
+    fn exposed_headers_listed() {
+        let cors = Cors::default()
+            .allowed_origin(ORIGIN)
+            .exposed_header(header::ETAG)
+            .exposed_header(header::ETAG);
+        let res = call(cors, TestRequest::get().uri("/posts").header(header::ORIGIN, ORIGIN));
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap(), "x-request-id, etag");
+    }
+
+    #[test]
+    fn preflights_answered_for_allowed_methods_and_headers() {
+        let cors = Cors::default().allowed_origin(ORIGIN);
+        let res = call(cors.clone(), preflight("POST", "Content-Type, X-Request-Id"));
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ORIGIN);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+
+        assert_eq!(call(cors.clone(), preflight("TRACE", "")).status(), StatusCode::FORBIDDEN);
+        assert_eq!(call(cors, preflight("POST", "X-Secret")).status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn any_origin_answered_with_a_wildcard() {
+        let req = TestRequest::get().uri("/posts").header(header::ORIGIN, ORIGIN);
+        let res = call(Cors::default().allowed_origin("*"), req);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+    }
+
+    #[test]
+    fn credentials_never_allowed_for_any_origin() {
+        let cors = Cors::default().allowed_origin("*").allow_credentials();
+        let req = TestRequest::get().uri("/posts").header(header::ORIGIN, ORIGIN);
+        let res = call(cors.clone(), req);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        let res = call(cors, preflight("POST", "Authorization"));
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        // listed origins still get them
+        let cors = Cors::default().allowed_origin(ORIGIN).allow_credentials();
+        let res = call(cors, TestRequest::get().uri("/posts").header(header::ORIGIN, ORIGIN));
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ORIGIN);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+    }
+}
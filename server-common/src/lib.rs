@@ -0,0 +1,21 @@
+// Pieces shared by the actix servers in this repository
+
+// certificate files for HttpServer::bind_rustls, reloaded when they change
+pub mod certs;
+// CORS preflights and Access-Control headers
+pub mod cors;
+// JSON access log and request ids
+pub mod logging;
+// Prometheus metrics for the requests handled by a server
+pub mod metrics;
+// per client token buckets for the routes that write
+pub mod rate_limit;
+// CSP, HSTS and the other security headers
+pub mod security;
+// graceful shutdown on SIGTERM and SIGINT
+pub mod shutdown;
+// spans exported over OTLP or written to stdout or a file
+pub mod trace;
+
+// re-exported so the servers register their own metrics against the same registry
+pub use prometheus;
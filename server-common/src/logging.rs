@@ -0,0 +1,306 @@
+use std::io::Write;
+use std::time::Instant;
+
+use actix_web::body::{Body, ResponseBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage, HttpRequest};
+use futures::future::{ok, FutureResult};
+use futures::{Future, Poll};
+use log::info;
+use serde_json::{json, Map, Value};
+use tracing::field::Empty;
+use tracing::info_span;
+use tracing_futures::Instrument;
+use uuid::Uuid;
+
+pub const REQUEST_ID: &str = "x-request-id";
+
+// Log target of the lines written by RequestLog, their message is already a JSON object
+const ACCESS: &str = "access";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Id of the request being handled, taken from the X-Request-Id header or generated
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+// Sets up env_logger to write every record as one JSON object per line
+pub fn init() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let mut line = match record.target() {
+                ACCESS => serde_json::from_str(&record.args().to_string()).unwrap_or_default(),
+                _ => Map::new(),
+            };
+            if line.is_empty() {
+                line.insert("msg".to_owned(), record.args().to_string().into());
+            }
+            line.insert("ts".to_owned(), buf.timestamp().to_string().into());
+            line.insert("level".to_owned(), record.level().to_string().into());
+            line.insert("target".to_owned(), record.target().into());
+
+            writeln!(buf, "{}", Value::Object(line))
+        })
+        .init();
+}
+
+// Middleware giving every request an id and logging it once the response is ready. The id is
+// returned in the X-Request-Id header and added to JSON error bodies, so a failing request
+// can be found in the logs. The request is also handled inside a span carrying the id, the
+// parent of the spans opened by the handlers.
+#[derive(Clone, Copy, Default)]
+pub struct RequestLog {
+    user_id: Option<fn(&HttpRequest) -> Option<String>>,
+}
+
+impl RequestLog {
+    // Tells the middleware which user a request acts for, it is called after routing so the
+    // function can look at the match_info
+    pub fn user_id(mut self, f: fn(&HttpRequest) -> Option<String>) -> Self {
+        self.user_id = Some(f);
+        self
+    }
+}
+
+impl<S> Transform<S> for RequestLog
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<Body>, Error=Error>,
+        S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLogMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestLogMiddleware {
+            service,
+            config: *self,
+        })
+    }
+}
+
+pub struct RequestLogMiddleware<S> {
+    service: S,
+    config: RequestLog,
+}
+
+impl<S> Service for RequestLogMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<Body>, Error=Error>,
+        S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| valid_request_id(id))
+            .map(str::to_owned)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let method = req.method().to_string();
+        let path = req.path().to_owned();
+        let user_id = self.config.user_id;
+
+        // route and status are only known once the request has been handled
+        let span = info_span!(
+            "request",
+            otel.name = %method,
+            http.method = %method,
+            http.target = %path,
+            http.route = Empty,
+            http.status_code = Empty,
+            request_id = %request_id,
+        );
+        let handled = span.in_scope(|| self.service.call(req));
+
+        Box::new(handled.instrument(span.clone()).then(move |result| {
+            let res = match result {
+                Ok(res) => res,
+                // a middleware further in failed, actix turns the error into the response
+                Err(e) => {
+                    let status = e.as_response_error().error_response().status().as_u16();
+                    span.record("http.status_code", status);
+                    log_access(&request_id, &method, &path, status, start, None);
+                    return Err(e);
+                }
+            };
+
+            let route = crate::metrics::route(res.request());
+            span.record("otel.name", format!("{} {}", method, route).as_str());
+            span.record("http.route", route.as_str());
+            span.record("http.status_code", res.status().as_u16());
+
+            let user_id = user_id.and_then(|f| f(res.request()));
+            let mut res = with_request_id(res, &request_id);
+            log_access(&request_id, &method, &path, res.status().as_u16(), start, user_id);
+
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(HeaderName::from_static(REQUEST_ID), value);
+            }
+            Ok(res)
+        }))
+    }
+}
+
+fn log_access(request_id: &str, method: &str, path: &str, status: u16, start: Instant, user_id: Option<String>) {
+    info!(target: ACCESS, "{}", json!({
+        "request_id": request_id,
+        "method": method,
+        "path": path,
+        "status": status,
+        "latency_ms": start.elapsed().as_secs_f64() * 1000.0,
+        "user_id": user_id,
+    }));
+}
+
+// Ids coming from clients end up in the logs and headers, so only short tokens are kept
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == ':')
+}
+
+// Adds a request_id field to the JSON object returned for an error
+fn with_request_id(res: ServiceResponse<Body>, request_id: &str) -> ServiceResponse<Body> {
+    if res.response().error().is_none() {
+        return res;
+    }
+
+    res.map_body(|_, body| {
+        let bytes = match &body {
+            ResponseBody::Body(Body::Bytes(bytes)) | ResponseBody::Other(Body::Bytes(bytes)) => bytes,
+            _ => return body,
+        };
+        match serde_json::from_slice::<Map<String, Value>>(bytes) {
+            Ok(mut object) => {
+                object.insert("request_id".to_owned(), request_id.into());
+                ResponseBody::Body(Body::from(Value::Object(object).to_string()))
+            }
+            Err(_) => body,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{valid_request_id, RequestLog, ACCESS, MAX_REQUEST_ID_LEN, REQUEST_ID};
+    use actix_web::dev::Service;
+    use actix_web::error::ErrorForbidden;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use log::{LevelFilter, Log, Metadata, Record};
+    use serde_json::Value;
+    use std::sync::{Mutex, Once};
+
+    // Keeps the access log lines written by the tests of this module
+    struct AccessLines(Mutex<Vec<Value>>);
+
+    impl Log for AccessLines {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == ACCESS
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                let line = serde_json::from_str(&record.args().to_string()).unwrap();
+                self.0.lock().unwrap().push(line);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static ACCESS_LINES: AccessLines = AccessLines(Mutex::new(Vec::new()));
+    static CAPTURE: Once = Once::new();
+
+    fn access_line(request_id: &str) -> Option<Value> {
+        let lines = ACCESS_LINES.0.lock().unwrap();
+        lines.iter().find(|line| line["request_id"] == request_id).cloned()
+    }
+
+    fn request_id(req: TestRequest) -> String {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestLog::default())
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        );
+        let res = test::call_service(&mut app, req.to_request());
+        res.headers().get(REQUEST_ID).unwrap().to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn request_ids_validated() {
+        assert!(valid_request_id("4bf92f35-77b3_4da6.a3ce:929d"));
+        assert!(valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN)));
+        assert!(!valid_request_id(""));
+        assert!(!valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+        assert!(!valid_request_id("two words"));
+        assert!(!valid_request_id("forged\nlog line"));
+        assert!(!valid_request_id("\"quoted\""));
+    }
+
+    #[test]
+    fn valid_request_ids_propagated() {
+        let id = request_id(TestRequest::with_uri("/").header(REQUEST_ID, "abc-123"));
+        assert_eq!(id, "abc-123");
+    }
+
+    #[test]
+    fn invalid_request_ids_replaced() {
+        let id = request_id(TestRequest::with_uri("/").header(REQUEST_ID, "<script>"));
+        assert_ne!(id, "<script>");
+        assert_eq!(id.len(), 36);
+        // and missing ones generated
+        assert_eq!(request_id(TestRequest::with_uri("/")).len(), 36);
+    }
+
+    #[test]
+    fn responses_logged_when_a_middleware_fails() {
+        CAPTURE.call_once(|| {
+            log::set_logger(&ACCESS_LINES).unwrap();
+            log::set_max_level(LevelFilter::Info);
+        });
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap_fn(|req, _| {
+                    let denied = req.path() == "/denied";
+                    let res = req.into_response(HttpResponse::Ok().finish());
+                    if denied {
+                        Err(ErrorForbidden("denied"))
+                    } else {
+                        Ok(res)
+                    }
+                })
+                .wrap(RequestLog::default())
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        );
+
+        let req = TestRequest::with_uri("/denied").header(REQUEST_ID, "denied-1").to_request();
+        assert!(test::block_on(app.call(req)).is_err());
+        let line = access_line("denied-1").expect("the failed request is logged");
+        assert_eq!(line["status"], 403);
+        assert_eq!(line["path"], "/denied");
+
+        let req = TestRequest::with_uri("/").header(REQUEST_ID, "allowed-1").to_request();
+        assert!(test::call_service(&mut app, req).status().is_success());
+        assert_eq!(access_line("allowed-1").unwrap()["status"], 200);
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt::Debug;
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::BlockingError;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use futures::future::{ok, FutureResult};
+use futures::{Future, Poll};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use tracing::{info_span, Span};
+
+// Label used for requests that did not match any route, so scanners can't blow up the
+// number of series with random paths
+const UNMATCHED: &str = "unmatched";
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests handled, by route and status code",
+        &["method", "path", "status"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time spent handling HTTP requests, by route",
+        &["method", "path"]
+    )
+    .unwrap();
+    static ref BLOCKING_QUEUED: IntGauge = register_int_gauge!(
+        "blocking_tasks_queued",
+        "Closures passed to web::block waiting for a thread of the blocking pool"
+    )
+    .unwrap();
+    static ref BLOCKING_RUNNING: IntGauge = register_int_gauge!(
+        "blocking_tasks_running",
+        "Closures passed to web::block running on the blocking pool"
+    )
+    .unwrap();
+}
+
+// Middleware counting the requests and timing them, labelled by route pattern
+pub struct Metrics;
+
+impl<S, B> Transform<S> for Metrics
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware { service })
+    }
+}
+
+pub struct MetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for MetricsMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+
+        Box::new(self.service.call(req).then(move |res| {
+            // the route is only known once the request went through the router
+            let (path, status) = match &res {
+                Ok(res) => (route(res.request()), res.status().as_u16()),
+                Err(e) => (
+                    UNMATCHED.to_owned(),
+                    e.as_response_error().error_response().status().as_u16(),
+                ),
+            };
+
+            HTTP_REQUESTS
+                .with_label_values(&[&method, &path, &status.to_string()])
+                .inc();
+            HTTP_REQUEST_DURATION
+                .with_label_values(&[&method, &path])
+                .observe(start.elapsed().as_secs_f64());
+            res
+        }))
+    }
+}
+
+// Turns /users/1/posts back into /users/{id}/posts. The values in the match_info are slices
+// of the matched path, so their offsets tell exactly which segments to swap for the parameter
+// names, even when a value equals a literal segment of the route.
+pub(crate) fn route(req: &HttpRequest) -> String {
+    if !req.resource_map().has_resource(req.path()) {
+        return UNMATCHED.to_owned();
+    }
+
+    let info = req.match_info();
+    let path = info.get_ref().path();
+    let mut params: Vec<(usize, usize, &str)> = info
+        .iter()
+        .filter_map(|(name, value)| {
+            // defaults added to the match_info are not part of the path
+            let start = (value.as_ptr() as usize).checked_sub(path.as_ptr() as usize)?;
+            let end = start + value.len();
+            (end <= path.len()).then_some((start, end, name))
+        })
+        .collect();
+    params.sort_unstable();
+
+    let mut route = String::with_capacity(path.len());
+    let mut copied = 0;
+    for (start, end, name) in params {
+        if start < copied {
+            continue;
+        }
+        route.push_str(&path[copied..start]);
+        route.push('{');
+        route.push_str(name);
+        route.push('}');
+        copied = end;
+    }
+    route.push_str(&path[copied..]);
+    route
+}
+
+// Same as web::block, but keeps track of how many closures are waiting for a thread, and runs
+// the closure in a span under the one of the request that submitted it
+pub fn block<F, I, E>(f: F) -> impl Future<Item=I, Error=BlockingError<E>>
+    where
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + Debug + 'static,
+{
+    let parent = Span::current();
+    let queued = Instant::now();
+    BLOCKING_QUEUED.inc();
+    web::block(move || {
+        BLOCKING_QUEUED.dec();
+        let span = info_span!(
+            parent: &parent,
+            "web::block",
+            queued_ms = queued.elapsed().as_secs_f64() * 1000.0
+        );
+        let _entered = span.enter();
+        let _running = Running::start();
+        f()
+    })
+}
+
+// Closures passed to block that have not returned yet
+pub(crate) fn blocking_tasks() -> i64 {
+    BLOCKING_QUEUED.get() + BLOCKING_RUNNING.get()
+}
+
+// Decrements the running gauge even when the closure panics
+struct Running;
+
+impl Running {
+    fn start() -> Self {
+        BLOCKING_RUNNING.inc();
+        Running
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        BLOCKING_RUNNING.dec();
+    }
+}
+
+// Renders every registered metric in the Prometheus text format
+pub fn render() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metrics, HTTP_REQUESTS, UNMATCHED};
+    use actix_web::http::Method;
+    use actix_web::{test, web, App, HttpResponse};
+
+    // Every test sends its requests with its own method, so the counters it reads are its own
+    fn send(method: &str, uris: &[&str]) {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Metrics)
+                .service(web::resource("/users/find/{name}").to(HttpResponse::Ok))
+                .service(web::resource("/users/{id}/posts").to(HttpResponse::Ok))
+                .service(web::resource("/{from}/{to}").to(HttpResponse::Ok)),
+        );
+        for uri in uris {
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            let req = test::TestRequest::with_uri(uri).method(method).to_request();
+            test::call_service(&mut app, req);
+        }
+    }
+
+    fn count(method: &str, path: &str, status: &str) -> u64 {
+        HTTP_REQUESTS.with_label_values(&[method, path, status]).get()
+    }
+
+    #[test]
+    fn requests_labelled_by_route_pattern() {
+        send("LABEL", &["/users/1/posts", "/users/2/posts", "/users/find/frank"]);
+        assert_eq!(count("LABEL", "/users/{id}/posts", "200"), 2);
+        assert_eq!(count("LABEL", "/users/find/{name}", "200"), 1);
+    }
+
+    #[test]
+    fn values_equal_to_literal_segments_keep_the_pattern() {
+        send("LITERAL", &["/users/find/users", "/users/find/find", "/users/posts/posts", "/same/same"]);
+        assert_eq!(count("LITERAL", "/users/find/{name}", "200"), 2);
+        assert_eq!(count("LITERAL", "/users/{id}/posts", "200"), 1);
+        assert_eq!(count("LITERAL", "/{from}/{to}", "200"), 1);
+    }
+
+    #[test]
+    fn unmatched_requests_share_a_label() {
+        send("UNMATCHED", &["/a/b/c/d", "/e/f/g/h"]);
+        assert_eq!(count("UNMATCHED", UNMATCHED, "404"), 2);
+    }
+}
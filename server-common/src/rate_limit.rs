@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::http::{header, Method};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, Either, FutureResult};
+use futures::Poll;
+use serde_json::json;
+
+// How often buckets that filled up again are dropped, so clients seen once don't stay in memory
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Number of requests a client can make in a burst, the bucket refilling evenly over `period`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Limit {
+    pub fn per_minute(requests: u32) -> Self {
+        Limit {
+            requests,
+            period: Duration::from_secs(60),
+        }
+    }
+
+    // tokens added per second
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+// Written as requests/seconds, 10/60 being 10 requests a minute
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid limit {:?}, expected requests/seconds", s);
+        let (requests, secs) = s.split_once('/').ok_or_else(invalid)?;
+        let requests = requests.trim().parse().map_err(|_| invalid())?;
+        let secs: u64 = secs.trim().parse().map_err(|_| invalid())?;
+        if requests == 0 || secs == 0 {
+            return Err(invalid());
+        }
+        Ok(Limit {
+            requests,
+            period: Duration::from_secs(secs),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    method: Method,
+    // segments of the route pattern, {name} matching any value
+    pattern: Vec<String>,
+    limit: Limit,
+}
+
+impl Rule {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        let segments: Vec<&str> = path.split('/').collect();
+        self.method == method
+            && self.pattern.len() == segments.len()
+            && self
+            .pattern
+            .iter()
+            .zip(segments)
+            .all(|(pattern, segment)| pattern.starts_with('{') || pattern == segment)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(f64::from(limit.requests));
+        self.updated = now;
+    }
+
+    // Takes a token, or tells how long until the next one is available
+    fn take(&mut self, limit: &Limit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.rate()))
+        }
+    }
+}
+
+struct Buckets {
+    // keyed by the index of the rule and the client
+    buckets: HashMap<(usize, String), Bucket>,
+    purged: Instant,
+}
+
+// Token bucket rate limiter for the routes given limits, every client getting its own bucket per
+// route. Clients are told apart by the user returned by the `user` function when there is one,
+// by IP address otherwise, and requests with neither are let through. Clones share their
+// buckets, so the limits hold across workers. `user` runs on the event loop and must not block.
+#[derive(Clone)]
+pub struct RateLimit {
+    rules: Vec<Rule>,
+    user: Option<fn(&ServiceRequest) -> Option<String>>,
+    error: fn(Duration) -> Error,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            rules: vec![],
+            user: None,
+            error: too_many_requests,
+            buckets: Arc::new(Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                purged: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl RateLimit {
+    // Limits the requests to a route pattern such as /posts/{id}/comments
+    pub fn route(mut self, method: Method, path: &str, limit: Limit) -> Self {
+        self.rules.push(Rule {
+            method,
+            pattern: path.split('/').map(str::to_owned).collect(),
+            limit,
+        });
+        self
+    }
+
+    // Replaces the limits with the ones listed in `rules`, comma separated entries like
+    // `POST /users=10/60`
+    pub fn routes(mut self, rules: &str) -> io::Result<Self> {
+        self.rules.clear();
+        for rule in rules.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let parsed = rule.split_once('=').and_then(|(route, limit)| {
+                let (method, path) = route.trim().split_once(' ')?;
+                Some((method.parse::<Method>().ok()?, path.trim(), limit.parse::<Limit>()))
+            });
+            match parsed {
+                Some((method, path, Ok(limit))) => self = self.route(method, path, limit),
+                Some((_, _, Err(e))) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid rate limit {:?}, expected METHOD /path=requests/seconds", rule),
+                    ))
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    // Identifies the authenticated user a request acts for
+    pub fn user(mut self, f: fn(&ServiceRequest) -> Option<String>) -> Self {
+        self.user = Some(f);
+        self
+    }
+
+    // Builds the error returned once a client ran out of tokens, from the time until the next one
+    pub fn error(mut self, f: fn(Duration) -> Error) -> Self {
+        self.error = f;
+        self
+    }
+
+    fn check(&self, req: &ServiceRequest) -> Result<(), Duration> {
+        let (index, rule) = match self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(req.method(), req.path()))
+        {
+            Some(rule) => rule,
+            None => return Ok(()),
+        };
+
+        // X-Forwarded-For is left alone, any client could set it to get a fresh bucket. Requests
+        // with neither, like the ones over a Unix socket, aren't limited rather than all sharing
+        // one bucket.
+        let client = match self.user.and_then(|f| f(req)) {
+            Some(user) => format!("user:{}", user),
+            None => match req.peer_addr() {
+                Some(addr) => addr.ip().to_string(),
+                None => return Ok(()),
+            },
+        };
+
+        let now = Instant::now();
+        let mut state = self.buckets.lock().unwrap();
+        if now.duration_since(state.purged) >= PURGE_INTERVAL {
+            let rules = &self.rules;
+            state.buckets.retain(|(index, _), bucket| {
+                let limit = &rules[*index].limit;
+                bucket.refill(limit, now);
+                bucket.tokens < f64::from(limit.requests)
+            });
+            state.purged = now;
+        }
+
+        state
+            .buckets
+            .entry((index, client))
+            .or_insert_with(|| Bucket {
+                tokens: f64::from(rule.limit.requests),
+                updated: now,
+            })
+            .take(&rule.limit, now)
+    }
+}
+
+// Whole seconds for the Retry-After header, rounded up so the client doesn't come back too early
+pub fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil() as u64
+}
+
+fn too_many_requests(retry_after: Duration) -> Error {
+    let res = HttpResponse::TooManyRequests()
+        .header(header::RETRY_AFTER, retry_after_secs(retry_after))
+        .json(json!({ "err": "Too many requests" }));
+    InternalError::from_response("Too many requests", res).into()
+}
+
+impl<S, B> Transform<S> for RateLimit
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service,
+            limit: self.clone(),
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limit: RateLimit,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<FutureResult<Self::Response, Error>, S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        match self.limit.check(&req) {
+            Ok(()) => Either::B(self.service.call(req)),
+            Err(retry_after) => {
+                let error = (self.limit.error)(retry_after);
+                Either::A(ok(req.error_response(error)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limit, RateLimit};
+    use actix_service::Service;
+    use actix_web::dev::ServiceRequest;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::time::Duration;
+
+    // TestRequest has no peer address, it is set from the X-Peer header
+    fn call(limit: &RateLimit, method: Method, uri: &str, peer: &str) -> StatusCode {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(limit.clone())
+                .wrap_fn(|mut req: ServiceRequest, srv| {
+                    req.head_mut().peer_addr = req
+                        .headers()
+                        .get("x-peer")
+                        .and_then(|peer| peer.to_str().ok()?.parse().ok());
+                    srv.call(req)
+                })
+                .service(web::resource("/posts/{id}/comments").to(HttpResponse::Ok)),
+        );
+        let req = TestRequest::with_uri(uri).method(method).header("x-peer", peer);
+        test::call_service(&mut app, req.to_request()).status()
+    }
+
+    #[test]
+    fn limits_parsed() {
+        assert_eq!("10/60".parse(), Ok(Limit::per_minute(10)));
+        assert_eq!(
+            " 5 / 1 ".parse(),
+            Ok(Limit {
+                requests: 5,
+                period: Duration::from_secs(1)
+            })
+        );
+        for invalid in &["", "10", "0/60", "10/0", "ten/60"] {
+            assert!(invalid.parse::<Limit>().is_err(), "{}", invalid);
+        }
+
+        assert!(RateLimit::default().routes("POST /users=10/60, GET /posts=100/60").is_ok());
+        assert!(RateLimit::default().routes("/users=10/60").is_err());
+        assert!(RateLimit::default().routes("POST /users=10").is_err());
+    }
+
+    #[test]
+    fn clients_limited_per_route_pattern() {
+        let limit = RateLimit::default().route(Method::POST, "/posts/{id}/comments", Limit::per_minute(2));
+        let peer = "10.0.0.1:1234";
+
+        assert_eq!(call(&limit, Method::POST, "/posts/1/comments", peer), StatusCode::OK);
+        assert_eq!(call(&limit, Method::POST, "/posts/2/comments", peer), StatusCode::OK);
+        assert_eq!(call(&limit, Method::POST, "/posts/3/comments", peer), StatusCode::TOO_MANY_REQUESTS);
+
+        // the other clients and methods have their own buckets
+        assert_eq!(call(&limit, Method::POST, "/posts/1/comments", "10.0.0.2:1234"), StatusCode::OK);
+        assert_eq!(call(&limit, Method::GET, "/posts/1/comments", peer), StatusCode::OK);
+    }
+
+    #[test]
+    fn users_limited_wherever_they_come_from() {
+        let limit = RateLimit::default()
+            .route(Method::POST, "/posts/{id}/comments", Limit::per_minute(1))
+            .user(|req| req.headers().get("x-peer").map(|_| "frank".to_owned()));
+
+        assert_eq!(call(&limit, Method::POST, "/posts/1/comments", "10.0.0.1:1234"), StatusCode::OK);
+        let status = call(&limit, Method::POST, "/posts/1/comments", "10.0.0.2:1234");
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn requests_without_a_client_not_limited() {
+        let limit = RateLimit::default().route(Method::POST, "/posts/{id}/comments", Limit::per_minute(1));
+        for _ in 0..3 {
+            assert_eq!(call(&limit, Method::POST, "/posts/1/comments", "unknown"), StatusCode::OK);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::env;
+use std::rc::Rc;
+use std::time::Duration;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::{ok, FutureResult};
+use futures::{Future, Poll};
+
+// The servers only return JSON, which needs no resources and shouldn't be framed
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; frame-ancestors 'none'";
+
+// Middleware adding the security headers browsers look at to every response. A handler can set
+// its own value for any of them, the default is only added when the header is missing.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    content_security_policy: String,
+    hsts: Option<Duration>,
+    frame_options: String,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders {
+            content_security_policy: DEFAULT_CONTENT_SECURITY_POLICY.to_owned(),
+            hsts: None,
+            frame_options: "DENY".to_owned(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    pub fn content_security_policy(mut self, policy: impl Into<String>) -> Self {
+        self.content_security_policy = policy.into();
+        self
+    }
+
+    // Tells browsers to only use HTTPS for `max_age`, only worth sending when served over TLS
+    pub fn hsts(mut self, max_age: Duration) -> Self {
+        self.hsts = Some(max_age);
+        self
+    }
+
+    pub fn frame_options(mut self, frame_options: impl Into<String>) -> Self {
+        self.frame_options = frame_options.into();
+        self
+    }
+
+    // CONTENT_SECURITY_POLICY and FRAME_OPTIONS replace the defaults, HSTS_MAX_AGE_SECS enables HSTS
+    pub fn from_env() -> Self {
+        let mut headers = SecurityHeaders::default();
+        if let Ok(policy) = env::var("CONTENT_SECURITY_POLICY") {
+            headers = headers.content_security_policy(policy);
+        }
+        if let Ok(frame_options) = env::var("FRAME_OPTIONS") {
+            headers = headers.frame_options(frame_options);
+        }
+        if let Some(secs) = env::var("HSTS_MAX_AGE_SECS").ok().and_then(|secs| secs.parse().ok()) {
+            headers = headers.hsts(Duration::from_secs(secs));
+        }
+        headers
+    }
+
+    fn values(&self) -> Vec<(HeaderName, HeaderValue)> {
+        let mut values = vec![(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        )];
+        if let Ok(policy) = HeaderValue::from_str(&self.content_security_policy) {
+            values.push((header::CONTENT_SECURITY_POLICY, policy));
+        }
+        if let Ok(frame_options) = HeaderValue::from_str(&self.frame_options) {
+            values.push((header::X_FRAME_OPTIONS, frame_options));
+        }
+        if let Some(max_age) = self.hsts {
+            let hsts = format!("max-age={}; includeSubDomains", max_age.as_secs());
+            values.push((header::STRICT_TRANSPORT_SECURITY, HeaderValue::from_str(&hsts).unwrap()));
+        }
+        values
+    }
+}
+
+impl<S, B> Transform<S> for SecurityHeaders
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SecurityHeadersMiddleware {
+            service,
+            values: Rc::new(self.values()),
+        })
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: S,
+    values: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl<S, B> Service for SecurityHeadersMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let values = self.values.clone();
+        Box::new(self.service.call(req).map(move |mut res| {
+            let headers = res.headers_mut();
+            for (name, value) in values.iter() {
+                if !headers.contains_key(name) {
+                    headers.insert(name.clone(), value.clone());
+                }
+            }
+            res
+        }))
+    }
+}
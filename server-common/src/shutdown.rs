@@ -0,0 +1,165 @@
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::Server;
+use futures::{Future, Stream};
+use log::info;
+
+use crate::metrics;
+
+// Same as actix: how long workers get to finish the requests in flight once asked to stop
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Runs the server started by `start` until SIGTERM or SIGINT. actix stops immediately on SIGINT,
+// so `start` has to build the server with `disable_signals()`, and with `system_exit()` so the
+// System stops once the workers have drained. Returns when the server is down.
+pub fn run<F>(name: &str, start: F) -> io::Result<()>
+    where
+        F: FnOnce() -> io::Result<Server>,
+{
+    let sys = actix_rt::System::new(name);
+    let server = start()?;
+    stop_on_signal(server);
+    sys.run()
+}
+
+fn stop_on_signal(server: Server) {
+    let interrupt = tokio_signal::ctrl_c().flatten_stream();
+    let signals = interrupt.select(terminate());
+
+    actix_rt::spawn(signals.into_future().then(move |_| {
+        info!("Stopping, waiting for the requests in flight");
+        server.stop(true)
+    }));
+}
+
+#[cfg(unix)]
+fn terminate() -> impl Stream<Item=(), Error=io::Error> {
+    use tokio_signal::unix::{Signal, SIGTERM};
+
+    Signal::new(SIGTERM).flatten_stream().map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn terminate() -> impl Stream<Item=(), Error=io::Error> {
+    futures::stream::empty()
+}
+
+// Waits for the closures passed to metrics::block, so the transactions they opened are
+// committed before the process exits. Returns false if some are still running after `timeout`.
+pub fn wait_for_blocking_tasks(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while metrics::blocking_tasks() > 0 {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+// A background thread running `task` every `interval` until it is stopped, so the servers can
+// let the round in progress finish before they close what the task uses
+pub struct Periodic {
+    name: String,
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: JoinHandle<()>,
+}
+
+impl Periodic {
+    pub fn spawn<F>(name: &str, interval: Duration, mut task: F) -> Self
+        where
+            F: FnMut() + Send + 'static,
+    {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stopped.clone();
+        let thread = thread::spawn(move || {
+            let (lock, wakeup) = &*signal;
+            let mut stop = lock.lock().unwrap();
+            loop {
+                stop = wakeup.wait_timeout_while(stop, interval, |stop| !*stop).unwrap().0;
+                if *stop {
+                    return;
+                }
+                drop(stop);
+                task();
+                stop = lock.lock().unwrap();
+            }
+        });
+
+        Periodic {
+            name: name.to_owned(),
+            stopped,
+            thread,
+        }
+    }
+
+    // Wakes the thread up and waits for the task to return if it is running
+    pub fn stop(self) {
+        let (lock, wakeup) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        wakeup.notify_one();
+        if self.thread.join().is_err() {
+            log::error!("{} panicked", self.name);
+        } else {
+            info!("{} stopped", self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Periodic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn periodic_tasks_run_until_stopped() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let periodic = Periodic::spawn("counter", Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while runs.load(Ordering::SeqCst) < 3 {
+            assert!(Instant::now() < deadline, "the task never ran");
+            thread::sleep(Duration::from_millis(5));
+        }
+        periodic.stop();
+
+        let stopped_at = runs.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runs.load(Ordering::SeqCst), stopped_at);
+    }
+
+    #[test]
+    fn stopping_does_not_wait_for_the_interval() {
+        let periodic = Periodic::spawn("idle", Duration::from_secs(3600), || {});
+        let start = Instant::now();
+        periodic.stop();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stopping_waits_for_the_running_task() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let started = Arc::new(AtomicUsize::new(0));
+        let (done, running) = (finished.clone(), started.clone());
+        let periodic = Periodic::spawn("slow", Duration::from_millis(1), move || {
+            running.store(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            done.fetch_add(1, Ordering::SeqCst);
+        });
+
+        while started.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        periodic.stop();
+        assert!(finished.load(Ordering::SeqCst) >= 1);
+    }
+}
@@ -0,0 +1,87 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io;
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::EnvFilter;
+
+// Flushes the spans still waiting in the OTLP batch when the server stops
+pub struct Tracing {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                log::warn!("Failed to flush traces: {}", e);
+            }
+        }
+    }
+}
+
+// Installs the subscriber exporting spans, configured from the environment:
+// - OTEL_EXPORTER_OTLP_ENDPOINT (or OTEL_EXPORTER_OTLP_TRACES_ENDPOINT) sends them over OTLP/HTTP
+// - TRACE_OUTPUT=stdout, or a file path, writes a line with the timings of every closed span
+// - TRACE_FILTER picks the spans to keep, `info` by default
+// Without an exporter no subscriber is installed and the spans cost next to nothing.
+pub fn init(service_name: &'static str) -> io::Result<Tracing> {
+    let otlp = env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .or_else(|| env::var_os("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"))
+        .is_some();
+    let output = env::var("TRACE_OUTPUT").ok();
+    if !otlp && output.is_none() {
+        return Ok(Tracing { provider: None });
+    }
+
+    let provider = if otlp {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .build()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Some(
+            SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(Resource::builder().with_service_name(service_name).build())
+                .build(),
+        )
+    } else {
+        None
+    };
+    let otlp_layer = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name)));
+
+    let writer = match output.as_deref() {
+        None => None,
+        Some("stdout") => Some(BoxMakeWriter::new(io::stdout)),
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Some(BoxMakeWriter::new(std::sync::Mutex::new(file)))
+        }
+    };
+    let fmt_layer = writer.map(|writer| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false)
+            .with_span_events(FmtSpan::CLOSE)
+    });
+
+    let filter = EnvFilter::try_from_env("TRACE_FILTER").unwrap_or_else(|_| EnvFilter::new("info"));
+    // set_global_default rather than init, which would also take over the `log` records
+    // already written by env_logger
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(otlp_layer)
+        .with(fmt_layer);
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    Ok(Tracing { provider })
+}